@@ -0,0 +1,352 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::init::{Board, FenError, Move, BB_ALL, STARTING_FEN};
+use crate::pgn::{self, Game, Games, Node, NodeId};
+
+const MAGIC: &[u8; 8] = b"RCGAME01";
+/// Tokens of the move stream. Anything below `NULL_MOVE` is the index of a move
+/// among `generate_legal_moves`, of which there are at most 218.
+const NULL_MOVE: u8 = 0xfc;
+const ANNOTATION: u8 = 0xfd;
+const BEGIN_VARIATION: u8 = 0xfe;
+const END_VARIATION: u8 = 0xff;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("error while reading the archive: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a game archive")]
+    BadMagic,
+    #[error("truncated game record")]
+    Truncated,
+    #[error("invalid game record: {0}")]
+    Invalid(&'static str),
+    #[error("no legal move {index} in {fen}")]
+    IllegalMove { index: u8, fen: String },
+    #[error("cannot encode illegal move {uci} in {fen}")]
+    UnencodableMove { uci: String, fen: String },
+    #[error("invalid FEN header: {0}")]
+    Fen(#[from] FenError)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Reads the fields of a game record.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    at: usize
+}
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, ArchiveError> {
+        let byte = *self.bytes.get(self.at).ok_or(ArchiveError::Truncated)?;
+        self.at += 1;
+        Ok(byte)
+    }
+    fn varint(&mut self) -> Result<u64, ArchiveError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(ArchiveError::Invalid("varint too long"))
+    }
+    fn str(&mut self) -> Result<&'a str, ArchiveError> {
+        let len = self.varint()? as usize;
+        let bytes = self.bytes.get(self.at..self.at.saturating_add(len)).ok_or(ArchiveError::Truncated)?;
+        self.at += len;
+        std::str::from_utf8(bytes).map_err(|_| ArchiveError::Invalid("string is not UTF-8"))
+    }
+    fn done(&self) -> bool {
+        self.at == self.bytes.len()
+    }
+}
+
+fn write_move(out: &mut Vec<u8>, board: &Board, m: Move) -> Result<(), ArchiveError> {
+    if m == Move::null() {
+        out.push(NULL_MOVE);
+        return Ok(());
+    }
+    let index = board.generate_legal_moves(BB_ALL, BB_ALL).position(|legal| legal == m)
+        .ok_or_else(|| ArchiveError::UnencodableMove { uci: m.uci(), fen: board.fen() })?;
+    out.push(index as u8);
+    Ok(())
+}
+fn write_annotations(out: &mut Vec<u8>, node: Node) {
+    if node.comment().is_empty() && node.starting_comment().is_empty() && node.nags().is_empty() {
+        return;
+    }
+    out.push(ANNOTATION);
+    write_str(out, node.comment());
+    write_str(out, node.starting_comment());
+    let mut nags: Vec<&u64> = node.nags().iter().collect();
+    nags.sort();
+    write_varint(out, nags.len() as u64);
+    for nag in nags {
+        write_varint(out, *nag);
+    }
+}
+/// The moves after `parent`, with the alternatives to each move as
+/// variations between the move and its continuation, like in PGN.
+fn write_line(out: &mut Vec<u8>, board: &mut Board, mut parent: Node) -> Result<(), ArchiveError> {
+    loop {
+        let mut variations = parent.variations();
+        let main = match variations.next() {
+            Some(main) => main,
+            None => return Ok(())
+        };
+        write_move(out, board, main.m().unwrap())?;
+        write_annotations(out, main);
+        for variation in variations {
+            out.push(BEGIN_VARIATION);
+            let mut board = board.copy(false);
+            write_move(out, &board, variation.m().unwrap())?;
+            write_annotations(out, variation);
+            board.push(variation.m().unwrap());
+            write_line(out, &mut board, variation)?;
+            out.push(END_VARIATION);
+        }
+        board.push(main.m().unwrap());
+        parent = main;
+    }
+}
+
+/// Encodes a game: its headers, then the move tree with one byte per move,
+/// its index among the legal moves of the position. Comments, starting
+/// comments, NAGs and variations are kept, so `decode_game` gives back the
+/// same game. Null moves get a token of their own; any other move that is
+/// not legal in its position is an `UnencodableMove` error.
+///
+/// The indices depend on the order of the move generator. Archives written
+/// before it stopped generating pawn moves to the last rank that do not
+/// promote decode to wrong moves after any pawn reaches the seventh rank,
+/// and must be converted from the PGN again.
+pub fn encode_game(game: &Game) -> Result<Vec<u8>, ArchiveError> {
+    let mut out = Vec::new();
+    let headers = game.headers.items();
+    write_varint(&mut out, headers.len() as u64);
+    for (key, value) in headers {
+        write_str(&mut out, key);
+        write_str(&mut out, value);
+    }
+    write_annotations(&mut out, game.root());
+    write_line(&mut out, &mut Board::from_fen(game.headers.get("FEN").unwrap_or(STARTING_FEN))?, game.root())?;
+    Ok(out)
+}
+
+/// Decodes a game written by `encode_game`.
+pub fn decode_game(bytes: &[u8]) -> Result<Game, ArchiveError> {
+    let mut decoder = Decoder { bytes, at: 0 };
+    let mut game = Game::new();
+    for _ in 0..decoder.varint()? {
+        let (key, value) = (decoder.str()?, decoder.str()?);
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(ArchiveError::Invalid("header tag is not alphanumeric"));
+        }
+        if value.contains(['\n', '\r']) {
+            return Err(ArchiveError::Invalid("line break in header value"));
+        }
+        game.headers.set(key, value);
+    }
+
+    let mut board = Board::from_fen(game.headers.get("FEN").unwrap_or(STARTING_FEN))?;
+    let mut current = Game::ROOT;
+    let mut stack: Vec<(NodeId, Board)> = Vec::new();
+    while !decoder.done() {
+        match decoder.byte()? {
+            ANNOTATION => {
+                let (comment, starting_comment) = (decoder.str()?, decoder.str()?);
+                let mut nags = HashSet::new();
+                for _ in 0..decoder.varint()? {
+                    nags.insert(decoder.varint()?);
+                }
+                let mut node = game.node_mut(current);
+                node.set_comment(comment);
+                node.set_starting_comment(starting_comment);
+                *node.nags_mut() = nags;
+            },
+            BEGIN_VARIATION => {
+                let parent = game.node(current).parent().ok_or(ArchiveError::Invalid("variation before the first move"))?.id();
+                stack.push((current, board.copy(true)));
+                board.pop();
+                current = parent;
+            },
+            END_VARIATION => {
+                (current, board) = stack.pop().ok_or(ArchiveError::Invalid("unbalanced variation"))?;
+            },
+            NULL_MOVE => {
+                current = game.node_mut(current).add_variation(Move::null(), "", "", HashSet::new());
+                board.push(Move::null());
+            },
+            index => {
+                let m = board.generate_legal_moves(BB_ALL, BB_ALL).nth(index as usize)
+                    .ok_or_else(|| ArchiveError::IllegalMove { index, fen: board.fen() })?;
+                current = game.node_mut(current).add_variation(m, "", "", HashSet::new());
+                board.push(m);
+            }
+        }
+    }
+    if !stack.is_empty() {
+        return Err(ArchiveError::Truncated);
+    }
+    Ok(game)
+}
+
+/// Writes games to an archive: the magic `RCGAME01`, then every game as a
+/// varint length and its `encode_game` record.
+pub struct ArchiveWriter<W: Write> {
+    writer: W
+}
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<ArchiveWriter<W>> {
+        writer.write_all(MAGIC)?;
+        Ok(ArchiveWriter { writer })
+    }
+    /// Writes nothing if the game cannot be encoded.
+    pub fn write_game(&mut self, game: &Game) -> Result<(), ArchiveError> {
+        let record = encode_game(game)?;
+        let mut length = Vec::new();
+        write_varint(&mut length, record.len() as u64);
+        self.writer.write_all(&length)?;
+        self.writer.write_all(&record)?;
+        Ok(())
+    }
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Iterator over the games of an archive written by `ArchiveWriter`.
+pub struct ArchiveReader<R: Read> {
+    reader: R
+}
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut reader: R) -> Result<ArchiveReader<R>, ArchiveError> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(ArchiveError::BadMagic);
+        }
+        Ok(ArchiveReader { reader })
+    }
+    /// The length of the next record, or None at the end of the archive.
+    fn length(&mut self) -> Result<Option<usize>, ArchiveError> {
+        let (mut value, mut shift) = (0u64, 0);
+        loop {
+            let mut byte = [0u8];
+            if self.reader.read(&mut byte)? == 0 {
+                return match shift {
+                    0 => Ok(None),
+                    _ => Err(ArchiveError::Truncated)
+                };
+            }
+            value |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] < 0x80 {
+                return Ok(Some(value as usize));
+            }
+            shift += 7;
+            if shift >= 64 {
+                return Err(ArchiveError::Invalid("varint too long"));
+            }
+        }
+    }
+}
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = Result<Game, ArchiveError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let length = match self.length() {
+            Ok(length) => length?,
+            Err(e) => return Some(Err(e))
+        };
+        let mut record = vec![0u8; length];
+        if let Err(e) = self.reader.read_exact(&mut record) {
+            return Some(Err(match e.kind() {
+                io::ErrorKind::UnexpectedEof => ArchiveError::Truncated,
+                _ => ArchiveError::Io(e)
+            }));
+        }
+        Some(decode_game(&record))
+    }
+}
+
+/// Converts a PGN file to an archive and returns the number of games written.
+/// Games that fail to parse or contain illegal moves are skipped.
+pub fn pgn_to_archive(input: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<u64> {
+    let mut writer = ArchiveWriter::new(io::BufWriter::new(File::create(output)?))?;
+    let mut written = 0;
+    for game in Games::new(pgn::BufReader::open(input)?) {
+        match game {
+            Ok(game) => match writer.write_game(&game) {
+                Ok(()) => written += 1,
+                Err(ArchiveError::Io(e)) => return Err(e),
+                Err(_) => {}
+            },
+            Err(pgn::ParsingError::ReadLineError(e)) => return Err(e),
+            Err(_) => {}
+        }
+    }
+    writer.into_inner()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::{read_game, BufReader, GameBuilder};
+
+    fn record(headers: &[(&str, &str)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, headers.len() as u64);
+        for (key, value) in headers {
+            write_str(&mut out, key);
+            write_str(&mut out, value);
+        }
+        out
+    }
+
+    #[test]
+    fn null_move_round_trip() {
+        let pgn = "1. e4 -- 2. d4 *\n\n";
+        let game = read_game(&mut BufReader::new(pgn.as_bytes()), &mut GameBuilder::new()).unwrap();
+        let decoded = decode_game(&encode_game(&game).unwrap()).unwrap();
+        let moves: Vec<Move> = decoded.root().mainline_moves().flatten().collect();
+        assert_eq!(moves, [Move::from_uci("e2e4"), Move::null(), Move::from_uci("d2d4")]);
+    }
+
+    #[test]
+    fn illegal_move_is_an_error() {
+        let mut game = Game::new();
+        let root = game.root().id();
+        game.node_mut(root).add_variation(Move::from_uci("e2e5"), "", "", HashSet::new());
+        assert!(matches!(encode_game(&game), Err(ArchiveError::UnencodableMove { uci, .. }) if uci == "e2e5"));
+    }
+
+    #[test]
+    fn invalid_headers_are_errors() {
+        assert!(decode_game(&record(&[("Event", "Casual")])).is_ok());
+        assert!(matches!(decode_game(&record(&[("", "Casual")])), Err(ArchiveError::Invalid(_))));
+        assert!(matches!(decode_game(&record(&[("Bad Tag", "Casual")])), Err(ArchiveError::Invalid(_))));
+        assert!(matches!(decode_game(&record(&[("Event", "Casual\n[Result")])), Err(ArchiveError::Invalid(_))));
+        assert!(matches!(decode_game(&record(&[("FEN", "4k3/8/8/8/8/8/8/4K w - - 0 1")])), Err(ArchiveError::Fen(_))));
+        let record = record(&[("FEN", "4k3/8/8/8/8/8/8/4K3 b - - 0 1")]);
+        assert_eq!(decode_game(&record).unwrap().headers.get("FEN"), Some("4k3/8/8/8/8/8/8/4K3 b - - 0 1"));
+    }
+}
@@ -0,0 +1,413 @@
+//! Win/draw bitbases of small endgames, computed by retrograde analysis.
+//!
+//! Positions are indexed with the side that has material playing White: the
+//! side to move, its king, the lone king and then its other pieces. The king
+//! is brought to the a1-d1-d4 triangle by symmetry, or to the a-d files when
+//! there is a pawn. One bit per position tells whether that side wins; the
+//! lone king can never do better than a draw.
+//!
+//! Generating the KBNK bitbase takes seconds, so the built-in bitbases are
+//! only generated by an explicit call to `init`.
+
+use std::sync::OnceLock;
+
+use crate::init::{Board, BB_ALL, BB_SQUARES, BISHOP, BLACK, KNIGHT, PAWN, QUEEN, ROOK, WHITE};
+use crate::syzygy::Wdl;
+
+/// Squares a1, b1, c1, d1, b2, c2, d2, c3, d3 and d4.
+const TRIANGLE: [u8; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Material {
+    Kpk,
+    Kqk,
+    Krk,
+    Kbnk
+}
+impl Material {
+    pub const ALL: [Material; 4] = [Material::Kpk, Material::Kqk, Material::Krk, Material::Kbnk];
+
+    /// The material of `board`, with either color as the side with pieces.
+    pub fn of(board: &Board) -> Option<Material> {
+        let signature = board.baseboard.material_signature();
+        let strong = match signature.split_once('v')? {
+            ("K", strong) | (strong, "K") => strong,
+            _ => return None
+        };
+        match strong {
+            "KP" => Some(Material::Kpk),
+            "KQ" => Some(Material::Kqk),
+            "KR" => Some(Material::Krk),
+            "KBN" => Some(Material::Kbnk),
+            _ => None
+        }
+    }
+    /// The pieces of the side with material besides its king.
+    pub fn pieces(self) -> &'static [u8] {
+        match self {
+            Material::Kpk => &[PAWN],
+            Material::Kqk => &[QUEEN],
+            Material::Krk => &[ROOK],
+            Material::Kbnk => &[BISHOP, KNIGHT]
+        }
+    }
+    fn king_squares(self) -> usize {
+        match self {
+            Material::Kpk => 32,
+            _ => TRIANGLE.len()
+        }
+    }
+    /// Number of indices, including illegal and symmetric positions.
+    fn size(self) -> usize {
+        2 * self.king_squares() * 64usize.pow(1 + self.pieces().len() as u32)
+    }
+    fn count(self) -> usize {
+        2 + self.pieces().len()
+    }
+
+    fn raw_index(self, p: &Position) -> usize {
+        let king = p.squares[0];
+        let king = match self {
+            Material::Kpk => (king >> 3) as usize * 4 + (king & 7) as usize,
+            _ => TRIANGLE.iter().position(|square| *square == king).unwrap()
+        };
+        let mut index = p.strong_to_move as usize * self.king_squares() + king;
+        for square in &p.squares[1..self.count()] {
+            index = index * 64 + *square as usize;
+        }
+        index
+    }
+    /// The index of `p`, the same for every position symmetric to it.
+    fn index(self, mut p: Position) -> usize {
+        let n = self.count();
+        if p.squares[0] & 7 >= 4 {
+            p.map(n, |square| square ^ 7);
+        }
+        if self == Material::Kpk {
+            return self.raw_index(&p);
+        }
+        if p.squares[0] >= 32 {
+            p.map(n, |square| square ^ 56);
+        }
+        let (rank, file) = (p.squares[0] >> 3, p.squares[0] & 7);
+        if rank > file {
+            p.map(n, transpose);
+        }
+        let index = self.raw_index(&p);
+        if rank != file {
+            return index;
+        }
+        // The king is on the diagonal, so the transposed position is in
+        // the triangle as well.
+        p.map(n, transpose);
+        index.min(self.raw_index(&p))
+    }
+    fn position(self, mut index: usize) -> Position {
+        let mut squares = [0u8; 4];
+        for slot in (1..self.count()).rev() {
+            squares[slot] = (index % 64) as u8;
+            index /= 64;
+        }
+        let king = index % self.king_squares();
+        squares[0] = match self {
+            Material::Kpk => (king / 4 * 8 + king % 4) as u8,
+            _ => TRIANGLE[king]
+        };
+        Position { strong_to_move: index / self.king_squares() == 1, squares }
+    }
+
+    /// The board of `p` with the side with material as White, or None if
+    /// the squares do not make a legal position.
+    fn board(self, p: &Position) -> Option<Board> {
+        let mut board = Board::new(None);
+        let b = &mut board.baseboard;
+        for (slot, square) in p.squares[..self.count()].iter().enumerate() {
+            let bb = BB_SQUARES[*square as usize];
+            if b.occupied & bb != 0 {
+                return None;
+            }
+            b.occupied |= bb;
+            match slot.checked_sub(2).map(|piece| self.pieces()[piece]) {
+                None => b.kings |= bb,
+                Some(PAWN) if !(8..56).contains(square) => return None,
+                Some(PAWN) => b.pawns |= bb,
+                Some(KNIGHT) => b.knights |= bb,
+                Some(BISHOP) => b.bishops |= bb,
+                Some(ROOK) => b.rooks |= bb,
+                Some(_) => b.queens |= bb
+            }
+        }
+        b.occupied_co[BLACK as usize] = BB_SQUARES[p.squares[1] as usize];
+        b.occupied_co[WHITE as usize] = b.occupied & !b.occupied_co[BLACK as usize];
+        board.turn = if p.strong_to_move { WHITE } else { BLACK };
+        let king = if p.strong_to_move { p.squares[1] } else { p.squares[0] };
+        match board.baseboard.is_attacked_by(board.turn, king) {
+            true => None,
+            false => Some(board)
+        }
+    }
+    /// Positions one ply earlier: a piece of the side that just moved goes
+    /// back to a square it could have come from without capturing.
+    fn unmoves(self, p: &Position, board: &Board) -> Vec<Position> {
+        let slots: Vec<usize> = match p.strong_to_move {
+            true => vec![1],
+            false => std::iter::once(0).chain(2..self.count()).collect()
+        };
+        let empty = !board.baseboard.occupied;
+        let mut positions = Vec::new();
+        for slot in slots {
+            let to = p.squares[slot];
+            let mut from = match slot.checked_sub(2).map(|piece| self.pieces()[piece]) {
+                Some(PAWN) => {
+                    let mut from = 0;
+                    if to >= 16 && empty & BB_SQUARES[to as usize - 8] != 0 {
+                        from |= BB_SQUARES[to as usize - 8];
+                        if to >> 3 == 3 && empty & BB_SQUARES[to as usize - 16] != 0 {
+                            from |= BB_SQUARES[to as usize - 16];
+                        }
+                    }
+                    from
+                },
+                _ => board.baseboard.attacks_mask(to) & empty
+            };
+            while from != 0 {
+                let mut q = *p;
+                q.squares[slot] = from.trailing_zeros() as u8;
+                q.strong_to_move = !p.strong_to_move;
+                positions.push(q);
+                from &= from - 1;
+            }
+        }
+        positions
+    }
+}
+
+fn transpose(square: u8) -> u8 {
+    (square >> 3) | (square & 7) << 3
+}
+
+/// A position of a material set: the side with material to move or not,
+/// and the squares of its king, the lone king and its other pieces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position {
+    strong_to_move: bool,
+    squares: [u8; 4]
+}
+impl Position {
+    fn map(&mut self, n: usize, f: impl Fn(u8) -> u8) {
+        for square in self.squares[..n].iter_mut() {
+            *square = f(*square);
+        }
+    }
+}
+
+/// Whether the side with material wins every position of one material set,
+/// at one bit per position. The fifty-move rule is ignored.
+pub struct Bitbase {
+    material: Material,
+    bits: Vec<u64>
+}
+impl Bitbase {
+    /// Computes the bitbase backwards from the checkmates (and, for KPK,
+    /// from the promotions that win), using `generate_legal_moves` for the
+    /// moves of the lone king and un-moves for the positions before. Each
+    /// position of the lone king to move counts down the moves it has left
+    /// that do not lose, so its moves are only generated once.
+    pub fn generate(material: Material) -> Bitbase {
+        let mut bitbase = Bitbase { material, bits: vec![0; (material.size() + 63) / 64] };
+        let mut queue = Vec::new();
+        for index in 0..material.size() {
+            let p = material.position(index);
+            if (p.strong_to_move && material != Material::Kpk) || material.index(p) != index {
+                continue;
+            }
+            let mut board = match material.board(&p) {
+                Some(board) => board,
+                None => continue
+            };
+            let won = match p.strong_to_move {
+                true => {
+                    let promotions: Vec<_> = board.generate_legal_moves(board.baseboard.pawns, BB_ALL)
+                        .filter(|m| m.promotion.is_some())
+                        .collect();
+                    promotions.into_iter().any(|m| {
+                        board.push(m);
+                        let wdl = Material::of(&board).and_then(|material| init(material).probe(&board));
+                        board.pop();
+                        wdl == Some(Wdl::Loss)
+                    })
+                },
+                false => board.is_check() && board.is_checkmate()
+            };
+            if won {
+                bitbase.set(index);
+                queue.push(index);
+            }
+        }
+
+        // Moves left that do not lose yet, plus one: 0 before the first
+        // visit and `u8::MAX` if the position is illegal or can capture.
+        let mut left = vec![0u8; material.size()];
+        while let Some(index) = queue.pop() {
+            let p = material.position(index);
+            let board = material.board(&p).unwrap();
+            // Un-moves to symmetric positions are the same move of the
+            // position before, so each index only counts once.
+            let mut unmoves: Vec<(usize, Position)> = material.unmoves(&p, &board).into_iter().map(|q| (material.index(q), q)).collect();
+            unmoves.sort_unstable_by_key(|(index, _)| *index);
+            unmoves.dedup_by_key(|(index, _)| *index);
+            for (index, q) in unmoves {
+                if bitbase.get(index) || left[index] == u8::MAX {
+                    continue;
+                }
+                // The side with material needs one winning move, the lone
+                // king loses if every move loses and none captures.
+                if left[index] == 0 {
+                    left[index] = match material.board(&q) {
+                        Some(_) if q.strong_to_move => 2,
+                        Some(board) => {
+                            let moves: Vec<_> = board.generate_legal_moves(BB_ALL, BB_ALL).collect();
+                            let mut next: Vec<usize> = moves.iter().map(|m| {
+                                let mut next = q;
+                                next.squares[1] = m.to_square;
+                                next.strong_to_move = true;
+                                material.index(next)
+                            }).collect();
+                            next.sort_unstable();
+                            next.dedup();
+                            match moves.iter().any(|m| board.baseboard.occupied & BB_SQUARES[m.to_square as usize] != 0) {
+                                true => u8::MAX,
+                                false => next.len() as u8 + 1
+                            }
+                        },
+                        None => u8::MAX
+                    };
+                    if left[index] == u8::MAX {
+                        continue;
+                    }
+                }
+                left[index] -= 1;
+                if left[index] == 1 {
+                    bitbase.set(index);
+                    queue.push(index);
+                }
+            }
+        }
+        bitbase
+    }
+    fn get(&self, index: usize) -> bool {
+        self.bits[index >> 6] >> (index & 63) & 1 != 0
+    }
+    fn set(&mut self, index: usize) {
+        self.bits[index >> 6] |= 1 << (index & 63);
+    }
+    pub fn material(&self) -> Material {
+        self.material
+    }
+    /// Size of the bitbase in bytes.
+    pub fn size(&self) -> usize {
+        self.bits.len() * 8
+    }
+    /// Win, draw or loss for the side to move, or None if the material of
+    /// `board` is not this bitbase's or there are castling rights.
+    pub fn probe(&self, board: &Board) -> Option<Wdl> {
+        if Material::of(board)? != self.material || board.castling_rights != 0 {
+            return None;
+        }
+        let b = &board.baseboard;
+        let strong = b.occupied_co[WHITE as usize].count_ones() > 1;
+        let orient = |square: u8| if strong == WHITE { square } else { square ^ 56 };
+        let mut squares = [orient(b.king(strong)?), orient(b.king(!strong)?), 0, 0];
+        for (slot, piece_type) in self.material.pieces().iter().enumerate() {
+            squares[slot + 2] = orient(b.pieces_mask(*piece_type, strong).trailing_zeros() as u8);
+        }
+        let p = Position { strong_to_move: board.turn == strong, squares };
+        Some(match (self.get(self.material.index(p)), p.strong_to_move) {
+            (false, _) => Wdl::Draw,
+            (true, true) => Wdl::Win,
+            (true, false) => Wdl::Loss
+        })
+    }
+}
+
+/// The built-in bitbases, in the order of `Material::ALL`.
+static BITBASES: [OnceLock<Bitbase>; 4] = [OnceLock::new(), OnceLock::new(), OnceLock::new(), OnceLock::new()];
+
+/// Generates the built-in bitbase for `material` unless it already was, and
+/// returns it. KPK generates KQK and KRK as well.
+pub fn init(material: Material) -> &'static Bitbase {
+    BITBASES[material as usize].get_or_init(|| Bitbase::generate(material))
+}
+/// Generates every built-in bitbase, see `init`.
+pub fn init_all() {
+    for material in Material::ALL {
+        init(material);
+    }
+}
+
+/// The built-in bitbase for `material`, or None until `init` generated it.
+pub fn bitbase(material: Material) -> Option<&'static Bitbase> {
+    BITBASES[material as usize].get()
+}
+
+/// Probes the built-in bitbase for the material of `board`, see
+/// `Bitbase::probe`. None as well if that bitbase was not generated.
+pub fn probe(board: &Board) -> Option<Wdl> {
+    bitbase(Material::of(board)?)?.probe(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wdl(fen: &str) -> Option<Wdl> {
+        let board = Board::new(Some(fen));
+        init(Material::of(&board)?);
+        probe(&board)
+    }
+
+    #[test]
+    fn kpk() {
+        // The king on the sixth rank in front of its pawn wins either way.
+        assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("8/8/8/8/4p3/4k3/8/4K3 w - - 0 1"), Some(Wdl::Loss));
+        // The defending king has the opposition.
+        assert_eq!(wdl("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1"), Some(Wdl::Draw));
+        // The pawn hangs.
+        assert_eq!(wdl("8/8/8/8/8/8/3kP3/7K b - - 0 1"), Some(Wdl::Draw));
+        // Stalemate.
+        assert_eq!(wdl("k7/P7/1K6/8/8/8/8/8 b - - 0 1"), Some(Wdl::Draw));
+    }
+
+    #[test]
+    fn kqk() {
+        assert_eq!(wdl("4k3/8/8/8/8/8/8/3QK3 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(wdl("3qk3/8/8/8/8/8/8/4K3 w - - 0 1"), Some(Wdl::Loss));
+        // Checkmate.
+        assert_eq!(wdl("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), Some(Wdl::Loss));
+        // The queen hangs.
+        assert_eq!(wdl("8/8/8/3k4/4Q3/8/8/7K b - - 0 1"), Some(Wdl::Draw));
+        // Stalemate.
+        assert_eq!(wdl("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), Some(Wdl::Draw));
+    }
+
+    #[test]
+    fn kbnk() {
+        assert_eq!(wdl("8/8/8/4k3/8/2KBN3/8/8 b - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(wdl("8/8/8/4k3/8/2KBN3/8/8 w - - 0 1"), Some(Wdl::Win));
+        // The bishop hangs.
+        assert_eq!(wdl("8/8/8/8/3k4/4B3/8/K6N b - - 0 1"), Some(Wdl::Draw));
+        // Stalemate.
+        assert_eq!(wdl("k7/8/1K6/4B3/8/8/8/7N b - - 0 1"), Some(Wdl::Draw));
+    }
+
+    #[test]
+    fn not_generated_or_other_material() {
+        assert_eq!(probe(&Board::new(Some("4k3/8/8/8/8/8/8/3RK2R w - - 0 1"))), None);
+        assert_eq!(Material::of(&Board::new(Some("4k3/8/8/8/8/8/8/3RK3 w - - 0 1"))), Some(Material::Krk));
+        let kqk = init(Material::Kqk);
+        assert_eq!(kqk.probe(&Board::new(Some("4k3/8/8/8/8/8/8/3RK3 w - - 0 1"))), None);
+        assert_eq!(bitbase(Material::Kqk).map(Bitbase::material), Some(Material::Kqk));
+    }
+}
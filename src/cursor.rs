@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+
+use crate::init::{Board, Move};
+use crate::pgn::{Game, MoveRepr, Node, NodeId, NodeMut};
+
+/// A position in a game tree together with its board, as used by an
+/// analysis board. Navigation keeps the board in sync by pushing and
+/// popping single moves; editing goes through the `NodeMut` of the game.
+pub struct GameCursor<'a> {
+    game: &'a mut Game,
+    node: NodeId,
+    board: Board
+}
+impl<'a> GameCursor<'a> {
+    pub fn new(game: &'a mut Game) -> GameCursor<'a> {
+        let board = game.setup_board();
+        GameCursor { game, node: Game::ROOT, board }
+    }
+    pub fn game(&self) -> &Game {
+        self.game
+    }
+    pub fn id(&self) -> NodeId {
+        self.node
+    }
+    pub fn node(&self) -> Node<'_> {
+        self.game.node(self.node)
+    }
+    pub fn node_mut(&mut self) -> NodeMut<'_> {
+        self.game.node_mut(self.node)
+    }
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+    /// Moves the cursor to `id`, replaying the board from the starting position.
+    pub fn goto(&mut self, id: NodeId) {
+        self.board = self.game.node(id).board();
+        self.node = id;
+    }
+    fn enter(&mut self, id: NodeId) {
+        self.board.push(self.game[id].m.unwrap());
+        self.node = id;
+    }
+    /// Follows the main variation. Returns false at the end of a line.
+    pub fn forward(&mut self) -> bool {
+        match self.node().next() {
+            Some(next) => {
+                let id = next.id();
+                self.enter(id);
+                true
+            },
+            None => false
+        }
+    }
+    /// Steps back to the parent. Returns false at the root.
+    pub fn back(&mut self) -> bool {
+        match self.game[self.node].parent {
+            Some(parent) => {
+                self.board.pop();
+                self.node = parent;
+                true
+            },
+            None => false
+        }
+    }
+    pub fn go_start(&mut self) {
+        while self.back() {}
+    }
+    pub fn go_end(&mut self) {
+        while self.forward() {}
+    }
+    fn sibling(&mut self, offset: isize) -> bool {
+        let parent = match self.game[self.node].parent {
+            Some(parent) => parent,
+            None => return false
+        };
+        let siblings = &self.game[parent].variations;
+        let index = siblings.iter().position(|id| *id == self.node).unwrap() as isize + offset;
+        if index < 0 || index as usize >= siblings.len() {
+            return false;
+        }
+        let sibling = siblings[index as usize];
+        self.board.pop();
+        self.enter(sibling);
+        true
+    }
+    /// Switches to the next sibling variation of the current move.
+    pub fn next_variation(&mut self) -> bool {
+        self.sibling(1)
+    }
+    /// Switches to the previous sibling variation of the current move.
+    pub fn prev_variation(&mut self) -> bool {
+        self.sibling(-1)
+    }
+    /// Plays `m` from the current node, following an existing variation
+    /// with that move or adding a new one. Returns None and stays put if `m`
+    /// is not legal.
+    pub fn play(&mut self, m: Move) -> Option<NodeId> {
+        if !self.board.is_legal(m) {
+            return None;
+        }
+        let existing = self.node().variations().find(|variation| variation.m() == Some(m)).map(|variation| variation.id());
+        let id = match existing {
+            Some(id) => id,
+            None => self.node_mut().add_variation(m, "", "", HashSet::new())
+        };
+        self.enter(id);
+        Some(id)
+    }
+    /// Removes the current move and everything after it, then steps back to
+    /// the parent. Returns false at the root, which cannot be deleted.
+    pub fn delete(&mut self) -> bool {
+        let id = self.node;
+        if !self.back() {
+            return false;
+        }
+        self.node_mut().remove_variation(MoveRepr::Node(id));
+        true
+    }
+    /// Removes all continuations of the current node.
+    pub fn truncate(&mut self) {
+        let variations: Vec<NodeId> = self.node().variations().map(|variation| variation.id()).collect();
+        for variation in variations {
+            self.node_mut().remove_variation(MoveRepr::Node(variation));
+        }
+    }
+    /// Makes the line leading to the current node the mainline of the game.
+    pub fn promote_to_main(&mut self) {
+        let mut id = self.node;
+        while let Some(parent) = self.game[id].parent {
+            self.game.node_mut(parent).promote_to_main(MoveRepr::Node(id));
+            id = parent;
+        }
+    }
+    /// Moves the current move one place up among its siblings.
+    pub fn promote(&mut self) {
+        if let Some(parent) = self.game[self.node].parent {
+            self.game.node_mut(parent).promote(MoveRepr::Node(self.node));
+        }
+    }
+    /// Moves the current move one place down among its siblings.
+    pub fn demote(&mut self) {
+        if let Some(parent) = self.game[self.node].parent {
+            self.game.node_mut(parent).demote(MoveRepr::Node(self.node));
+        }
+    }
+    pub fn set_comment(&mut self, comment: &str) {
+        self.node_mut().set_comment(comment);
+    }
+    pub fn set_starting_comment(&mut self, starting_comment: &str) {
+        self.node_mut().set_starting_comment(starting_comment);
+    }
+    /// Toggles a NAG on the current node, as a front end does on a click.
+    pub fn toggle_nag(&mut self, nag: u64) {
+        let mut node = self.node_mut();
+        let nags = node.nags_mut();
+        if !nags.remove(&nag) {
+            nags.insert(nag);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor_moves(cursor: &GameCursor) -> Vec<String> {
+        cursor.game().root().mainline_moves().flatten().map(|m| m.uci()).collect()
+    }
+
+    #[test]
+    fn play_follows_or_adds_variations() {
+        let mut game = Game::new();
+        let mut cursor = GameCursor::new(&mut game);
+        let e4 = cursor.play(Move::from_uci("e2e4")).unwrap();
+        assert_eq!(cursor.play(Move::from_uci("e2e4")), None);
+        cursor.play(Move::from_uci("e7e5")).unwrap();
+        assert_eq!(cursor.board().fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+        cursor.go_start();
+        assert_eq!(cursor.play(Move::from_uci("e2e4")), Some(e4));
+        assert_eq!(cursor.node().variations().count(), 1);
+        assert!(cursor.back());
+        let d4 = cursor.play(Move::from_uci("d2d4")).unwrap();
+        assert_eq!(cursor.game().root().variations().map(|variation| variation.id()).collect::<Vec<_>>(), [e4, d4]);
+        cursor.go_start();
+        cursor.go_end();
+        assert_eq!(cursor.board().ply(), 2);
+        assert_eq!(cursor_moves(&cursor), ["e2e4", "e7e5"]);
+    }
+
+    #[test]
+    fn promote_and_demote() {
+        let mut game = Game::new();
+        let mut cursor = GameCursor::new(&mut game);
+        let e4 = cursor.play(Move::from_uci("e2e4")).unwrap();
+        cursor.back();
+        let d4 = cursor.play(Move::from_uci("d2d4")).unwrap();
+        cursor.play(Move::from_uci("d7d5")).unwrap();
+        cursor.promote_to_main();
+        assert_eq!(cursor_moves(&cursor), ["d2d4", "d7d5"]);
+        cursor.goto(e4);
+        cursor.promote();
+        assert_eq!(cursor_moves(&cursor), ["e2e4"]);
+        cursor.demote();
+        assert_eq!(cursor_moves(&cursor), ["d2d4", "d7d5"]);
+        assert!(cursor.prev_variation());
+        assert_eq!(cursor.id(), d4);
+        assert!(cursor.next_variation());
+        assert_eq!(cursor.id(), e4);
+        assert_eq!(cursor.board().fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+    }
+
+    #[test]
+    fn delete_and_truncate() {
+        let mut game = Game::new();
+        let mut cursor = GameCursor::new(&mut game);
+        assert!(!cursor.delete());
+        cursor.play(Move::from_uci("e2e4")).unwrap();
+        cursor.play(Move::from_uci("e7e5")).unwrap();
+        cursor.play(Move::from_uci("g1f3")).unwrap();
+        cursor.back();
+        assert!(cursor.delete());
+        assert_eq!(cursor.board().ply(), 1);
+        assert_eq!(cursor_moves(&cursor), ["e2e4"]);
+        cursor.play(Move::from_uci("c7c5")).unwrap();
+        cursor.back();
+        cursor.back();
+        cursor.truncate();
+        assert_eq!(cursor_moves(&cursor), Vec::<String>::new());
+        assert_eq!(cursor.board().ply(), 0);
+    }
+}
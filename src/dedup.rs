@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::Path;
+
+use crate::init::{Board, Move};
+use crate::pgn::{self, Games, SkipType, Visitor};
+
+/// Identity of a game for duplicate detection: where it starts, its
+/// normalised headers and its mainline.
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    pub offset: u64,
+    pub white: String,
+    pub black: String,
+    pub date: String,
+    pub event: String,
+    pub round: String,
+    pub result: String,
+    pub moves: Vec<Move>
+}
+impl Fingerprint {
+    fn headers_key(&self) -> String {
+        format!("{}\t{}\t{}\t{}\t{}\t{}", self.white, self.black, self.date, self.event, self.round, self.result)
+    }
+    /// Same players (allowing small typos) and the same result.
+    fn similar_headers(&self, other: &Fingerprint) -> bool {
+        self.result == other.result && similar_names(&self.white, &other.white) && similar_names(&self.black, &other.black)
+    }
+}
+/// Lowercase alphanumerics only, so `Carlsen, M.` and `carlsen m` compare equal.
+fn normalise(value: &str) -> String {
+    value.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+/// Equal, one a prefix of the other (`carlsen` and `carlsenm`), or within
+/// one typo per five characters of the shorter name, at most two. Empty
+/// names are never similar, and short ones only when equal.
+fn similar_names(a: &str, b: &str) -> bool {
+    let shorter = a.chars().count().min(b.chars().count());
+    if shorter == 0 {
+        return false;
+    }
+    a == b || (shorter >= 4 && (a.starts_with(b) || b.starts_with(a))) || edit_distance(a, b) <= (shorter / 5).min(2)
+}
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = (previous + (ca != *cb) as usize).min(row[j] + 1).min(current + 1);
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+fn hash_moves(moves: &[Move]) -> u64 {
+    // FNV-1a over from, to and promotion of every move.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for m in moves {
+        for byte in [m.from_square, m.to_square, m.promotion.unwrap_or(0)] {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Visitor producing a `Fingerprint`. Variations are skipped.
+pub struct FingerprintVisitor {
+    fingerprint: Fingerprint
+}
+impl FingerprintVisitor {
+    pub fn new() -> FingerprintVisitor {
+        FingerprintVisitor { fingerprint: FingerprintVisitor::empty() }
+    }
+    fn empty() -> Fingerprint {
+        Fingerprint {
+            offset: 0,
+            white: String::new(),
+            black: String::new(),
+            date: String::new(),
+            event: String::new(),
+            round: String::new(),
+            result: String::new(),
+            moves: Vec::new()
+        }
+    }
+}
+impl Visitor for FingerprintVisitor {
+    type Output = Fingerprint;
+    fn begin_game(&mut self) -> Option<SkipType> {
+        self.fingerprint = FingerprintVisitor::empty();
+        None
+    }
+    fn visit_offset(&mut self, offset: u64) {
+        self.fingerprint.offset = offset;
+    }
+    fn visit_header(&mut self, tagname: &str, tagvalue: &str) {
+        let fingerprint = &mut self.fingerprint;
+        match tagname {
+            "White" => fingerprint.white = normalise(tagvalue),
+            "Black" => fingerprint.black = normalise(tagvalue),
+            "Date" => fingerprint.date = normalise(tagvalue),
+            "Event" => fingerprint.event = normalise(tagvalue),
+            "Round" => fingerprint.round = normalise(tagvalue),
+            "Result" => fingerprint.result = tagvalue.trim().to_string(),
+            _ => {}
+        }
+    }
+    fn visit_move(&mut self, _board: &Board, m: Move) {
+        self.fingerprint.moves.push(m);
+    }
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        Some(SkipType::SKIP)
+    }
+    /// Keeps the moves up to an illegal one, so that every game of a file
+    /// gets a fingerprint and byte ranges between offsets stay whole games.
+    fn handle_error(&mut self, _error: pgn::ParsingError) -> Result<(), pgn::ParsingError> {
+        Ok(())
+    }
+    fn result(&mut self) -> Fingerprint {
+        std::mem::replace(&mut self.fingerprint, FingerprintVisitor::empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateKind {
+    /// Same normalised headers and moves.
+    Exact,
+    /// Same moves, players that differ by a typo.
+    SimilarHeaders,
+    /// The moves are a prefix of the kept game's, e.g. a game that was cut
+    /// off or broadcast before it ended.
+    Prefix
+}
+/// Game `game` duplicates game `of`, which is kept. Indices count games in
+/// file order from 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Duplicate {
+    pub game: usize,
+    pub of: usize,
+    pub kind: DuplicateKind
+}
+
+/// Finds duplicates among fingerprinted games. Near-duplicates are only
+/// considered for games of at least `min_plies` plies, since short games
+/// share their moves all the time. Of two games where one is a prefix of
+/// the other, the longer one is kept.
+pub fn find_duplicates(games: &[Fingerprint], min_plies: usize) -> Vec<Duplicate> {
+    let min_plies = min_plies.max(1);
+    let mut duplicate_of: Vec<Option<(usize, DuplicateKind)>> = vec![None; games.len()];
+    let mut exact: HashMap<(String, u64), Vec<usize>> = HashMap::new();
+    let mut by_moves: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut by_prefix: HashMap<u64, Vec<usize>> = HashMap::new();
+
+    for (index, game) in games.iter().enumerate() {
+        let moves_hash = hash_moves(&game.moves);
+        let same_game = exact.entry((game.headers_key(), moves_hash)).or_default();
+        if let Some(first) = same_game.iter().find(|first| games[**first].moves == game.moves) {
+            duplicate_of[index] = Some((*first, DuplicateKind::Exact));
+            continue;
+        }
+        same_game.push(index);
+        if game.moves.len() < min_plies {
+            continue;
+        }
+
+        let same_moves = by_moves.entry(moves_hash).or_default();
+        if let Some(kept) = same_moves.iter().find(|kept| games[**kept].moves == game.moves && games[**kept].similar_headers(game)) {
+            duplicate_of[index] = Some((*kept, DuplicateKind::SimilarHeaders));
+            continue;
+        }
+        same_moves.push(index);
+
+        let bucket = by_prefix.entry(hash_moves(&game.moves[..min_plies])).or_default();
+        bucket.retain(|kept| duplicate_of[*kept].is_none());
+        let mut kept_here = true;
+        for kept in bucket.iter() {
+            let other = &games[*kept];
+            if !other.similar_headers(game) {
+                continue;
+            }
+            if other.moves.starts_with(&game.moves) {
+                duplicate_of[index] = Some((*kept, DuplicateKind::Prefix));
+                kept_here = false;
+                break;
+            }
+            if game.moves.starts_with(&other.moves) {
+                duplicate_of[*kept] = Some((index, DuplicateKind::Prefix));
+            }
+        }
+        if kept_here {
+            bucket.push(index);
+        }
+    }
+
+    // Point every duplicate at the game that is finally kept.
+    let mut duplicates = Vec::new();
+    for (game, entry) in duplicate_of.iter().enumerate() {
+        if let Some((mut of, kind)) = entry {
+            while let Some((next, _)) = duplicate_of[of] {
+                of = next;
+            }
+            duplicates.push(Duplicate { game, of, kind: *kind });
+        }
+    }
+    duplicates
+}
+
+/// Fingerprints every game of a PGN file.
+pub fn fingerprint_file(path: impl AsRef<Path>) -> io::Result<Vec<Fingerprint>> {
+    let handle = pgn::BufReader::open(path)?;
+    let mut games = Vec::new();
+    for game in Games::with_visitor(handle, FingerprintVisitor::new()) {
+        match game {
+            Ok(game) => games.push(game),
+            Err(pgn::ParsingError::ReadLineError(e)) => return Err(e),
+            // The visitor recovers from invalid moves, so nothing else should
+            // get here; fail rather than lose track of the game offsets.
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        }
+    }
+    Ok(games)
+}
+
+/// Copies `input` to `output` without the duplicate games. Kept games are
+/// copied byte for byte, so their formatting and annotations are untouched.
+pub fn write_cleaned(input: impl AsRef<Path>, output: impl AsRef<Path>, games: &[Fingerprint], duplicates: &[Duplicate]) -> io::Result<u64> {
+    let mut file = File::open(input)?;
+    let len = file.metadata()?.len();
+    let mut writer = io::BufWriter::new(File::create(output)?);
+    let mut removed = vec![false; games.len()];
+    for duplicate in duplicates {
+        removed[duplicate.game] = true;
+    }
+
+    let mut written = 0;
+    for (index, game) in games.iter().enumerate() {
+        if removed[index] {
+            continue;
+        }
+        let end = games.get(index + 1).map_or(len, |next| next.offset);
+        file.seek(SeekFrom::Start(game.offset))?;
+        io::copy(&mut (&mut file).take(end - game.offset), &mut writer)?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+/// Removes the duplicates of `input`, writing the remaining games to
+/// `output`, and returns what was removed.
+pub fn dedup_file(input: impl AsRef<Path>, output: impl AsRef<Path>, min_plies: usize) -> io::Result<Vec<Duplicate>> {
+    let games = fingerprint_file(&input)?;
+    let duplicates = find_duplicates(&games, min_plies);
+    write_cleaned(input, output, &games, &duplicates)?;
+    Ok(duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similar_names_scale_with_length() {
+        assert!(similar_names("carlsenmagnus", "carlsenmagnsu"));
+        assert!(similar_names("carlsen", "carlsem"));
+        assert!(similar_names("carlsen", "carlsenm"));
+        assert!(!similar_names("carlsen", "karlsem"));
+        assert!(!similar_names("li", "wu"));
+        assert!(!similar_names("li", "lin"));
+        assert!(!similar_names("", ""));
+        assert!(!similar_names("", "li"));
+    }
+
+    fn game(white: &str, black: &str, result: &str, moves: &str) -> Fingerprint {
+        Fingerprint {
+            white: normalise(white),
+            black: normalise(black),
+            result: result.to_string(),
+            moves: moves.split_whitespace().map(Move::from_uci).collect(),
+            ..FingerprintVisitor::empty()
+        }
+    }
+    const MOVES: &str = "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6";
+
+    #[test]
+    fn exact_duplicates() {
+        let games = [game("Carlsen", "Caruana", "1-0", MOVES), game("Carlsen", "Caruana", "1-0", MOVES),
+                     game("Carlsen", "Caruana", "1-0", "d2d4"), game("Carlsen", "Caruana", "1-0", "d2d4")];
+        assert_eq!(find_duplicates(&games, 4), [
+            Duplicate { game: 1, of: 0, kind: DuplicateKind::Exact },
+            Duplicate { game: 3, of: 2, kind: DuplicateKind::Exact }
+        ]);
+        // Different moves are never exact duplicates, even short ones.
+        let games = [game("Carlsen", "Caruana", "1-0", "d2d4"), game("Carlsen", "Caruana", "1-0", "e2e4")];
+        assert_eq!(find_duplicates(&games, 4), []);
+    }
+
+    #[test]
+    fn similar_header_duplicates() {
+        let games = [game("Carlsen, Magnus", "Caruana, Fabiano", "1-0", MOVES), game("Carlsen, Magnsu", "Caruana F", "1-0", MOVES),
+                     game("Carlsen, Magnus", "Caruana, Fabiano", "0-1", MOVES), game("Nakamura", "Caruana, Fabiano", "1-0", MOVES)];
+        assert_eq!(find_duplicates(&games, 4), [Duplicate { game: 1, of: 0, kind: DuplicateKind::SimilarHeaders }]);
+        // Too short to be near-duplicates.
+        assert_eq!(find_duplicates(&games[..2], 10), []);
+    }
+
+    #[test]
+    fn prefix_duplicates_keep_the_longer_game() {
+        let games = [game("Carlsen", "Caruana", "*", "e2e4 e7e5 g1f3 b8c6"), game("Carlsen", "Caruana", "*", MOVES),
+                     game("Carlsen", "Caruana", "*", "e2e4 e7e5 g1f3")];
+        assert_eq!(find_duplicates(&games, 2), [
+            Duplicate { game: 0, of: 1, kind: DuplicateKind::Prefix },
+            Duplicate { game: 2, of: 1, kind: DuplicateKind::Prefix }
+        ]);
+    }
+
+    #[test]
+    fn duplicates_point_at_the_kept_game() {
+        // 1 is an exact copy of 0, which becomes a prefix of 2.
+        let games = [game("Carlsen", "Caruana", "*", "e2e4 e7e5 g1f3 b8c6"), game("Carlsen", "Caruana", "*", "e2e4 e7e5 g1f3 b8c6"),
+                     game("Carlsen", "Caruana", "*", MOVES)];
+        assert_eq!(find_duplicates(&games, 2), [
+            Duplicate { game: 0, of: 2, kind: DuplicateKind::Prefix },
+            Duplicate { game: 1, of: 2, kind: DuplicateKind::Exact }
+        ]);
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+use crate::init::{Board, STARTING_FEN};
+use crate::pgn::Game;
+
+/// Opening dataset compiled into the binary: `eco`, `name` and the moves in
+/// PGN notation, one opening per line, covering every code from A00 to E99.
+/// Names follow the `Opening: Variation` convention. The columns and names
+/// are those of lichess-org/chess-openings (CC0); see `data/README.md` for
+/// where this copy comes from and how to regenerate it.
+const ECO_TSV: &str = include_str!("../data/eco.tsv");
+
+/// An opening from the dataset and the ply at which a game reached it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opening {
+    pub eco: &'static str,
+    pub name: &'static str,
+    pub ply: u64
+}
+impl Opening {
+    /// The family, e.g. `Sicilian Defense`.
+    pub fn opening(&self) -> &'static str {
+        self.name.split_once(':').map_or(self.name, |(opening, _)| opening)
+    }
+    /// The variation, e.g. `Najdorf Variation, English Attack`, if any.
+    pub fn variation(&self) -> Option<&'static str> {
+        self.name.split_once(':').map(|(_, variation)| variation.trim())
+    }
+}
+
+struct EcoTable {
+    positions: HashMap<String, Opening>,
+    max_ply: u64
+}
+/// The opening of a dataset line and its position, or why the line is
+/// malformed.
+fn parse_line(line: &'static str) -> Result<(Opening, Board), String> {
+    let (eco, name, moves) = match line.split('\t').collect::<Vec<_>>()[..] {
+        [eco, name, moves] => (eco, name, moves),
+        _ => return Err(String::from("expected 3 fields"))
+    };
+    let mut board = Board::new(Some(STARTING_FEN));
+    for san in moves.split_whitespace().filter(|token| !token.ends_with('.')) {
+        let m = board.parse_san(san).map_err(|e| e.to_string())?;
+        board.push(m);
+    }
+    Ok((Opening { eco, name, ply: board.ply() }, board))
+}
+lazy_static! {
+    // Malformed lines are skipped; the tests check that there are none.
+    static ref ECO_TABLE: EcoTable = {
+        let mut positions = HashMap::new();
+        let mut max_ply = 0;
+        for (opening, board) in ECO_TSV.lines().skip(1).filter_map(|line| parse_line(line).ok()) {
+            max_ply = max_ply.max(opening.ply);
+            positions.insert(board.epd(), opening);
+        }
+        EcoTable { positions, max_ply }
+    };
+}
+
+/// The opening of exactly this position, if it is in the dataset.
+pub fn lookup(board: &Board) -> Option<Opening> {
+    let mut opening = ECO_TABLE.positions.get(&board.epd())?.clone();
+    opening.ply = board.ply();
+    Some(opening)
+}
+/// Classifies a board by the deepest known position in its move stack.
+pub fn classify_board(board: &Board) -> Option<Opening> {
+    let mut board = board.copy(true);
+    loop {
+        if let Some(opening) = lookup(&board) {
+            return Some(opening);
+        }
+        if board.move_stack.is_empty() {
+            return None;
+        }
+        board.pop();
+    }
+}
+/// Classifies a game by the deepest known position on its mainline. Positions
+/// are matched rather than move orders, so transpositions are recognized.
+pub fn classify(game: &Game) -> Option<Opening> {
+    let mut board = game.setup_board();
+    let start = board.ply();
+    let mut best = lookup(&board);
+    for m in game.root().mainline_moves().flatten() {
+        // Games set up from a FEN may start at any ply, so only count the
+        // plies played from there.
+        if board.ply() - start >= ECO_TABLE.max_ply {
+            break;
+        }
+        board.push(m);
+        best = lookup(&board).or(best);
+    }
+    best
+}
+/// Sets the `ECO`, `Opening` and `Variation` headers a game does not have yet
+/// from its classification.
+pub fn fill_headers(game: &mut Game) -> Option<Opening> {
+    let opening = classify(game)?;
+    let headers = &mut game.headers;
+    if headers.get("ECO").is_none() {
+        headers.set("ECO", opening.eco);
+    }
+    if headers.get("Opening").is_none() {
+        headers.set("Opening", opening.opening());
+    }
+    if let (None, Some(variation)) = (headers.get("Variation"), opening.variation()) {
+        headers.set("Variation", variation);
+    }
+    Some(opening)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::{read_game, BufReader, GameBuilder};
+
+    #[test]
+    fn every_line_is_valid() {
+        let mut lines = ECO_TSV.lines();
+        assert_eq!(lines.next(), Some("eco\tname\tpgn"));
+        for (number, line) in lines.enumerate() {
+            let (opening, _) = parse_line(line).unwrap_or_else(|e| panic!("data/eco.tsv:{}: {}", number + 2, e));
+            let code = opening.eco.as_bytes();
+            assert!(code.len() == 3 && (b'A'..=b'E').contains(&code[0]) && code[1..].iter().all(u8::is_ascii_digit),
+                    "data/eco.tsv:{}: invalid code {}", number + 2, opening.eco);
+            assert!(!opening.name.is_empty(), "data/eco.tsv:{}: empty name", number + 2);
+        }
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        assert!(parse_line("C20\tKing's Pawn Game\t1. e4 e5").is_ok());
+        assert!(parse_line("C20\tKing's Pawn Game").is_err());
+        assert!(parse_line("C20\tKing's Pawn Game\t1. e4 e4").is_err());
+    }
+
+    #[test]
+    fn classify_transposition() {
+        let pgn = "1. Nf3 d5 2. d4 Nf6 3. c4 e6 4. Nc3 Be7 *\n\n";
+        let mut game = read_game(&mut BufReader::new(pgn.as_bytes()), &mut GameBuilder::new()).unwrap();
+        let opening = fill_headers(&mut game).unwrap();
+        assert_eq!(opening.eco.get(..1), Some("D"));
+        assert_eq!(game.headers.get("ECO"), Some(opening.eco));
+        assert_eq!(game.headers.get("Opening"), Some(opening.opening()));
+    }
+}
@@ -0,0 +1,218 @@
+//! Input encodings of positions for neural networks.
+
+use crate::init::{flip_vertical, Board, Color, BLACK, KING, PAWN, WHITE};
+
+/// Number of values returned by `piece_planes`.
+pub const PIECE_PLANES_SIZE: usize = 12 * 64;
+/// Number of values returned by `aux_planes`.
+pub const AUX_PLANES_SIZE: usize = 8 * 64;
+/// Number of HalfKP features per perspective.
+pub const HALFKP_SIZE: usize = 64 * 641;
+/// Number of HalfKA features per perspective.
+pub const HALFKA_SIZE: usize = 64 * 768;
+
+/// The colors in plane order and whether the board is flipped, so that with
+/// `orient` the side to move is always first and plays up the board.
+fn perspective(board: &Board, orient: bool) -> ([Color; 2], bool) {
+    match (orient, board.turn) {
+        (true, BLACK) => ([BLACK, WHITE], true),
+        _ => ([WHITE, BLACK], false)
+    }
+}
+fn set_plane(planes: &mut [f32], plane: usize, mut bb: u64, flip: bool) {
+    if flip {
+        bb = flip_vertical(bb);
+    }
+    while bb != 0 {
+        planes[plane * 64 + bb.trailing_zeros() as usize] = 1.0;
+        bb &= bb - 1;
+    }
+}
+
+/// 12 planes of 64 squares (a1 = 0), one per color and piece type: white
+/// pawn, knight, bishop, rook, queen, king, then black. With `orient` the
+/// planes of the side to move come first and, when Black is to move, the
+/// board is mirrored with `flip_vertical`.
+pub fn piece_planes(board: &Board, orient: bool) -> Vec<f32> {
+    let (colors, flip) = perspective(board, orient);
+    let mut planes = vec![0.0; PIECE_PLANES_SIZE];
+    for (side, color) in colors.iter().enumerate() {
+        for piece_type in PAWN..=KING {
+            set_plane(&mut planes, side * 6 + piece_type as usize - 1, board.baseboard.pieces_mask(piece_type, *color), flip);
+        }
+    }
+    planes
+}
+
+/// 8 planes of 64 squares, oriented like `piece_planes`:
+///
+/// 0-3. kingside and queenside castling rights of the first color, then the
+///      second, each plane all ones or all zeros
+/// 4.   the en passant square, if en passant is legal
+/// 5.   all ones if the position occurred before
+/// 6.   all ones if the position occurred at least twice before
+/// 7.   the halfmove clock divided by 100
+///
+/// Without `orient` the side to move is not encoded here; append a plane of
+/// your own if the network needs it.
+pub fn aux_planes(board: &Board, orient: bool) -> Vec<f32> {
+    let (colors, flip) = perspective(board, orient);
+    let mut planes = vec![0.0; AUX_PLANES_SIZE];
+    let mut fill = |plane: usize, value: f32| planes[plane * 64..(plane + 1) * 64].fill(value);
+    for (side, color) in colors.iter().enumerate() {
+        fill(side * 2, board.has_kingside_castling_rights(*color) as u8 as f32);
+        fill(side * 2 + 1, board.has_queenside_castling_rights(*color) as u8 as f32);
+    }
+    let mut copy = board.copy(true);
+    fill(5, copy.is_repetition(2) as u8 as f32);
+    fill(6, copy.is_repetition(3) as u8 as f32);
+    fill(7, board.halfmove_clock as f32 / 100.0);
+    if let (Some(ep_square), true) = (board.ep_square, board.has_legal_en_passant()) {
+        set_plane(&mut planes, 4, 1 << ep_square, flip);
+    }
+    planes
+}
+
+/// `piece_planes` followed by `aux_planes`, 20 planes in total.
+pub fn planes(board: &Board, orient: bool) -> Vec<f32> {
+    let mut planes = piece_planes(board, orient);
+    planes.extend(aux_planes(board, orient));
+    planes
+}
+
+/// Active HalfKP features of `board` from `perspective`, as used by the
+/// original NNUE networks: for every piece except the kings,
+/// `641 * king + 1 + 64 * (2 * (piece_type - 1) + theirs) + square`, where
+/// `king` is the perspective's king square. From Black's perspective squares
+/// are rotated by 180 degrees. Empty without a king of that color.
+pub fn halfkp(board: &Board, perspective: Color) -> Vec<u16> {
+    let orient = |square: u32| if perspective == WHITE { square } else { square ^ 63 };
+    let king = match board.baseboard.king(perspective) {
+        Some(king) => orient(king as u32),
+        None => return Vec::new()
+    };
+    let mut features = Vec::new();
+    for color in [WHITE, BLACK] {
+        for piece_type in PAWN..KING {
+            let mut bb = board.baseboard.pieces_mask(piece_type, color);
+            while bb != 0 {
+                let piece = 2 * (piece_type as u32 - 1) + (color != perspective) as u32;
+                features.push((641 * king + 1 + 64 * piece + orient(bb.trailing_zeros())) as u16);
+                bb &= bb - 1;
+            }
+        }
+    }
+    features.sort_unstable();
+    features
+}
+
+/// Active HalfKA features of `board` from `perspective`: for every piece,
+/// kings included, `768 * king + 64 * (6 * theirs + piece_type - 1) + square`.
+/// From Black's perspective squares are mirrored with `flip_vertical`. Empty
+/// without a king of that color.
+pub fn halfka(board: &Board, perspective: Color) -> Vec<u16> {
+    let orient = |square: u32| if perspective == WHITE { square } else { square ^ 56 };
+    let king = match board.baseboard.king(perspective) {
+        Some(king) => orient(king as u32),
+        None => return Vec::new()
+    };
+    let mut features = Vec::new();
+    for color in [WHITE, BLACK] {
+        for piece_type in PAWN..=KING {
+            let mut bb = board.baseboard.pieces_mask(piece_type, color);
+            while bb != 0 {
+                let piece = 6 * (color != perspective) as u32 + piece_type as u32 - 1;
+                features.push((768 * king + 64 * piece + orient(bb.trailing_zeros())) as u16);
+                bb &= bb - 1;
+            }
+        }
+    }
+    features.sort_unstable();
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::STARTING_FEN;
+
+    fn ones(planes: &[f32], plane: usize) -> Vec<usize> {
+        (0..64).filter(|square| planes[plane * 64 + square] == 1.0).collect()
+    }
+
+    #[test]
+    fn start_position_planes() {
+        let board = Board::new(Some(STARTING_FEN));
+        let planes = piece_planes(&board, false);
+        assert_eq!(ones(&planes, 0), (8..16).collect::<Vec<_>>());
+        assert_eq!(ones(&planes, 3), [0, 7]);
+        assert_eq!(ones(&planes, 4), [3]);
+        assert_eq!(ones(&planes, 5), [4]);
+        assert_eq!(ones(&planes, 6), (48..56).collect::<Vec<_>>());
+        assert_eq!(ones(&planes, 10), [59]);
+        assert_eq!(ones(&planes, 11), [60]);
+        assert_eq!(planes.iter().sum::<f32>(), 32.0);
+        assert_eq!(piece_planes(&board, true), planes);
+    }
+
+    #[test]
+    fn black_to_move_is_flipped_vertically() {
+        let mut board = Board::new(Some(STARTING_FEN));
+        let m = board.parse_san("e4").unwrap();
+        board.push(m);
+        let planes = piece_planes(&board, true);
+        assert_eq!(ones(&planes, 0), (8..16).collect::<Vec<_>>());
+        assert_eq!(ones(&planes, 4), [3]);
+        assert_eq!(ones(&planes, 5), [4]);
+        assert_eq!(ones(&planes, 6), [36, 48, 49, 50, 51, 53, 54, 55]);
+        assert_eq!(ones(&planes, 10), [59]);
+        assert_eq!(ones(&planes, 11), [60]);
+        assert_eq!(ones(&piece_planes(&board, false), 0), [8, 9, 10, 11, 13, 14, 15, 28]);
+    }
+
+    #[test]
+    fn castling_and_en_passant_planes() {
+        let board = Board::new(Some("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 3"));
+        let planes = aux_planes(&board, false);
+        assert_eq!(ones(&planes, 0).len(), 64);
+        assert!(ones(&planes, 1).is_empty());
+        assert!(ones(&planes, 2).is_empty());
+        assert_eq!(ones(&planes, 3).len(), 64);
+        assert_eq!(ones(&planes, 4), [20]);
+        let planes = aux_planes(&board, true);
+        assert!(ones(&planes, 0).is_empty());
+        assert_eq!(ones(&planes, 1).len(), 64);
+        assert_eq!(ones(&planes, 2).len(), 64);
+        assert!(ones(&planes, 3).is_empty());
+        assert_eq!(ones(&planes, 4), [44]);
+
+        // No black pawn can capture on e3, so the square is not encoded.
+        let board = Board::new(Some("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 50 1"));
+        let planes = aux_planes(&board, false);
+        assert!(ones(&planes, 4).is_empty());
+        assert!(planes[7 * 64..].iter().all(|value| *value == 0.5));
+    }
+
+    #[test]
+    fn halfkp_features() {
+        let board = Board::new(Some("1n2k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        // White king on e1 (4): our pawn on e2 (12) and their knight on b8 (57).
+        assert_eq!(halfkp(&board, WHITE), [641 * 4 + 1 + 12, 641 * 4 + 1 + 64 * 3 + 57]);
+        // Rotated, the black king is on d1 (3), their pawn on d7 (51) and our knight on g1 (6).
+        assert_eq!(halfkp(&board, BLACK), [641 * 3 + 1 + 64 + 51, 641 * 3 + 1 + 64 * 2 + 6]);
+        let board = Board::new(Some("8/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        assert!(halfkp(&board, BLACK).is_empty());
+    }
+
+    #[test]
+    fn halfka_features() {
+        let board = Board::new(Some("1n2k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        // Pawn e2, king e1, then their knight b8 and king e8.
+        assert_eq!(halfka(&board, WHITE), [768 * 4 + 12, 768 * 4 + 64 * 5 + 4, 768 * 4 + 64 * 7 + 57, 768 * 4 + 64 * 11 + 60]);
+        // Mirrored: knight b1, king e1, then their pawn e7 and king e8.
+        assert_eq!(halfka(&board, BLACK), [768 * 4 + 64 + 1, 768 * 4 + 64 * 5 + 4, 768 * 4 + 64 * 6 + 52, 768 * 4 + 64 * 11 + 60]);
+        let board = Board::new(Some(STARTING_FEN));
+        assert_eq!(halfka(&board, WHITE).len(), 32);
+        assert_eq!(halfka(&board, WHITE), halfka(&board, BLACK));
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::init::{Board, Move};
+use crate::parallel;
+use crate::pgn::{self, Games, ParsingError, SkipType, Visitor};
+
+const MAGIC: &[u8; 8] = b"RCEXPL01";
+/// hash u64, move u16, games/white/draws/black u32, elo sum u64, elo count u32, date u32.
+const RECORD_SIZE: u64 = 42;
+
+/// Statistics of one move played from one position.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Stats {
+    games: u32,
+    white: u32,
+    draws: u32,
+    black: u32,
+    elo_sum: u64,
+    elo_count: u32,
+    last_date: u32
+}
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.games += other.games;
+        self.white += other.white;
+        self.draws += other.draws;
+        self.black += other.black;
+        self.elo_sum += other.elo_sum;
+        self.elo_count += other.elo_count;
+        self.last_date = self.last_date.max(other.last_date);
+    }
+}
+
+fn encode_move(m: Move) -> u16 {
+    m.from_square as u16 | (m.to_square as u16) << 6 | (m.promotion.unwrap_or(0) as u16) << 12
+}
+fn decode_move(m: u16) -> Move {
+    let promotion = (m >> 12) as u8;
+    Move { from_square: (m & 63) as u8, to_square: (m >> 6 & 63) as u8, promotion: (promotion != 0).then_some(promotion) }
+}
+/// `YYYY.MM.DD` as `YYYYMMDD`, unknown parts as 0.
+fn pack_date(date: &str) -> u32 {
+    let mut parts = date.split('.').map(|part| part.parse::<u32>().unwrap_or(0));
+    let (year, month, day) = (parts.next().unwrap_or(0), parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    year * 10000 + month.min(99) * 100 + day.min(99)
+}
+fn unpack_date(date: u32) -> Option<String> {
+    if date == 0 {
+        return None;
+    }
+    let part = |value: u32, width: usize| match value {
+        0 => "?".repeat(width),
+        value => format!("{:0width$}", value, width = width)
+    };
+    Some(format!("{}.{}.{}", part(date / 10000, 4), part(date / 100 % 100, 2), part(date % 100, 2)))
+}
+
+/// What the explorer needs from one game: its outcome and ratings, and the
+/// mainline moves up to the ply limit keyed by the hash of the position
+/// they were played from.
+pub struct GameRecord {
+    stats: Stats,
+    moves: Vec<(u64, u16)>
+}
+/// Visitor turning a game into a `GameRecord`. Variations are skipped, and
+/// a move that a game plays again from a repeated position counts once.
+pub struct ExplorerVisitor {
+    max_ply: u64,
+    ply: u64,
+    seen: HashSet<(u64, u16)>,
+    white_elo: Option<u32>,
+    black_elo: Option<u32>,
+    record: GameRecord
+}
+impl ExplorerVisitor {
+    pub fn new(max_ply: u64) -> ExplorerVisitor {
+        ExplorerVisitor { max_ply, ply: 0, seen: HashSet::new(), white_elo: None, black_elo: None,
+                          record: GameRecord { stats: Stats::default(), moves: Vec::new() } }
+    }
+}
+impl Visitor for ExplorerVisitor {
+    type Output = GameRecord;
+    fn begin_game(&mut self) -> Option<SkipType> {
+        self.ply = 0;
+        self.seen.clear();
+        self.white_elo = None;
+        self.black_elo = None;
+        self.record = GameRecord { stats: Stats { games: 1, ..Stats::default() }, moves: Vec::new() };
+        None
+    }
+    fn visit_header(&mut self, tagname: &str, tagvalue: &str) {
+        let stats = &mut self.record.stats;
+        match tagname {
+            "Result" => match tagvalue {
+                "1-0" => stats.white = 1,
+                "0-1" => stats.black = 1,
+                "1/2-1/2" => stats.draws = 1,
+                _ => {}
+            },
+            "WhiteElo" => self.white_elo = tagvalue.parse().ok(),
+            "BlackElo" => self.black_elo = tagvalue.parse().ok(),
+            "Date" => stats.last_date = pack_date(tagvalue),
+            _ => {}
+        }
+    }
+    fn end_headers(&mut self) -> Option<SkipType> {
+        let elos: Vec<u32> = [self.white_elo, self.black_elo].into_iter().flatten().collect();
+        if !elos.is_empty() {
+            self.record.stats.elo_sum = (elos.iter().sum::<u32>() / elos.len() as u32) as u64;
+            self.record.stats.elo_count = 1;
+        }
+        None
+    }
+    fn visit_move(&mut self, board: &Board, m: Move) {
+        let key = (board.zobrist_hash(), encode_move(m));
+        if self.ply < self.max_ply && self.seen.insert(key) {
+            self.record.moves.push(key);
+        }
+        self.ply += 1;
+    }
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        Some(SkipType::SKIP)
+    }
+    fn result(&mut self) -> GameRecord {
+        std::mem::replace(&mut self.record, GameRecord { stats: Stats::default(), moves: Vec::new() })
+    }
+}
+
+/// Aggregates games in memory before they are written out with `write`.
+pub struct ExplorerBuilder {
+    max_ply: u64,
+    stats: HashMap<(u64, u16), Stats>
+}
+impl ExplorerBuilder {
+    /// Only the first `max_ply` moves of each game are recorded.
+    pub fn new(max_ply: u64) -> ExplorerBuilder {
+        ExplorerBuilder { max_ply, stats: HashMap::new() }
+    }
+    pub fn add_game(&mut self, record: &GameRecord) {
+        for key in &record.moves {
+            self.stats.entry(*key).or_default().add(&record.stats);
+        }
+    }
+    pub fn merge(mut self, other: ExplorerBuilder) -> ExplorerBuilder {
+        for (key, stats) in other.stats {
+            self.stats.entry(key).or_default().add(&stats);
+        }
+        self
+    }
+    /// Streams every game of `handle` into the builder. Games that fail to
+    /// parse are skipped.
+    pub fn add_pgn<R: BufRead>(&mut self, handle: pgn::BufReader<R>) {
+        for record in Games::with_visitor(handle, ExplorerVisitor::new(self.max_ply)).flatten() {
+            self.add_game(&record);
+        }
+    }
+    /// Builds the statistics of a PGN file on `threads` threads.
+    pub fn from_pgn_parallel(path: impl AsRef<Path>, threads: usize, max_ply: u64) -> io::Result<ExplorerBuilder> {
+        parallel::reduce(path, threads, || ExplorerVisitor::new(max_ply), || ExplorerBuilder::new(max_ply),
+            |mut builder, record: Result<GameRecord, ParsingError>| {
+                if let Ok(record) = record {
+                    builder.add_game(&record);
+                }
+                builder
+            },
+            ExplorerBuilder::merge)
+    }
+    /// Writes fixed-size records sorted by position hash, so that `Explorer`
+    /// can binary search the file without loading it.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut records: Vec<(&(u64, u16), &Stats)> = self.stats.iter().collect();
+        records.sort_unstable_by_key(|(key, _)| **key);
+
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(records.len() as u64).to_le_bytes())?;
+        for ((hash, m), stats) in records {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&m.to_le_bytes())?;
+            for value in [stats.games, stats.white, stats.draws, stats.black] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.write_all(&stats.elo_sum.to_le_bytes())?;
+            writer.write_all(&stats.elo_count.to_le_bytes())?;
+            writer.write_all(&stats.last_date.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+}
+
+/// Statistics of a continuation, as returned by `Explorer::query`. The
+/// percentages are of the games with a decisive or drawn result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveStats {
+    pub m: Move,
+    pub san: String,
+    pub games: u32,
+    pub white_percent: f64,
+    pub draw_percent: f64,
+    pub black_percent: f64,
+    pub average_elo: Option<u32>,
+    pub last_played: Option<String>
+}
+
+/// Read-only explorer over a file written by `ExplorerBuilder::write`.
+pub struct Explorer {
+    file: Mutex<File>,
+    len: u64
+}
+impl Explorer {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Explorer> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an explorer file"));
+        }
+        let len = u64::from_le_bytes(header[8..].try_into().unwrap());
+        Ok(Explorer { file: Mutex::new(file), len })
+    }
+    fn record(&self, index: u64) -> io::Result<(u64, u16, Stats)> {
+        let mut buffer = [0u8; RECORD_SIZE as usize];
+        // Seeking and reading must not interleave with another query.
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(16 + index * RECORD_SIZE))?;
+        file.read_exact(&mut buffer)?;
+        drop(file);
+        let u32_at = |at: usize| u32::from_le_bytes(buffer[at..at + 4].try_into().unwrap());
+        let stats = Stats {
+            games: u32_at(10),
+            white: u32_at(14),
+            draws: u32_at(18),
+            black: u32_at(22),
+            elo_sum: u64::from_le_bytes(buffer[26..34].try_into().unwrap()),
+            elo_count: u32_at(34),
+            last_date: u32_at(38)
+        };
+        Ok((u64::from_le_bytes(buffer[..8].try_into().unwrap()), u16::from_le_bytes(buffer[8..10].try_into().unwrap()), stats))
+    }
+    /// Continuations played from `board`, most popular first. Records of
+    /// moves that are not legal in `board`, which only a hash collision
+    /// gives, are left out.
+    pub fn query(&self, board: &Board) -> io::Result<Vec<MoveStats>> {
+        let hash = board.zobrist_hash();
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.record(middle)?.0 < hash {
+                low = middle + 1;
+            }
+            else {
+                high = middle;
+            }
+        }
+
+        let mut moves = Vec::new();
+        for index in low..self.len {
+            let (record_hash, m, stats) = self.record(index)?;
+            if record_hash != hash {
+                break;
+            }
+            let m = decode_move(m);
+            if !board.is_legal(m) {
+                continue;
+            }
+            let decided = (stats.white + stats.draws + stats.black).max(1) as f64;
+            moves.push(MoveStats {
+                m,
+                san: board.copy(false).san(m),
+                games: stats.games,
+                white_percent: 100.0 * stats.white as f64 / decided,
+                draw_percent: 100.0 * stats.draws as f64 / decided,
+                black_percent: 100.0 * stats.black as f64 / decided,
+                average_elo: (stats.elo_count > 0).then(|| (stats.elo_sum / stats.elo_count as u64) as u32),
+                last_played: unpack_date(stats.last_date)
+            });
+        }
+        moves.sort_by_key(|stats| std::cmp::Reverse(stats.games));
+        Ok(moves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::STARTING_FEN;
+
+    fn explorer(pgn: &str, max_ply: u64) -> (Explorer, std::path::PathBuf) {
+        let mut builder = ExplorerBuilder::new(max_ply);
+        builder.add_pgn(pgn::BufReader::new(pgn.as_bytes()));
+        let path = std::env::temp_dir().join(format!("explorer-{}-{}", std::process::id(), pgn.len()));
+        builder.write(&path).unwrap();
+        (Explorer::open(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn query_counts_games() {
+        let pgn = "[Result \"1-0\"]\n[WhiteElo \"2000\"]\n[BlackElo \"1800\"]\n[Date \"2020.05.??\"]\n\n1. e4 e5 2. Nf3 1-0\n\n\
+                   [Result \"1/2-1/2\"]\n\n1. e4 c5 (1... e5) 1/2-1/2\n\n\
+                   [Result \"0-1\"]\n\n1. d4 d5 0-1\n\n";
+        let (explorer, path) = explorer(pgn, 10);
+        let moves = explorer.query(&Board::new(Some(STARTING_FEN))).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(moves.iter().map(|stats| (stats.san.as_str(), stats.games)).collect::<Vec<_>>(), [("e4", 2), ("d4", 1)]);
+        assert_eq!((moves[0].white_percent, moves[0].draw_percent, moves[0].black_percent), (50.0, 50.0, 0.0));
+        assert_eq!(moves[0].average_elo, Some(1900));
+        assert_eq!(moves[0].last_played.as_deref(), Some("2020.05.??"));
+    }
+
+    #[test]
+    fn repeated_positions_count_once_per_game() {
+        let pgn = "[Result \"1/2-1/2\"]\n\n1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 1/2-1/2\n\n";
+        let (explorer, path) = explorer(pgn, 10);
+        let moves = explorer.query(&Board::new(Some(STARTING_FEN))).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(moves.iter().map(|stats| (stats.san.as_str(), stats.games)).collect::<Vec<_>>(), [("Nf3", 1)]);
+    }
+
+    #[test]
+    fn illegal_records_are_skipped() {
+        let mut builder = ExplorerBuilder::new(10);
+        let hash = Board::new(Some(STARTING_FEN)).zobrist_hash();
+        builder.add_game(&GameRecord { stats: Stats { games: 1, ..Stats::default() },
+                                       moves: vec![(hash, encode_move(Move::from_uci("e2e5"))), (hash, encode_move(Move::from_uci("e2e4")))] });
+        let path = std::env::temp_dir().join(format!("explorer-{}-illegal", std::process::id()));
+        builder.write(&path).unwrap();
+        let moves = Explorer::open(&path).unwrap().query(&Board::new(Some(STARTING_FEN))).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(moves.iter().map(|stats| stats.san.as_str()).collect::<Vec<_>>(), ["e4"]);
+    }
+}
@@ -0,0 +1,188 @@
+use std::io::{self, prelude::*};
+
+use crate::output::{csv_field, json_number, json_string};
+use crate::pgn::{self, Game, Games, Node, ParsingError, Score};
+
+fn json_score(score: Score) -> String {
+    match score {
+        Score::Cp(cp) => format!("{{\"cp\":{}}}", cp),
+        Score::Mate(mate) => format!("{{\"mate\":{}}}", mate)
+    }
+}
+fn json_nags(node: &Node) -> String {
+    let mut nags: Vec<&u64> = node.nags().iter().collect();
+    nags.sort();
+    format!("[{}]", nags.iter().map(|nag| nag.to_string()).collect::<Vec<String>>().join(","))
+}
+/// A move and the variations that replace it, in the layout of `game_to_json`.
+fn json_move(node: Node) -> String {
+    let clock = node.clock().map_or(String::from("null"), |clock| json_number(clock.as_secs_f64()));
+    let eval = node.eval().map_or(String::from("null"), |eval| {
+        let depth = eval.depth.map_or(String::from("null"), |depth| depth.to_string());
+        format!("{{\"white\":{},\"depth\":{}}}", json_score(eval.white()), depth)
+    });
+    let alternatives: Vec<String> = node.parent().unwrap().variations().skip(1)
+        .filter(|_| node.is_main_variation())
+        .map(json_line)
+        .collect();
+    format!("{{\"ply\":{},\"san\":{},\"uci\":{},\"fen\":{},\"starting_comment\":{},\"comment\":{},\"nags\":{},\"clock\":{},\"eval\":{},\"variations\":[{}]}}",
+        node.ply(), json_string(&node.san().unwrap()), json_string(&node.uci().unwrap()), json_string(&node.board().fen()),
+        json_string(node.starting_comment()), json_string(node.comment()), json_nags(&node), clock, eval, alternatives.join(","))
+}
+/// `start` and the main continuation below it, as a JSON array of moves.
+fn json_line(start: Node) -> String {
+    let moves: Vec<String> = std::iter::once(start).chain(start.mainline()).map(json_move).collect();
+    format!("[{}]", moves.join(","))
+}
+
+/// One game as a JSON object:
+///
+/// `{"headers": {...}, "comment": "...", "mainline": [move, ...]}`
+///
+/// where every move is `{"ply", "san", "uci", "fen", "starting_comment",
+/// "comment", "nags", "clock", "eval", "variations"}`. `fen` is the position
+/// after the move, `clock` is in seconds, `eval` is from White's point of
+/// view as `{"white": {"cp": 31} | {"mate": -2}, "depth": 20}`, and
+/// `variations` holds the alternatives to the move, each a list of moves in
+/// the same layout.
+pub fn game_to_json(game: &Game) -> String {
+    let headers: Vec<String> = game.headers.items().iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+        .collect();
+    let mainline = match game.root().next() {
+        Some(first) => json_line(first),
+        None => String::from("[]")
+    };
+    format!("{{\"headers\":{{{}}},\"comment\":{},\"mainline\":{}}}", headers.join(","), json_string(game.root().comment()), mainline)
+}
+
+/// Columns of `game_csv_row`: these headers, then the mainline in SAN and UCI.
+pub const CSV_GAME_HEADERS: [&str; 12] = ["Event", "Site", "Date", "Round", "White", "Black", "Result", "WhiteElo", "BlackElo", "ECO", "TimeControl", "Termination"];
+
+pub fn game_csv_header() -> String {
+    let mut columns: Vec<String> = CSV_GAME_HEADERS.iter().map(|column| column.to_string()).collect();
+    columns.extend([String::from("plies"), String::from("san"), String::from("uci")]);
+    columns.join(",")
+}
+/// The game as one CSV row, with the mainline as space-separated moves.
+pub fn game_csv_row(game: &Game) -> String {
+    let mut fields: Vec<String> = CSV_GAME_HEADERS.iter().map(|key| csv_field(game.headers.get(key).unwrap_or(""))).collect();
+    let mainline: Vec<Node> = game.root().mainline().collect();
+    let san: Vec<String> = mainline.iter().map(|node| node.san().unwrap()).collect();
+    let uci: Vec<String> = mainline.iter().map(|node| node.uci().unwrap()).collect();
+    fields.push(mainline.len().to_string());
+    fields.push(csv_field(&san.join(" ")));
+    fields.push(csv_field(&uci.join(" ")));
+    fields.join(",")
+}
+
+pub fn move_csv_header() -> String {
+    String::from("game,ply,san,uci,fen,comment,nags,clock,eval_cp,eval_mate")
+}
+/// One CSV row per mainline move of game number `index`. Evals are from
+/// White's point of view.
+pub fn move_csv_rows(game: &Game, index: u64) -> Vec<String> {
+    game.root().mainline().map(|node| {
+        let nags: Vec<String> = {
+            let mut nags: Vec<&u64> = node.nags().iter().collect();
+            nags.sort();
+            nags.iter().map(|nag| nag.to_string()).collect()
+        };
+        let (cp, mate) = match node.eval().map(|eval| eval.white()) {
+            Some(Score::Cp(cp)) => (cp.to_string(), String::new()),
+            Some(Score::Mate(mate)) => (String::new(), mate.to_string()),
+            None => (String::new(), String::new())
+        };
+        let clock = node.clock().map_or(String::new(), |clock| clock.as_secs_f64().to_string());
+        format!("{},{},{},{},{},{},{},{},{},{}", index, node.ply(), csv_field(&node.san().unwrap()), node.uci().unwrap(),
+            node.board().fen(), csv_field(node.comment()), nags.join(" "), clock, cp, mate)
+    }).collect()
+}
+
+/// How games that fail to parse are handled by the writers below.
+fn report(error: ParsingError) -> io::Result<()> {
+    match error {
+        ParsingError::ReadLineError(e) => Err(e),
+        error => {
+            eprintln!("skipping game: {}", error);
+            Ok(())
+        }
+    }
+}
+/// Writes every game of `handle` as JSON Lines, one `game_to_json` object per line.
+pub fn write_json<R: BufRead, W: Write>(handle: pgn::BufReader<R>, writer: &mut W) -> io::Result<()> {
+    for game in Games::new(handle) {
+        match game {
+            Ok(game) => writeln!(writer, "{}", game_to_json(&game))?,
+            Err(error) => report(error)?
+        }
+    }
+    Ok(())
+}
+/// Writes every game of `handle` as CSV, one row per game or per move.
+pub fn write_csv<R: BufRead, W: Write>(handle: pgn::BufReader<R>, writer: &mut W, per_move: bool) -> io::Result<()> {
+    writeln!(writer, "{}", if per_move { move_csv_header() } else { game_csv_header() })?;
+    for (index, game) in Games::new(handle).enumerate() {
+        match game {
+            Ok(game) if per_move => {
+                for row in move_csv_rows(&game, index as u64) {
+                    writeln!(writer, "{}", row)?;
+                }
+            },
+            Ok(game) => writeln!(writer, "{}", game_csv_row(&game))?,
+            Err(error) => report(error)?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PGN: &str = "[Event \"Open, A\"]\n[Site \"?\"]\n[Date \"2024.01.02\"]\n[Round \"1\"]\n[White \"A\"]\n[Black \"B\"]\n[Result \"1-0\"]\n[WhiteElo \"2500\"]\n\n\
+        {Start} 1. e4 {Best \"move\" [%clk 0:01:00.5] [%eval 0.31,20]} $1 (1. d4 d5) 1... e5 2. Qh5 {[%eval #2]} 1-0\n\n";
+
+    fn export(f: impl Fn(pgn::BufReader<&[u8]>, &mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        f(pgn::BufReader::new(PGN.as_bytes()), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn exports_json() {
+        let e4 = concat!(
+            r#"{"ply":1,"san":"e4","uci":"e2e4","fen":"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1","starting_comment":"","#,
+            r#""comment":"Best \"move\" [%clk 0:01:00.5] [%eval 0.31,20]","nags":[1],"clock":60.5,"eval":{"white":{"cp":31},"depth":20},"variations":[["#,
+            r#"{"ply":1,"san":"d4","uci":"d2d4","fen":"rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1","starting_comment":"","comment":"","nags":[],"clock":null,"eval":null,"variations":[]},"#,
+            r#"{"ply":2,"san":"d5","uci":"d7d5","fen":"rnbqkbnr/ppp1pppp/8/3p4/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 0 2","starting_comment":"","comment":"","nags":[],"clock":null,"eval":null,"variations":[]}]]}"#
+        );
+        let e5 = r#"{"ply":2,"san":"e5","uci":"e7e5","fen":"rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2","starting_comment":"","comment":"","nags":[],"clock":null,"eval":null,"variations":[]}"#;
+        let qh5 = r#"{"ply":3,"san":"Qh5","uci":"d1h5","fen":"rnbqkbnr/pppp1ppp/8/4p2Q/4P3/8/PPPP1PPP/RNB1KBNR b KQkq - 1 2","starting_comment":"","comment":"[%eval #2]","nags":[],"clock":null,"eval":{"white":{"mate":2},"depth":null},"variations":[]}"#;
+        let headers = r#"{"Event":"Open, A","Site":"?","Date":"2024.01.02","Round":"1","White":"A","Black":"B","Result":"1-0","WhiteElo":"2500"}"#;
+        let expected = format!("{{\"headers\":{},\"comment\":\"Start\",\"mainline\":[{},{},{}]}}\n", headers, e4, e5, qh5);
+        assert_eq!(export(|handle, out| write_json(handle, out)), expected);
+
+        let mut game = Game::new();
+        game.root_mut().set_comment("line\nbreak");
+        assert!(game_to_json(&game).ends_with(r#""comment":"line\nbreak","mainline":[]}"#));
+    }
+
+    #[test]
+    fn exports_csv_per_game() {
+        assert_eq!(export(|handle, out| write_csv(handle, out, false)), concat!(
+            "Event,Site,Date,Round,White,Black,Result,WhiteElo,BlackElo,ECO,TimeControl,Termination,plies,san,uci\n",
+            "\"Open, A\",?,2024.01.02,1,A,B,1-0,2500,,,,,3,e4 e5 Qh5,e2e4 e7e5 d1h5\n"
+        ));
+    }
+
+    #[test]
+    fn exports_csv_per_move() {
+        assert_eq!(export(|handle, out| write_csv(handle, out, true)), concat!(
+            "game,ply,san,uci,fen,comment,nags,clock,eval_cp,eval_mate\n",
+            "0,1,e4,e2e4,rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1,\"Best \"\"move\"\" [%clk 0:01:00.5] [%eval 0.31,20]\",1,60.5,31,\n",
+            "0,2,e5,e7e5,rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2,,,,,\n",
+            "0,3,Qh5,d1h5,rnbqkbnr/pppp1ppp/8/4p2Q/4P3/8/PPPP1PPP/RNB1KBNR b KQkq - 1 2,[%eval #2],,,,2\n"
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::pgn::{self, GameHeaders, Headers, ParsingError, Visitor};
+
+const MAGIC: &str = "RustChess index 2";
+/// Bytes before the end of the indexed part that the checksum covers.
+const CHECKSUM_BYTES: u64 = 4096;
+
+/// Offset and the commonly displayed headers of one game in a PGN file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub offset: u64,
+    pub white: String,
+    pub black: String,
+    pub date: String,
+    pub result: String,
+    pub eco: String,
+    pub white_elo: Option<u16>,
+    pub black_elo: Option<u16>
+}
+impl IndexEntry {
+    fn from_headers(game: GameHeaders) -> IndexEntry {
+        let headers = &game.headers;
+        let get = |key: &str| headers.get(key).unwrap_or("").to_string();
+        IndexEntry {
+            offset: game.offset,
+            white: get("White"),
+            black: get("Black"),
+            date: get("Date"),
+            result: get("Result"),
+            eco: get("ECO"),
+            white_elo: headers.get("WhiteElo").and_then(|elo| elo.parse().ok()),
+            black_elo: headers.get("BlackElo").and_then(|elo| elo.parse().ok())
+        }
+    }
+    fn to_line(&self) -> String {
+        let field = |value: &str| value.replace(['\t', '\n', '\r'], " ");
+        let elo = |elo: Option<u16>| elo.map_or(String::new(), |elo| elo.to_string());
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.offset, field(&self.white), field(&self.black), field(&self.date),
+            field(&self.result), field(&self.eco), elo(self.white_elo), elo(self.black_elo))
+    }
+    fn from_line(line: &str) -> Option<IndexEntry> {
+        let fields: Vec<&str> = line.trim_end_matches(['\n', '\r']).split('\t').collect();
+        if fields.len() != 8 {
+            return None;
+        }
+        Some(IndexEntry {
+            offset: fields[0].parse().ok()?,
+            white: fields[1].to_string(),
+            black: fields[2].to_string(),
+            date: fields[3].to_string(),
+            result: fields[4].to_string(),
+            eco: fields[5].to_string(),
+            white_elo: fields[6].parse().ok(),
+            black_elo: fields[7].parse().ok()
+        })
+    }
+}
+
+/// Index of a PGN file, stored next to it as `<file>.idx`.
+///
+/// The index file is a text file: a header line with the number of PGN bytes
+/// covered and a checksum of the last of them, then one tab-separated line
+/// per game. The header has a fixed width, so games appended to the PGN are
+/// indexed by appending lines and rewriting the header in place.
+pub struct Index {
+    pgn: PathBuf,
+    indexed: u64,
+    checksum: u64,
+    entries: Vec<IndexEntry>,
+    errors: u64
+}
+impl Index {
+    pub fn index_path(pgn: &Path) -> PathBuf {
+        let mut path = pgn.as_os_str().to_owned();
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+    /// Loads the index of `pgn`, building it or indexing newly appended
+    /// games first if needed.
+    pub fn open(pgn: impl AsRef<Path>) -> io::Result<Index> {
+        let pgn = pgn.as_ref().to_path_buf();
+        let mut index = Index::load(&pgn)?.unwrap_or(Index { pgn, indexed: 0, checksum: 0, entries: Vec::new(), errors: 0 });
+        index.update()?;
+        Ok(index)
+    }
+    fn load(pgn: &Path) -> io::Result<Option<Index>> {
+        let file = match File::open(Index::index_path(pgn)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+        let mut lines = io::BufReader::new(file).lines();
+        let header = lines.next().transpose()?;
+        let header = header.as_deref().and_then(|line| line.strip_prefix(MAGIC)).and_then(|line| line.trim().split_once(' '));
+        let (indexed, checksum) = match header.map(|(indexed, checksum)| (indexed.parse(), u64::from_str_radix(checksum, 16))) {
+            Some((Ok(indexed), Ok(checksum))) => (indexed, checksum),
+            _ => return Ok(None)
+        };
+        let mut entries = Vec::new();
+        for line in lines {
+            match IndexEntry::from_line(&line?) {
+                Some(entry) => entries.push(entry),
+                None => return Ok(None)
+            }
+        }
+        Ok(Some(Index { pgn: pgn.to_path_buf(), indexed, checksum, entries, errors: 0 }))
+    }
+    /// FNV-1a over the last `CHECKSUM_BYTES` bytes of the first `len` bytes of
+    /// the PGN.
+    fn checksum(file: &mut File, len: u64) -> io::Result<u64> {
+        let start = len.saturating_sub(CHECKSUM_BYTES);
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(start))?;
+        file.take(len - start).read_to_end(&mut bytes)?;
+        Ok(bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)))
+    }
+    /// Indexes games appended to the PGN since the index was written. A PGN
+    /// that got shorter, or whose indexed part changed, was rewritten, so it
+    /// is indexed from scratch.
+    pub fn update(&mut self) -> io::Result<()> {
+        let mut file = File::open(&self.pgn)?;
+        let len = file.metadata()?.len();
+        let unchanged = len >= self.indexed && Index::checksum(&mut file, self.indexed)? == self.checksum;
+        if len == self.indexed && unchanged && Index::index_path(&self.pgn).exists() {
+            return Ok(());
+        }
+        let rebuild = !unchanged || !Index::index_path(&self.pgn).exists();
+        if rebuild {
+            self.indexed = 0;
+            self.entries.clear();
+        }
+
+        file.seek(SeekFrom::Start(self.indexed))?;
+        let mut reader = pgn::BufReader::at_offset(io::BufReader::new((&mut file).take(len - self.indexed)), self.indexed);
+        let old = self.entries.len();
+        self.errors = 0;
+        loop {
+            match pgn::read_headers(&mut reader) {
+                Ok(game) => self.entries.push(IndexEntry::from_headers(game)),
+                Err(ParsingError::Eof) => break,
+                Err(ParsingError::ReadLineError(e)) => return Err(e),
+                Err(_) => self.errors += 1
+            }
+        }
+        self.indexed = len;
+        self.checksum = Index::checksum(&mut file, len)?;
+        self.save(if rebuild { 0 } else { old })
+    }
+    /// Games that failed to parse when the index was last updated. They
+    /// have no entry.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+    /// Writes the header and the entries from `from` on.
+    fn save(&self, from: usize) -> io::Result<()> {
+        let path = Index::index_path(&self.pgn);
+        let mut file = match from {
+            0 => File::create(&path)?,
+            _ => OpenOptions::new().append(true).open(&path)?
+        };
+        let mut writer = io::BufWriter::new(&mut file);
+        if from == 0 {
+            writeln!(writer, "{} {:020} {:016x}", MAGIC, self.indexed, self.checksum)?;
+        }
+        for entry in &self.entries[from..] {
+            writer.write_all(entry.to_line().as_bytes())?;
+        }
+        writer.flush()?;
+        drop(writer);
+        if from != 0 {
+            let mut file = OpenOptions::new().write(true).open(&path)?;
+            writeln!(file, "{} {:020} {:016x}", MAGIC, self.indexed, self.checksum)?;
+        }
+        Ok(())
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn get(&self, n: usize) -> Option<&IndexEntry> {
+        self.entries.get(n)
+    }
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+    /// Opens the indexed PGN for use with `read_game`.
+    pub fn open_pgn(&self) -> io::Result<pgn::BufReader> {
+        pgn::BufReader::open(&self.pgn)
+    }
+    /// Reads game `n` with `visitor`, seeking straight to its offset.
+    pub fn read_game<V: Visitor, R: BufRead + Seek>(&self, handle: &mut pgn::BufReader<R>, n: usize, visitor: &mut V) -> Result<V::Output, ParsingError> {
+        let entry = self.entries.get(n).ok_or(ParsingError::Eof)?;
+        handle.seek(entry.offset)?;
+        pgn::read_game(handle, visitor)
+    }
+    /// Headers of game `n` as stored in the index.
+    pub fn headers(&self, n: usize) -> Option<Headers> {
+        let entry = self.entries.get(n)?;
+        let mut headers = Headers::new(None);
+        for (key, value) in [("White", &entry.white), ("Black", &entry.black), ("Date", &entry.date), ("Result", &entry.result), ("ECO", &entry.eco)] {
+            if !value.is_empty() {
+                headers.set(key, value);
+            }
+        }
+        if let Some(elo) = entry.white_elo {
+            headers.set("WhiteElo", &elo.to_string());
+        }
+        if let Some(elo) = entry.black_elo {
+            headers.set("BlackElo", &elo.to_string());
+        }
+        Some(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME: &str = "[White \"Carlsen\"]\n[Black \"Caruana\"]\n[Result \"1-0\"]\n[WhiteElo \"2850\"]\n\n1. e4 e5 1-0\n\n";
+
+    fn pgn_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("index-{}-{}.pgn", std::process::id(), name))
+    }
+    fn cleanup(pgn: &Path) {
+        std::fs::remove_file(pgn).unwrap();
+        std::fs::remove_file(Index::index_path(pgn)).unwrap();
+    }
+
+    #[test]
+    fn build_and_reload() {
+        let pgn = pgn_path("build");
+        std::fs::write(&pgn, format!("{}{}", GAME, GAME.replace("Carlsen", "Nakamura"))).unwrap();
+        let index = Index::open(&pgn).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(0).map(|entry| (entry.offset, entry.white.as_str(), entry.white_elo)), Some((0, "Carlsen", Some(2850))));
+        assert_eq!(index.get(1).map(|entry| (entry.offset, entry.white.as_str())), Some((GAME.len() as u64, "Nakamura")));
+        let reloaded = Index::open(&pgn).unwrap();
+        assert_eq!(reloaded.entries(), index.entries());
+        let game = reloaded.read_game(&mut reloaded.open_pgn().unwrap(), 1, &mut pgn::GameBuilder::new()).unwrap();
+        assert_eq!(game.headers.get("White"), Some("Nakamura"));
+        cleanup(&pgn);
+    }
+
+    #[test]
+    fn appended_games_are_indexed() {
+        let pgn = pgn_path("append");
+        std::fs::write(&pgn, GAME).unwrap();
+        assert_eq!(Index::open(&pgn).unwrap().len(), 1);
+        let mut file = OpenOptions::new().append(true).open(&pgn).unwrap();
+        file.write_all(GAME.replace("Carlsen", "Nakamura").as_bytes()).unwrap();
+        drop(file);
+        let index = Index::open(&pgn).unwrap();
+        assert_eq!(index.entries().iter().map(|entry| entry.white.as_str()).collect::<Vec<_>>(), ["Carlsen", "Nakamura"]);
+        assert_eq!(Index::load(&pgn).unwrap().unwrap().entries(), index.entries());
+        cleanup(&pgn);
+    }
+
+    #[test]
+    fn rewritten_pgn_is_reindexed() {
+        let pgn = pgn_path("rebuild");
+        std::fs::write(&pgn, format!("{}{}", GAME, GAME)).unwrap();
+        assert_eq!(Index::open(&pgn).unwrap().len(), 2);
+        // Same length, different players.
+        std::fs::write(&pgn, format!("{}{}", GAME, GAME).replace("Carlsen", "Anand\u{a0}")).unwrap();
+        let index = Index::open(&pgn).unwrap();
+        assert_eq!(index.entries().iter().map(|entry| entry.white.as_str()).collect::<Vec<_>>(), ["Anand\u{a0}", "Anand\u{a0}"]);
+        // Shorter.
+        std::fs::write(&pgn, GAME).unwrap();
+        assert_eq!(Index::open(&pgn).unwrap().len(), 1);
+        cleanup(&pgn);
+    }
+}
@@ -2,6 +2,7 @@
 use counter::Counter;
 use regex::Regex;
 use lazy_static::lazy_static;
+use thiserror::Error;
 use core::panic;
 use std::{cmp::max, collections::{HashMap, VecDeque}, fmt, fmt::Formatter, hash::Hash, intrinsics::{bitreverse, log2f64}, mem::MaybeUninit, ops::{self, BitOr, RangeBounds}, path::Iter, process::Output, result};
 
@@ -406,6 +407,24 @@ lazy_static! {
         bb_ranks
     };
 }
+lazy_static! {
+    /// Random keys for `Board::zobrist_hash`: 12 * 64 piece-square keys, 64
+    /// castling rook keys, 8 en passant file keys and the side to move.
+    /// Generated with splitmix64 from a fixed seed, so hashes are stable
+    /// across runs but not compatible with Polyglot books.
+    pub static ref ZOBRIST_KEYS: [u64; 841] = {
+        let mut state: u64 = 0x5eed_0f_c4e55;
+        let mut keys = [0u64; 841];
+        for key in keys.iter_mut() {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            *key = z ^ (z >> 31);
+        }
+        keys
+    };
+}
 pub const BB_FILE_A: u64 = 0x0101_0101_0101_0101 << 0;
 pub const BB_FILE_B: u64 = 0x0101_0101_0101_0101 << 1;
 pub const BB_FILE_C: u64 = 0x0101_0101_0101_0101 << 2;
@@ -462,7 +481,7 @@ pub fn scan_reversed(bb: Bitboard) -> impl Iterator<Item = Square> {
     })
 }
 
-fn popcount(bb: Bitboard) -> u32 {
+pub fn popcount(bb: Bitboard) -> u32 {
     bb.count_ones()
 }
 pub fn flip_vertical(mut bb: Bitboard) -> Bitboard {
@@ -728,7 +747,7 @@ lazy_static! {
         regex.unwrap()
     };
     pub static ref FEN_CASTLING_REGEX: Regex = {
-        let regex = Regex::new(r"^(?:-|[KQABCDEFGH]{0,2}[kqabcdefgh]{0,2})\Z");
+        let regex = Regex::new(r"^(?:-|[KQABCDEFGH]{0,2}[kqabcdefgh]{0,2})$");
         regex.unwrap()
     };
 }
@@ -770,6 +789,29 @@ impl Piece {
         }
     }
 }
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SanError {
+    #[error("invalid san: {0}")]
+    Invalid(String),
+    #[error("illegal san: {0}")]
+    Illegal(String),
+    #[error("ambiguous san: {0}")]
+    Ambiguous(String),
+    #[error("missing promotion piece type: {0}")]
+    MissingPromotion(String),
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum FenError {
+    #[error("invalid fen {fen}: {reason}")]
+    Invalid { fen: String, reason: &'static str },
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PackedError {
+    #[error("invalid packed position: {0}")]
+    Invalid(&'static str),
+}
+/// Size of `Board::to_packed`.
+pub const PACKED_SIZE: usize = 32;
 #[derive(PartialEq, Clone, Copy)]
 pub struct Move {
     pub from_square: Square,
@@ -935,6 +977,23 @@ impl BaseBoard {
         };
         return bb & self.occupied_co[color as usize];
     }
+    /// Material on the board as `KRPvKR`: white pieces, then black, each
+    /// from king down to pawns.
+    pub fn material_signature(&self) -> String {
+        let mut signature = String::new();
+        for color in COLORS {
+            if color == BLACK {
+                signature.push('v');
+            }
+            for piece_type in (PAWN..=KING).rev() {
+                let symbol = piece_symbol(piece_type).unwrap().to_ascii_uppercase();
+                for _ in 0..popcount(self.pieces_mask(piece_type, color)) {
+                    signature.push(symbol);
+                }
+            }
+        }
+        signature
+    }
     pub fn pieces(&self, piece_type: PieceType, color: Color) -> SquareSet {
         SquareSet {
             mask: self.pieces_mask(piece_type, color),
@@ -1198,6 +1257,42 @@ impl BaseBoard {
         }
         builder
     }
+    /// Checks the position part of a FEN, which `set_board_fen` panics on
+    /// if it is malformed.
+    pub fn check_board_fen(fen: &str) -> Result<(), FenError> {
+        let invalid = |reason| Err(FenError::Invalid { fen: fen.to_string(), reason });
+        if fen.trim().contains(' ') {
+            return invalid("more than the position part");
+        }
+        let rows: Vec<&str> = fen.split('/').collect();
+        if rows.len() != 8 {
+            return invalid("expected 8 rows");
+        }
+        for row in rows {
+            let (mut field_sum, mut previous) = (0, ' ');
+            for c in row.chars() {
+                if let Some(digit) = c.to_digit(10).filter(|digit| (1..=8).contains(digit)) {
+                    if previous.is_ascii_digit() {
+                        return invalid("two subsequent digits");
+                    }
+                    field_sum += digit;
+                } else if c == '~' {
+                    if !PIECE_SYMBOLS.contains(&Some(previous.to_ascii_lowercase())) {
+                        return invalid("'~' not after a piece");
+                    }
+                } else if PIECE_SYMBOLS.contains(&Some(c.to_ascii_lowercase())) {
+                    field_sum += 1;
+                } else {
+                    return invalid("invalid character");
+                }
+                previous = c;
+            }
+            if field_sum != 8 {
+                return invalid("expected 8 columns per row");
+            }
+        }
+        Ok(())
+    }
     pub fn set_board_fen(&mut self, fen: &str) {
         let fen_trimmed = fen.trim();
         if fen_trimmed.contains(" ") {
@@ -1435,7 +1530,7 @@ pub struct Board {
 
 impl Board {
     pub fn new(fen: Option<&str>) -> Board {
-        let baseboard = BaseBoard::new(fen);
+        let baseboard = BaseBoard::new(None);
         let mut board = Board {
             baseboard: baseboard,
             ep_square: None,
@@ -1458,6 +1553,28 @@ impl Board {
         }
         board
     }
+    /// The board of `fen`, or an error where `new` would panic.
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let invalid = |reason| Err(FenError::Invalid { fen: fen.to_string(), reason });
+        let parts: Vec<&str> = fen.split(' ').collect();
+        if parts.len() > 6 {
+            return invalid("more than 6 parts");
+        }
+        BaseBoard::check_board_fen(parts[0])?;
+        if parts.get(1).is_some_and(|turn| !["w", "b"].contains(turn)) {
+            return invalid("expected 'w' or 'b' for the turn");
+        }
+        if parts.get(2).is_some_and(|castling| !FEN_CASTLING_REGEX.is_match(castling)) {
+            return invalid("invalid castling part");
+        }
+        if parts.get(3).is_some_and(|ep| *ep != "-" && !SQUARE_NAMES.contains(ep)) {
+            return invalid("invalid en passant square");
+        }
+        if parts[4.min(parts.len())..].iter().any(|number| number.parse::<u32>().is_err()) {
+            return invalid("invalid move number");
+        }
+        Ok(Board::new(Some(fen)))
+    }
     pub fn reset(&mut self) {
         self.turn = WHITE;
         self.castling_rights = BB_CORNERS;
@@ -1480,7 +1597,7 @@ impl Board {
 
         self.clear_board();
     }
-    pub fn copy(&mut self, copy_stack: bool) -> Board {
+    pub fn copy(&self, copy_stack: bool) -> Board {
         let mut board = Board::new(None);
         board.baseboard = self.baseboard;
         board.ep_square = self.ep_square;
@@ -1505,67 +1622,122 @@ impl Board {
     pub fn ply(&self) -> u64 {
         2 * (self.fullmove_number - 1) + (self.turn == BLACK) as u64
     }
-    pub fn find_move(&self, from_square: Square, to_square: Square, mut promotion: Option<PieceType>) -> Move {
-        if promotion.is_none() && self.baseboard.pawns != 0 
-            && BB_SQUARES[from_square as usize] != 0 && BB_SQUARES[to_square as usize] != 0 && BB_BACKRANKS != 0 {
+    pub fn find_move(&self, from_square: Square, to_square: Square, mut promotion: Option<PieceType>) -> Result<Move, SanError> {
+        if promotion.is_none() && self.baseboard.pawns & BB_SQUARES[from_square as usize] != 0
+            && BB_SQUARES[to_square as usize] & BB_BACKRANKS != 0 {
                 promotion = Some(QUEEN);
         }
         
         let m = Move{from_square: from_square, to_square: to_square, promotion: promotion};
 
         if !self.is_legal(m) {
-            panic!("No matching legal move for {}", m.uci())
+            return Err(SanError::Illegal(m.uci()));
         }
-        m
+        Ok(m)
 
     }
-    pub fn parse_san(&self, san: &str) -> Move {
+    /// Standard algebraic notation of a legal move, with a check or mate suffix.
+    pub fn san(&mut self, m: Move) -> String {
+        let mut san = self.algebraic_without_suffix(m);
+        if !m.bool() {
+            return san;
+        }
+        self.push(m);
+        if self.is_checkmate() {
+            san.push('#');
+        }
+        else if self.is_check() {
+            san.push('+');
+        }
+        self.pop();
+        san
+    }
+    fn algebraic_without_suffix(&self, m: Move) -> String {
+        if !m.bool() {
+            return String::from("--");
+        }
+        if self.is_castling(m) {
+            return String::from(if square_file(m.to_square) < square_file(m.from_square) { "O-O-O" } else { "O-O" });
+        }
+        let piece_type = self.baseboard.piece_type_at(m.from_square).expect("san() expects a legal move");
+        let capture = self.is_capture(m);
+        let mut san = String::new();
+        if piece_type == PAWN {
+            if capture {
+                san.push(FILE_NAMES[square_file(m.from_square) as usize]);
+            }
+        }
+        else {
+            san.push(piece_symbol(piece_type).unwrap().to_ascii_uppercase());
+
+            // Disambiguate against other pieces of the same type reaching the target.
+            let others = self.baseboard.pieces_mask(piece_type, self.turn) & !BB_SQUARES[m.from_square as usize];
+            let mut others_mask: Bitboard = 0;
+            for candidate in self.generate_legal_moves(others, BB_SQUARES[m.to_square as usize]) {
+                others_mask |= BB_SQUARES[candidate.from_square as usize];
+            }
+            if others_mask != 0 {
+                let (mut row, mut column) = (false, false);
+                if others_mask & BB_RANKS[square_rank(m.from_square) as usize] != 0 {
+                    column = true;
+                }
+                if others_mask & BB_FILES[square_file(m.from_square) as usize] != 0 {
+                    row = true;
+                }
+                else {
+                    column = true;
+                }
+                if column {
+                    san.push(FILE_NAMES[square_file(m.from_square) as usize]);
+                }
+                if row {
+                    san.push(RANK_NAMES[square_rank(m.from_square) as usize]);
+                }
+            }
+        }
+        if capture {
+            san.push('x');
+        }
+        san.push_str(SQUARE_NAMES[m.to_square as usize]);
+        if let Some(promotion) = m.promotion {
+            san.push('=');
+            san.push(piece_symbol(promotion).unwrap().to_ascii_uppercase());
+        }
+        san
+    }
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
         match san {
             "O-O"| "O-O+"| "O-O#"| "0-0"| "0-0+"| "0-0#" => {
                 let mut m_opt_iter = self.generate_castling_moves(BB_ALL, BB_ALL)
                     .filter(|m| self.is_kingside_castling(*m));
-                if let Some(m) = m_opt_iter.next() {
-                    return m;
-                }
-                else {
-                    println!("illegal san: {}", san);
-                    return Move::null();
-                }
-                
+                return m_opt_iter.next().ok_or_else(|| SanError::Illegal(san.to_string()));
             },
             "O-O-O"| "O-O-O+"| "O-O-O#"| "0-0-0"| "0-0-0+"| "0-0-0#" => {
                 let mut m_opt_iter = self.generate_castling_moves(BB_ALL, BB_ALL)
                     .filter(|m| self.is_queenside_castling(*m));
-                if let Some(m) = m_opt_iter.next() {
-                    return m;
-                }
-                else {
-                    println!("illegal san: {}", san);
-                    return Move::null();
-                }
+                return m_opt_iter.next().ok_or_else(|| SanError::Illegal(san.to_string()));
             }
             _ => {}
         }
 
-        let re_match_opt = SAN_REGEX.captures(san);
-
-        if re_match_opt.is_none() {
-            match san {
-                "--" | "Z0" | "0000" | "@@@@" => { return Move::null();},
-                _ => { panic!("invlaid san: {}", san);}
+        let re_match = match SAN_REGEX.captures(san) {
+            Some(re_match) => re_match,
+            None => match san {
+                "--" | "Z0" | "0000" | "@@@@" => { return Ok(Move::null());},
+                _ => { return Err(SanError::Invalid(san.to_string()));}
             }
-        }
-        // let fen = self.baseboard.board_fen(false);
-        
-        let re_match = re_match_opt.unwrap();
+        };
+
         let to_square = parse_square(&re_match[4]);
-        let mut to_mask = BB_SQUARES[to_square as usize] & !self.baseboard.occupied_co[self.turn as usize];
+        let to_mask = BB_SQUARES[to_square as usize] & !self.baseboard.occupied_co[self.turn as usize];
         let p = re_match.get(5).map_or("", |x|x.as_str());
         let promotion = if p.is_empty() { None } else {piece_type(p.to_lowercase().chars().last())};
+        if promotion == Some(KING) {
+            return Err(SanError::Invalid(san.to_string()));
+        }
         let mut from_file = 0;
         let mut from_rank = 0;
         let mut from_mask = BB_ALL;
-        let mut aaa = 0;
         if let Some(cap) = re_match.get(2){
             from_file = parse_file_name(cap.as_str().chars().nth(0).unwrap());
             from_mask &= BB_FILES[from_file as usize];
@@ -1577,54 +1749,29 @@ impl Board {
 
         if let Some(cap) = re_match.get(1) {
             let piece_type = piece_type(Some(cap.as_str().chars().nth(0).unwrap().to_ascii_lowercase()));
-            aaa = piece_type.unwrap();
             from_mask &= self.baseboard.pieces_mask(piece_type.unwrap(), self.turn);
         }
         else if re_match.get(2).is_some() && re_match.get(3).is_some() {
-            let m = self.find_move(square(from_file, from_rank), to_square, None);
+            let m = self.find_move(square(from_file, from_rank), to_square, promotion)?;
             if m.promotion == promotion {
-                return m
+                return Ok(m);
             }
-            else { println!("missing promotion piece type {} in {}", san, self.baseboard.board_fen(true)); return Move::null()}
-        }
-        else if re_match.get(5).is_some() && re_match.get(2).is_none() && re_match.get(3).is_none() && re_match.get(4).is_some(){
-            let mut chars = re_match.get(4).unwrap().as_str().chars();
-            let file = parse_file_name(chars.next().unwrap());
-            let mut rank = parse_rank_name(chars.next().unwrap());
-            if self.turn { rank -= 1} else {rank += 1}
-            from_mask &= BB_FILES[file as usize];
-            from_mask &= BB_RANKS[rank as usize];
-
-            let m = self.find_move(square(file, rank), to_square, promotion);
-            if m.promotion == promotion {
-                return m;
-            }
-            else { println!("missing promotion piece type {} in {}", san, self.baseboard.board_fen(true)); return Move::null()}
+            return Err(SanError::MissingPromotion(san.to_string()));
         }
         else {
             from_mask &= self.baseboard.pawns
         }
 
         let mut matched_move = None;
-            //println!("fen2: {}", self.baseboard.board_fen(true));
         for m in self.generate_legal_moves(from_mask, to_mask) {
             if m.promotion != promotion { continue; }
 
-            if matched_move.is_some() {println!("fen: {}", self.baseboard.board_fen(false));  println!("ambiguous san: {}", san); return Move::null();}
-
+            if matched_move.is_some() {
+                return Err(SanError::Ambiguous(san.to_string()));
+            }
             matched_move = Some(m);
-
-            if !matched_move.unwrap().bool() {
-                println!("illegal san: {}", san);
-                return Move::null();
-            } 
-        }
-        // println!("fen2: {}, {}", self.baseboard.board_fen(false), san);
-        match matched_move {
-            Some(m) => {return m;},
-            None => {println!("fen: {}", self.baseboard.board_fen(false)); println!("invalid move: {}", san); return Move::null();}
         }
-        //matched_move.unwrap()
+        matched_move.ok_or_else(|| SanError::Illegal(san.to_string()))
     }
     pub fn remove_piece_at(&mut self, square: Square) -> Option<Piece> {
         let piece = self.baseboard.remove_piece_at(square);
@@ -1691,12 +1838,13 @@ impl Board {
                             to_square: to_square as Square,
                             promotion: Some(KNIGHT),
                         };
+                    } else {
+                        yield Move {
+                            from_square: from_square as Square,
+                            to_square: to_square as Square,
+                            promotion: None,
+                        };
                     }
-                    yield Move {
-                        from_square: from_square as Square,
-                        to_square: to_square as Square,
-                        promotion: None,
-                    };
                 }
             }
             let mut single_moves = 0;
@@ -1739,12 +1887,13 @@ impl Board {
                         to_square: to_square as Square,
                         promotion: Some(KNIGHT),
                     };
+                } else {
+                    yield Move {
+                        from_square: from_square as Square,
+                        to_square: to_square as Square,
+                        promotion: None,
+                    };
                 }
-                yield Move {
-                    from_square: from_square as Square,
-                    to_square: to_square as Square,
-                    promotion: None,
-                };
             }
 
             for to_square in scan_reversed(double_moves) {
@@ -2110,19 +2259,12 @@ impl Board {
 
         let mut ep_square: Option<u8> = None;
         if let Some(ep_part) = parts.pop_front() {
-            if ep_part.len() != 1 {
-                panic!("invalid ep_part len {}", ep_part);
-            }
-            if let Some(sq) = ep_part.chars().nth(0) {
-                if sq == '-' {
-                    ep_square = None;
-                } else if 0x30 <= sq as u8 && 0x38 > sq as u8 {
-                    ep_square = Some(sq as u8 - 0x30);
-                } else {
-                    panic!("Invalid en passant square {}", sq);
-                }
+            if ep_part == "-" {
+                ep_square = None;
+            } else if SQUARE_NAMES.contains(&ep_part) {
+                ep_square = Some(parse_square(ep_part));
             } else {
-                panic!("ep_part is not a char");
+                panic!("Invalid en passant square {}", ep_part);
             }
         } else {
             ep_square = None
@@ -2175,6 +2317,33 @@ impl Board {
         self.fullmove_number = fullmove_number as u64;
         self.clear_stack();
     }
+    pub fn castling_fen(&self) -> String {
+        let castling = self.clean_castling_rights();
+        let mut builder = String::new();
+        for (bb, flag) in [(BB_H1, 'K'), (BB_A1, 'Q'), (BB_H8, 'k'), (BB_A8, 'q')] {
+            if castling & bb != 0 {
+                builder.push(flag);
+            }
+        }
+        if builder.is_empty() { String::from("-") } else { builder }
+    }
+    pub fn fen(&self) -> String {
+        format!("{} {} {}", self.epd(), self.halfmove_clock, self.fullmove_number)
+    }
+    /// The position part of the FEN, without the move counters.
+    pub fn epd(&self) -> String {
+        let ep_square = match self.ep_square {
+            Some(ep_square) if self.has_legal_en_passant() => SQUARE_NAMES[ep_square as usize],
+            _ => "-"
+        };
+        format!(
+            "{} {} {} {}",
+            self.baseboard.board_fen(false),
+            if self.turn == WHITE { "w" } else { "b" },
+            self.castling_fen(),
+            ep_square
+        )
+    }
     pub fn _set_castling_fen(&mut self, castling_fen: &str) {
         if castling_fen == "-" {
             self.castling_rights = BB_EMPTY;
@@ -2799,6 +2968,99 @@ impl Board {
             }
         })
     }
+    /// 64-bit hash of the position (pieces, side to move, castling rights and
+    /// a legal en passant square). Equal positions reached by different move
+    /// orders hash the same.
+    pub fn zobrist_hash(&self) -> u64 {
+        let mut hash = 0;
+        for color in COLORS {
+            for piece_type in PAWN..=KING {
+                let mut bb = self.baseboard.pieces_mask(piece_type, color);
+                let offset = 64 * (2 * (piece_type as usize - 1) + color as usize);
+                while bb != 0 {
+                    hash ^= ZOBRIST_KEYS[offset + bb.trailing_zeros() as usize];
+                    bb &= bb - 1;
+                }
+            }
+        }
+        let mut castling = self.clean_castling_rights();
+        while castling != 0 {
+            hash ^= ZOBRIST_KEYS[768 + castling.trailing_zeros() as usize];
+            castling &= castling - 1;
+        }
+        if let Some(ep_square) = self.ep_square {
+            if self.has_legal_en_passant() {
+                hash ^= ZOBRIST_KEYS[832 + square_file(ep_square) as usize];
+            }
+        }
+        if self.turn == WHITE {
+            hash ^= ZOBRIST_KEYS[840];
+        }
+        hash
+    }
+    /// The position in `PACKED_SIZE` bytes: the occupied squares (u64), a
+    /// nibble per occupied square in square order (piece type, plus 8 for
+    /// black), the turn, the castling rights on the back ranks (u16, rank 1
+    /// then rank 8), the en passant square or 64, and the halfmove clock and
+    /// fullmove number (u16 each), all little-endian. The move stack is not
+    /// kept. Positions with more than 32 pieces cannot be packed.
+    pub fn to_packed(&self) -> Result<[u8; PACKED_SIZE], PackedError> {
+        let mut packed = [0u8; PACKED_SIZE];
+        let occupied = self.baseboard.occupied;
+        if popcount(occupied) > 32 {
+            return Err(PackedError::Invalid("more than 32 pieces"));
+        }
+        packed[..8].copy_from_slice(&occupied.to_le_bytes());
+        let (mut bb, mut index) = (occupied, 0);
+        while bb != 0 {
+            let square = bb.trailing_zeros() as Square;
+            let nibble = self.baseboard.piece_type_at(square).unwrap() | (self.baseboard.color_at(square) == Some(BLACK)) as u8 * 8;
+            packed[8 + index / 2] |= nibble << (4 * (index % 2));
+            index += 1;
+            bb &= bb - 1;
+        }
+        packed[24] = (self.turn == WHITE) as u8;
+        let castling = (self.castling_rights & BB_RANK_1) as u16 | ((self.castling_rights & BB_RANK_8) >> 48) as u16;
+        packed[25..27].copy_from_slice(&castling.to_le_bytes());
+        packed[27] = self.ep_square.unwrap_or(64);
+        packed[28..30].copy_from_slice(&(self.halfmove_clock.min(u16::MAX as u64) as u16).to_le_bytes());
+        packed[30..32].copy_from_slice(&(self.fullmove_number.min(u16::MAX as u64) as u16).to_le_bytes());
+        Ok(packed)
+    }
+    /// The board of a position written by `to_packed`.
+    pub fn from_packed(packed: &[u8; PACKED_SIZE]) -> Result<Board, PackedError> {
+        let mut board = Board::new(None);
+        let occupied = u64::from_le_bytes(packed[..8].try_into().unwrap());
+        if popcount(occupied) > 32 {
+            return Err(PackedError::Invalid("more than 32 pieces"));
+        }
+        let (mut bb, mut index) = (occupied, 0);
+        while bb != 0 {
+            let nibble = packed[8 + index / 2] >> (4 * (index % 2)) & 15;
+            let piece_type = nibble & 7;
+            if !(PAWN..=KING).contains(&piece_type) {
+                return Err(PackedError::Invalid("unknown piece"));
+            }
+            board.baseboard.set_piece_at(bb.trailing_zeros() as Square, Some(Piece { piece_type, color: nibble < 8 }), false);
+            index += 1;
+            bb &= bb - 1;
+        }
+        board.turn = match packed[24] {
+            0 => BLACK,
+            1 => WHITE,
+            _ => return Err(PackedError::Invalid("unknown turn"))
+        };
+        let castling = u16::from_le_bytes([packed[25], packed[26]]) as Bitboard;
+        board.castling_rights = (castling & 0xff) | (castling >> 8) << 56;
+        board.ep_square = match packed[27] {
+            64 => None,
+            square if square < 64 => Some(square),
+            _ => return Err(PackedError::Invalid("en passant square out of range"))
+        };
+        board.halfmove_clock = u16::from_le_bytes([packed[28], packed[29]]) as u64;
+        board.fullmove_number = u16::from_le_bytes([packed[30], packed[31]]) as u64;
+        Ok(board)
+    }
     pub fn transposition_key(&self) -> Option<Transposition> {
         if self.has_legal_en_passant() {
             return None;
@@ -3088,4 +3350,78 @@ impl std::ops::Not for SquareSet {
     fn not(self) -> Self::Output {
         SquareSet { mask: !self.mask }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn board_from_full_fen() {
+        let board = Board::new(Some("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"));
+        assert_eq!(board.baseboard.occupied.count_ones(), 32);
+        assert_ne!(board.baseboard.pawns & BB_SQUARES[36], 0);
+        assert_eq!(board.turn, WHITE);
+        assert_eq!(board.ep_square, Some(45));
+        assert_eq!(board.castling_rights, BB_A1 | BB_H1 | BB_A8 | BB_H8);
+        assert_eq!((board.halfmove_clock, board.fullmove_number), (0, 3));
+        let board = Board::new(Some("4k3/8/8/8/8/8/8/4K3 b - - 12 40"));
+        assert_eq!((board.turn, board.ep_square, board.halfmove_clock, board.fullmove_number), (BLACK, None, 12, 40));
+    }
+
+    #[test]
+    fn board_from_invalid_fen() {
+        assert!(Board::from_fen(STARTING_FEN).is_ok());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3").is_ok());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/3Q~K3 b - - 0 1").is_ok());
+        for fen in ["", "4k3/8/8/8/8/8/8", "4k3/8/8/8/8/8/8/4K2", "4k3/8/8/8/8/8/8/4K12", "4k3/8/8/8/8/8/8/4X3",
+                    "4k3/8/8/8/8/8/8/~4K3", "4k3/8/8/8/8/8/8/4K3 x", "4k3/8/8/8/8/8/8/4K3 w KX",
+                    "4k3/8/8/8/8/8/8/4K3 w - e9", "4k3/8/8/8/8/8/8/4K3 w - - -1 1", "4k3/8/8/8/8/8/8/4K3 w - - 0 1 1",
+                    "4k3/8/8/8/8/8/8/4K3  w"] {
+            assert!(matches!(Board::from_fen(fen), Err(FenError::Invalid { .. })), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn packed_round_trip() {
+        let fens = [
+            STARTING_FEN,
+            "r3k2r/pppq1ppp/2np1n2/2b1p3/2B1P3/2NP1N2/PPPQ1PPP/R3K2R w Kq - 4 9",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "rnbqkbnr/pppp1ppp/8/8/3Pp3/8/PPP1PPPP/RNBQKBNR b KQkq d3 0 3",
+            "QQ2k3/8/8/8/8/8/8/4K2q b - - 99 120"
+        ];
+        let mut promoted = Board::new(Some("4k3/P7/8/8/8/8/8/4K3 w - - 0 1"));
+        promoted.push(Move::from_uci("a7a8n"));
+        let boards = fens.iter().map(|fen| Board::new(Some(fen))).chain([promoted]);
+        for board in boards {
+            let unpacked = Board::from_packed(&board.to_packed().unwrap()).unwrap();
+            assert_eq!(unpacked.fen(), board.fen());
+            assert_eq!(unpacked.castling_rights, board.castling_rights);
+            assert_eq!(unpacked.ep_square, board.ep_square);
+        }
+    }
+
+    #[test]
+    fn packing_too_many_pieces_is_an_error() {
+        let board = Board::new(Some("rnbqkbnr/pppppppp/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"));
+        assert_eq!(board.to_packed(), Err(PackedError::Invalid("more than 32 pieces")));
+        let mut packed = Board::new(None).to_packed().unwrap();
+        packed[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Board::from_packed(&packed).is_err());
+        let mut packed = Board::new(None).to_packed().unwrap();
+        packed[27] = 65;
+        assert!(Board::from_packed(&packed).is_err());
+    }
+
+    #[test]
+    fn moves_to_the_last_rank_are_promotions() {
+        // A push and a capture to the last rank, four promotions each.
+        for (fen, from) in [("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", 48), ("4k3/8/8/8/8/8/p7/1N2K3 b - - 0 1", 8)] {
+            let board = Board::new(Some(fen));
+            let moves: Vec<Move> = board.generate_legal_moves(BB_SQUARES[from], BB_ALL).collect();
+            assert_eq!(moves.len(), 8);
+            assert!(moves.iter().all(|m| m.promotion.is_some()));
+        }
+    }
+}
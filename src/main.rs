@@ -15,15 +15,92 @@ extern crate auto_ops;
 mod init;
 mod pgn;
 mod gen_iter;
+mod parallel;
+mod cursor;
+mod repertoire;
+mod index;
+mod query;
+mod search;
+mod eco;
+mod explorer;
+mod output;
+mod report;
+mod dedup;
+mod export;
+mod training;
+mod encode;
+mod archive;
+mod syzygy;
+mod bitbase;
 use init::{Board, Move};
 use lazy_static::lazy_static;
-use crate::pgn::read_game;
-use pgn::ParsingError;
 
 
 pub static mut I: u64 = 0;
 
+/// `json <pgn> [out]` and `csv [--moves] <pgn> [out]`: export a PGN file,
+/// writing to `out` or standard output.
+fn export_command(command: &str, args: &[String]) -> std::io::Result<()> {
+    let per_move = args.iter().any(|arg| arg == "--moves");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let input = match paths.first() {
+        Some(input) => pgn::BufReader::open(input)?,
+        None => {
+            eprintln!("usage: {} <pgn> [out]", command);
+            std::process::exit(2);
+        }
+    };
+    let mut writer: Box<dyn std::io::Write> = match paths.get(1) {
+        Some(output) => Box::new(std::io::BufWriter::new(File::create(output)?)),
+        None => Box::new(std::io::BufWriter::new(std::io::stdout()))
+    };
+    match command {
+        "json" => export::write_json(input, &mut writer)?,
+        _ => export::write_csv(input, &mut writer, per_move)?
+    }
+    writer.flush()
+}
+
+/// `report [--json|--csv] <player> <pgn>`: print the report of a player,
+/// as a table unless a format is given.
+fn report_command(args: &[String]) -> std::io::Result<()> {
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let (player, input) = match paths[..] {
+        [player, input] => (player, pgn::BufReader::open(input)?),
+        _ => {
+            eprintln!("usage: report [--json|--csv] <player> <pgn>");
+            std::process::exit(2);
+        }
+    };
+    let report = report::PlayerReport::from_pgn(player, input);
+    if args.iter().any(|arg| arg == "--json") {
+        println!("{}", report.to_json());
+    }
+    else if args.iter().any(|arg| arg == "--csv") {
+        print!("{}", report.to_csv());
+    }
+    else {
+        print!("{}", report.table());
+    }
+    Ok(())
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(command @ ("json" | "csv")) = args.get(1).map(String::as_str) {
+        if let Err(e) = export_command(command, &args[2..]) {
+            eprintln!("{}: {}", command, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("report") {
+        if let Err(e) = report_command(&args[2..]) {
+            eprintln!("report: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     // let mut b: BaseBoard = BaseBoard::new(None);
     // b.reset_board();
@@ -40,27 +117,25 @@ fn main() {
     // board.push(Move::from_uci("C2C4"));
     // println!("{}", board.baseboard.unicode(false, false, "."));
     // println!("moves: {:#?}", board.generate_legal_moves(init::BB_ALL, init::BB_ALL).collect::<Vec<Move>>());
-    use pgn::Node;
-    let mut a = Node::new("hey");
-    let mut b = a.add_variation(Move::from_uci("D2D4"), "comment", "starting", HashSet::new());
-    let mut c = b.add_variation(Move::from_uci("D7D5"), "comment2", "starting2",HashSet::new());
-    c = c.add_variation(Move::from_uci("C2C4"), "fdsa", "hdsa",HashSet::new());
-    let board = c.board();
+    use pgn::Game;
+    let mut game = Game::new();
+    game.root_mut().set_comment("hey");
+    let b = game.root_mut().add_variation(Move::from_uci("D2D4"), "comment", "starting", HashSet::new());
+    let mut c = game.node_mut(b).add_variation(Move::from_uci("D7D5"), "comment2", "starting2",HashSet::new());
+    c = game.node_mut(c).add_variation(Move::from_uci("C2C4"), "fdsa", "hdsa",HashSet::new());
+    let board = game.node(c).board();
     println!("{}", board.baseboard.unicode(board.turn, false, "."));
-    let mut handle = pgn::BufReader::open("database.txt").expect("couldnt");
-    let mut i = 0;
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
 
     let startTime = std::time::Instant::now();
-    loop {
-        let visitor = match read_game(&mut handle) {
+    parallel::for_each_game("database.txt", threads, pgn::GameBuilder::new, |game| {
+        let game = match game {
             Ok(game) => {game},
-            Err(ParsingError::ReadLineError) => {println!("Readline error: {}", i); break;}
-            Err(ParsingError::EmptyMoves) => {println!("Game has no moves: {}", i); continue;}
-            Err(ParsingError::InvalidMoveError) => {println!("Invalid move: {}", i); continue;}
+            Err(e) => {println!("{}", e); return;}
         };
-        //println!("Game headers : {:?}", visitor.borrow().result().root);
+        //println!("Game headers : {:?}", game.headers);
         unsafe{I+= 1};
-    }
+    }).expect("couldnt");
     println!("time elapsed: {:?}, {}", std::time::Instant::now() - startTime, unsafe{I});
 }
 
@@ -72,15 +147,11 @@ fn bench_cp(b :&mut test::Bencher) {
         for i in 1..3 {
             test::black_box({
 
-                let mut handle = pgn::BufReader::open("test.txt").expect("couldnt");
-                let mut i = 0;
-                loop {
-                    let visitor = match read_game(&mut handle) {
-                    Ok(game) => {game},
-                    Err(ParsingError::ReadLineError) => {println!("Readline error: {}", i); break;}
-                    Err(ParsingError::EmptyMoves) => {println!("Game has no moves: {}", i); continue;}
-                    Err(ParsingError::InvalidMoveError) => {println!("Invalid move: {}", i); continue;}
-                    };
+                let handle = pgn::BufReader::open("test.txt").expect("couldnt");
+                for game in pgn::Games::new(handle) {
+                    if let Err(e) = game {
+                        println!("{}", e);
+                    }
                 }
             })
         }
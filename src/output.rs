@@ -0,0 +1,34 @@
+//! Helpers for writing JSON and CSV by hand.
+
+/// `s` as a quoted JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+/// A JSON number, or `null` for values JSON cannot represent.
+pub fn json_number(value: f64) -> String {
+    match value.is_finite() {
+        true => format!("{}", value),
+        false => String::from("null")
+    }
+}
+/// `s` as a CSV field, quoted only when needed.
+pub fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string()
+    }
+}
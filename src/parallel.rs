@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use crate::pgn::{self, Game, GameBuilder, Games, ParsingError, Visitor};
+
+/// Byte range `[start, end)` of a PGN file that contains only whole games.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chunk {
+    pub start: u64,
+    pub end: u64
+}
+
+type ChunkReader = pgn::BufReader<io::BufReader<io::Take<File>>>;
+/// The games of one chunk, and how many games and lines it has.
+type ChunkGames<T> = (Vec<Result<T, ParsingError>>, u64, u64);
+
+fn open_chunk(path: &Path, chunk: Chunk) -> io::Result<ChunkReader> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(chunk.start))?;
+    Ok(pgn::BufReader::at_offset(io::BufReader::new(file.take(chunk.end - chunk.start)), chunk.start))
+}
+
+/// First game boundary (a blank line followed by `[Event`) at or after `from`.
+fn find_boundary(reader: &mut io::BufReader<File>, from: u64, len: u64) -> io::Result<u64> {
+    reader.seek(SeekFrom::Start(from))?;
+    let mut position = from;
+    let mut line = Vec::new();
+    // The first line is usually cut in half, so it never counts as blank.
+    let mut previous_blank = from == 0;
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(len);
+        }
+        if previous_blank && line.starts_with(b"[Event ") {
+            return Ok(position);
+        }
+        previous_blank = line.iter().all(|c| c.is_ascii_whitespace());
+        position += read as u64;
+    }
+}
+
+/// Splits a PGN file into at most `chunks` ranges at game boundaries.
+pub fn split(path: impl AsRef<Path>, chunks: usize) -> io::Result<Vec<Chunk>> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = io::BufReader::new(file);
+    let chunks = chunks.max(1) as u64;
+
+    let mut boundaries = Vec::from([0]);
+    for i in 1..chunks {
+        let boundary = find_boundary(&mut reader, len * i / chunks, len)?;
+        if boundary > *boundaries.last().unwrap() && boundary < len {
+            boundaries.push(boundary);
+        }
+    }
+    boundaries.push(len);
+    Ok(boundaries.windows(2).map(|w| Chunk { start: w[0], end: w[1] }).collect())
+}
+
+/// Runs `job` for every chunk on `threads` worker threads. Workers send their
+/// results through the channel, which is drained on the calling thread by `sink`.
+fn run<T, J, S>(path: &Path, threads: usize, job: J, mut sink: S) -> io::Result<()>
+where
+    T: Send,
+    J: Fn(usize, ChunkReader, &Sender<T>) + Sync,
+    S: FnMut(T)
+{
+    let threads = threads.max(1);
+    // More chunks than threads, so that a few slow chunks don't leave cores idle.
+    let chunks = split(path, threads * 4)?;
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        let mut workers = Vec::new();
+        for _ in 0..threads.min(chunks.len()) {
+            let sender = sender.clone();
+            let (job, next, chunks) = (&job, &next, &chunks);
+            workers.push(scope.spawn(move || -> io::Result<()> {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= chunks.len() {
+                        return Ok(());
+                    }
+                    job(index, open_chunk(path, chunks[index])?, &sender);
+                }
+            }));
+        }
+        drop(sender);
+        for item in receiver {
+            sink(item);
+        }
+        workers.into_iter().try_for_each(|worker| worker.join().unwrap_or_else(|_| Err(io::Error::other("a worker thread panicked"))))
+    })
+}
+
+/// Parses every game of `path` in parallel and returns them in file order.
+pub fn read_games(path: impl AsRef<Path>, threads: usize) -> io::Result<Vec<Result<Game, ParsingError>>> {
+    read_games_with(path, threads, GameBuilder::new)
+}
+
+/// Like `read_games`, but with a custom visitor.
+pub fn read_games_with<V, F>(path: impl AsRef<Path>, threads: usize, make_visitor: F) -> io::Result<Vec<Result<V::Output, ParsingError>>>
+where
+    V: Visitor,
+    V::Output: Send,
+    F: Fn() -> V + Sync
+{
+    let mut chunks: Vec<Option<ChunkGames<V::Output>>> = Vec::new();
+    run(path.as_ref(), threads, |index, reader, sender| {
+        let mut games = Games::with_visitor(reader, make_visitor());
+        let parsed = games.by_ref().collect();
+        let reader = games.into_inner();
+        sender.send((index, (parsed, reader.games(), reader.line_number()))).unwrap();
+    }, |(index, games)| {
+        if chunks.len() <= index {
+            chunks.resize_with(index + 1, || None);
+        }
+        chunks[index] = Some(games);
+    })?;
+    // Errors count games and lines from the start of their chunk; make them
+    // count from the start of the file.
+    let (mut games_before, mut lines_before) = (0, 0);
+    let mut all = Vec::new();
+    for (games, count, lines) in chunks.into_iter().flatten() {
+        all.extend(games.into_iter().map(|game| game.map_err(|error| match error {
+            ParsingError::InvalidMoveError { game, line, column, token, fen, reason } =>
+                ParsingError::InvalidMoveError { game: game + games_before, line: line + lines_before, column, token, fen, reason },
+            error => error
+        })));
+        games_before += count;
+        lines_before += lines;
+    }
+    Ok(all)
+}
+
+/// Streams the output of `make_visitor`'s visitors to `consume` as soon as a
+/// game is parsed, in no particular order. `consume` runs on the calling thread.
+/// Game indices and line numbers in parsing errors count from the start of
+/// the chunk the game was read from; use `read_games_with` for ones that
+/// count from the start of the file.
+pub fn for_each_game<V, F, C>(path: impl AsRef<Path>, threads: usize, make_visitor: F, consume: C) -> io::Result<()>
+where
+    V: Visitor,
+    V::Output: Send,
+    F: Fn() -> V + Sync,
+    C: FnMut(Result<V::Output, ParsingError>)
+{
+    run(path.as_ref(), threads, |_, reader, sender| {
+        for game in Games::with_visitor(reader, make_visitor()) {
+            sender.send(game).unwrap();
+        }
+    }, consume)
+}
+
+/// Folds every game into an accumulator per chunk on the worker threads,
+/// then merges the per-chunk accumulators. As with `for_each_game`, parsing
+/// errors count games and lines from the start of their chunk.
+pub fn reduce<V, F, A, I, R, M>(path: impl AsRef<Path>, threads: usize, make_visitor: F, identity: I, fold: R, merge: M) -> io::Result<A>
+where
+    V: Visitor,
+    F: Fn() -> V + Sync,
+    A: Send,
+    I: Fn() -> A + Sync,
+    R: Fn(A, Result<V::Output, ParsingError>) -> A + Sync,
+    M: Fn(A, A) -> A
+{
+    let mut total = identity();
+    run(path.as_ref(), threads, |_, reader, sender| {
+        let partial = Games::with_visitor(reader, make_visitor()).fold(identity(), &fold);
+        sender.send(partial).unwrap();
+    }, |partial| {
+        let acc = std::mem::replace(&mut total, identity());
+        total = merge(acc, partial);
+    })?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_count_from_the_start_of_the_file() {
+        let game = "[Event \"?\"]\n[Result \"*\"]\n\n1. e4 e5 *\n\n";
+        let bad = "[Event \"?\"]\n[Result \"*\"]\n\n1. e4 Ke5 *\n\n";
+        let path = std::env::temp_dir().join(format!("parallel-{}.pgn", std::process::id()));
+        std::fs::write(&path, format!("{}{}", game.repeat(40), bad)).unwrap();
+        let games = read_games(&path, 4).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(games.len(), 41);
+        assert!(games[..40].iter().all(Result::is_ok));
+        match &games[40] {
+            Err(ParsingError::InvalidMoveError { game, line, .. }) => assert_eq!((*game, *line), (40, 40 * 5 + 4)),
+            _ => panic!("expected an invalid move")
+        }
+    }
+}
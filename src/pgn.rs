@@ -1,11 +1,11 @@
 
 
-use std::{cell::RefCell, collections::{HashMap, HashSet, VecDeque}, io::{Error, ErrorKind}, rc::Rc};
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::Mutex, time::Duration};
 use std::fs::File;
 use std::io::{self, prelude::*};
 
 use regex::Regex;
-use crate::{gen_iter, init::{Move, Board, BaseBoard, Color, Boolean}};
+use crate::{gen_iter, init::{Move, Board, Color, SanError, WHITE, BLACK, SQUARE_NAMES, STARTING_FEN, parse_square}};
 use lazy_static::lazy_static;
 use std::ops::Index;
 use thiserror::Error;
@@ -62,107 +62,255 @@ create_regex!(EVAL_REGEX, r"\[%eval\s(?:\#([+-]?\d+)|([+-]?(?:\d{0,10}\.\d{1,2}|
 create_regex!(ARROWS_REGEX, r"\[%(?:csl|cal)\s([RGYB][a-h][1-8](?:[a-h][1-8])?(?:,[RGYB][a-h][1-8](?:[a-h][1-8])?)*)\]");
 
 pub const TAG_ROASTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
-#[derive(PartialEq)]
+/// Index of a node in the arena of its `Game`. The root is always `NodeId(0)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
 pub struct NodeBase {
-    pub is_root: bool,
-    pub parent: Option<NodeRef>,
+    pub parent: Option<NodeId>,
     pub m: Option<Move>,
-    pub variations: VecDeque<NodeRef>,
+    pub variations: VecDeque<NodeId>,
     pub comment: String,
     pub starting_comment: String,
     pub nags: HashSet<u64>,
+    /// Ply of the position after `m`, counted from the FEN header.
+    ply: u64,
 }
-type NodeRef = Rc<RefCell<NodeBase>>;
-pub struct Node (
-    pub NodeRef
-);
+impl NodeBase {
+    fn new(parent: Option<NodeId>, m: Option<Move>, ply: u64) -> NodeBase {
+        NodeBase {
+            parent,
+            m,
+            ply,
+            variations: VecDeque::new(),
+            comment: String::new(),
+            starting_comment: String::new(),
+            nags: HashSet::new()
+        }
+    }
+}
+/// A game tree stored as an arena of nodes. Nodes refer to each other by
+/// `NodeId`, so a `Game` owns all of its data and can be sent across threads.
+/// Subtrees removed with `remove_variation` stay allocated until the game is dropped.
+///
+/// Node plies follow the FEN header as it was when the last move was added,
+/// so set the FEN header before adding moves.
 pub struct Game {
-    pub root: Node,
-    pub headers: Headers
+    nodes: Vec<NodeBase>,
+    pub headers: Headers,
+    /// Errors recovered from by a lenient `GameBuilder`.
+    pub errors: Vec<ParsingError>,
+    /// FEN header the node plies were numbered from.
+    ply_fen: Option<String>,
+    boards: Mutex<BoardCache>
+}
+/// The boards from the root to the last node handed out by `Node::board`,
+/// indexed by depth. Nodes never change their move once added, so the boards
+/// stay valid. A node whose parent is on the path, like the next move or an
+/// alternative to it, replays a single move.
+struct BoardCache {
+    fen: Option<String>,
+    path: Vec<(NodeId, Board)>
+}
+impl Game {
+    pub const ROOT: NodeId = NodeId(0);
+    pub fn new() -> Game {
+        Game {
+            nodes: Vec::from([NodeBase::new(None, None, 0)]),
+            headers: Headers::new(None),
+            errors: Vec::new(),
+            ply_fen: None,
+            boards: Mutex::new(BoardCache { fen: None, path: Vec::new() })
+        }
+    }
+    /// The starting position of the game, taken from the FEN header if there is one.
+    pub fn setup_board(&self) -> Board {
+        Board::new(Some(self.headers.get("FEN").unwrap_or(STARTING_FEN)))
+    }
+    fn board_cache(&self) -> std::sync::MutexGuard<'_, BoardCache> {
+        let mut cache = self.boards.lock().unwrap();
+        let fen = self.headers.get("FEN");
+        if cache.path.is_empty() || cache.fen.as_deref() != fen {
+            cache.fen = fen.map(String::from);
+            cache.path = Vec::from([(Game::ROOT, self.setup_board())]);
+        }
+        cache
+    }
+    /// Renumbers the plies of all nodes if the FEN header changed since
+    /// they were numbered. Parents always come before their children in
+    /// the arena, so one pass suffices.
+    fn sync_plies(&mut self) {
+        if self.ply_fen.as_deref() == self.headers.get("FEN") {
+            return;
+        }
+        self.ply_fen = self.headers.get("FEN").map(String::from);
+        self.nodes[0].ply = self.setup_board().ply();
+        for index in 1..self.nodes.len() {
+            let parent = self.nodes[index].parent.unwrap();
+            self.nodes[index].ply = self.nodes[parent.0].ply + 1;
+        }
+    }
+    pub fn root(&self) -> Node<'_> {
+        self.node(Game::ROOT)
+    }
+    pub fn root_mut(&mut self) -> NodeMut<'_> {
+        self.node_mut(Game::ROOT)
+    }
+    pub fn node(&self, id: NodeId) -> Node<'_> {
+        assert!(id.0 < self.nodes.len(), "node {:?} does not belong to this game", id);
+        Node { game: self, id }
+    }
+    pub fn node_mut(&mut self, id: NodeId) -> NodeMut<'_> {
+        assert!(id.0 < self.nodes.len(), "node {:?} does not belong to this game", id);
+        NodeMut { game: self, id }
+    }
+    pub fn end(&self) -> Node<'_> {
+        self.root().end()
+    }
+    /// Resolves a path of variation indices from the root, or `None` if
+    /// the tree has no such node.
+    pub fn node_at(&self, path: &NodePath) -> Option<Node<'_>> {
+        let mut node = self.root();
+        for index in &path.0 {
+            if *index >= node.base().variations.len() {
+                return None;
+            }
+            node = node.variation(MoveRepr::Int(*index));
+        }
+        Some(node)
+    }
+    /// Move-number form of a path, e.g. `"5.w/1,12.b/3"`: follow the main
+    /// variation up to 5. white and take its variation 1 there, then up to
+    /// 12... black and take variation 3. A segment without `/k` takes the
+    /// main variation. The root is the empty string.
+    pub fn address(&self, path: &NodePath) -> String {
+        let root_ply = self.root().ply() as usize;
+        let mut segments = Vec::new();
+        for (depth, index) in path.0.iter().enumerate() {
+            let last = depth + 1 == path.0.len();
+            if *index == 0 && !last {
+                continue;
+            }
+            let ply = root_ply + depth;
+            let color = if ply % 2 == 0 { 'w' } else { 'b' };
+            segments.push(match index {
+                0 => format!("{}.{}", ply / 2 + 1, color),
+                _ => format!("{}.{}/{}", ply / 2 + 1, color, index)
+            });
+        }
+        segments.join(",")
+    }
+    /// Parses the move-number form produced by `address`.
+    pub fn parse_address(&self, address: &str) -> Result<NodePath, NodePathError> {
+        let root_ply = self.root().ply() as usize;
+        let invalid = || NodePathError::Invalid(address.to_string());
+        let mut path = Vec::new();
+        for segment in address.split(',').map(str::trim).filter(|segment| !segment.is_empty()) {
+            let (number, rest) = segment.split_once('.').ok_or_else(invalid)?;
+            let (color, index) = match rest.split_once('/') {
+                Some((color, index)) => (color, index.parse::<usize>().map_err(|_| invalid())?),
+                None => (rest, 0)
+            };
+            let number: usize = number.parse().map_err(|_| invalid())?;
+            let ply = match (number, color) {
+                (0, _) => return Err(invalid()),
+                (n, "w") => 2 * (n - 1),
+                (n, "b") => 2 * (n - 1) + 1,
+                _ => return Err(invalid())
+            };
+            if ply < root_ply + path.len() {
+                return Err(invalid());
+            }
+            path.resize(ply - root_ply, 0);
+            path.push(index);
+        }
+        Ok(NodePath(path))
+    }
+}
+impl Index<NodeId> for Game {
+    type Output = NodeBase;
+    fn index(&self, id: NodeId) -> &NodeBase {
+        &self.nodes[id.0]
+    }
+}
+/// Read-only view of a node, navigated through the `Game` it belongs to.
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    game: &'a Game,
+    id: NodeId
 }
-impl std::fmt::Debug for Node {
+impl std::fmt::Debug for Node<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut builder = String::new();
-        let mut ptr = self.0.clone();
-        while !ptr.borrow().variations.is_empty() {
-            builder.push_str(&ptr.borrow().variations.index(0).borrow().m.unwrap().uci());
+        for m in self.mainline_moves() {
+            builder.push_str(&m.unwrap().uci());
             builder.push(' ');
-            ptr = ptr.clone().borrow().variations.index(0).clone();
         }
         write!(f, "Game: {}", builder)
     }
 }
-impl<'a> PartialEq for Node {
+impl PartialEq for Node<'_> {
     fn eq(&self, other: &Self) -> bool {
-        self as *const _ == other as *const _
-    }
-}
-impl Node {
-    pub fn new(comment: &str) -> Node {
-        Node (
-            Rc::new(RefCell::new(NodeBase {
-                is_root: false,
-                parent: None,
-                m: None,
-                variations: VecDeque::new(),
-                comment: String::from(comment),
-                starting_comment: String::new(),
-                nags: HashSet::new()
-            }))
-        )
-    }
-    pub fn init(parent:NodeRef , m: Move, comment: &str, starting_comment: &str, nags: HashSet<u64>) -> NodeRef{
-        let node = Node::new(comment).0;
-        node.borrow_mut().m = Some(m);
-        node.borrow_mut().starting_comment = String::from(starting_comment);
-        parent.borrow_mut().variations.push_front(node.clone());
-        node.borrow_mut().parent = Some(parent);
-        node
+        std::ptr::eq(self.game, other.game) && self.id == other.id
+    }
+}
+impl<'a> Node<'a> {
+    fn base(&self) -> &'a NodeBase {
+        &self.game.nodes[self.id.0]
+    }
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+    pub fn game(&self) -> &'a Game {
+        self.game
+    }
+    pub fn parent(&self) -> Option<Node<'a>> {
+        self.base().parent.map(|id| self.game.node(id))
+    }
+    pub fn m(&self) -> Option<Move> {
+        self.base().m
+    }
+    pub fn comment(&self) -> &'a str {
+        &self.base().comment
+    }
+    pub fn starting_comment(&self) -> &'a str {
+        &self.base().starting_comment
+    }
+    pub fn nags(&self) -> &'a HashSet<u64> {
+        &self.base().nags
+    }
+    pub fn variations(&self) -> impl Iterator<Item = Node<'a>> + 'a {
+        let game = self.game;
+        self.base().variations.iter().map(move |id| game.node(*id))
     }
     pub fn ply(&self) -> u64 {
-        return 5;
+        self.base().ply
     }
     pub fn turn(&self) -> Color {
         self.ply() % 2 == 0
     }
-    pub fn root(&self) -> NodeRef {
-        let mut node = self.0.clone();
-        loop {
-            match node.clone().borrow().parent.clone() {
-                None => {break;},
-                Some(n) => { node = n} 
-            };
-        };
-        node
-    }
-    pub fn game(&self) -> NodeRef {
-        let root = self.root();
-        assert!(root.borrow().is_root);
-        root
+    pub fn root(&self) -> Node<'a> {
+        self.game.root()
     }
-    pub fn end(self) -> NodeRef {
-        let mut node =self.0;
-        while !node.clone().borrow().variations.is_empty() {
-            node = node.clone().borrow().variations[0].clone();
+    pub fn end(&self) -> Node<'a> {
+        let mut node = *self;
+        while let Some(next) = node.next() {
+            node = next;
         }
         node
     }
     pub fn is_end(&self) -> bool {
-        self.0.borrow().variations.is_empty()
+        self.base().variations.is_empty()
     }
     pub fn starts_variation(&self) -> bool {
-        if !self.0.borrow().parent.is_none() {
-            return false;
+        match self.parent() {
+            Some(parent) => parent.base().variations.front().map_or(false, |first| *first != self.id),
+            None => false
         }
-        if !self.0.borrow().parent.as_ref().unwrap().borrow().variations.is_empty() {
-            return false;
-        }
-        self.0.borrow().parent.as_ref().unwrap().borrow().variations[0] == self.0
     }
     pub fn is_mainline(&self) -> bool {
-        let mut node = self.0.clone();
-        while let Some(parent) = node.clone().borrow().parent.clone() {
-            if parent.borrow().variations.is_empty() || parent.borrow().variations[0] != node {
+        let mut node = *self;
+        while let Some(parent) = node.parent() {
+            if parent.base().variations.front() != Some(&node.id) {
                 return false;
             }
             node = parent;
@@ -170,214 +318,495 @@ impl Node {
         true
     }
     pub fn is_main_variation(&self) -> bool {
-        if let Some(parent) = self.0.borrow().parent.as_ref() {
-            return parent.borrow().variations.is_empty() || parent.borrow().variations[0] == self.0;
+        match self.parent() {
+            Some(parent) => parent.base().variations.front().map_or(true, |first| *first == self.id),
+            None => true
         }
-        true
     }
-    pub fn variation(&self, m: MoveRepr) -> NodeRef {
-        self.index(m).clone()
+    pub fn variation(&self, m: MoveRepr) -> Node<'a> {
+        self.game.node(self.index(m))
     }
     pub fn has_variation(&self, m: Move) -> bool {
-        for variation in &self.0.borrow().variations {
-            if variation.borrow().m.unwrap() == m {
-                return true;
-            }
-        }
-        false
-    }
-    pub fn has_variation_node(&self, node: NodeRef) -> bool {
-        for variation in &self.0.borrow().variations {
-            if *variation == node {
-                return true;
-            }
-        }
-        false
+        self.variations().any(|variation| variation.m() == Some(m))
     }
-    pub fn promote_to_main(&mut self, m: MoveRepr) {
-        let variation = self.index(m);
-        let index= self.0.borrow().variations.iter().position(|x| *x == variation).unwrap();
-        let temp = self.0.borrow_mut().variations.remove(index).unwrap();
-        self.0.borrow_mut().variations.push_front(temp);
-    }
-    pub fn promote(&mut self, m: MoveRepr) {
-        let variation = self.index(m);
-        let index= self.0.borrow().variations.iter().position(|x| *x == variation).unwrap();
-        if index > 0 {
-            self.0.borrow_mut().variations.swap(index - 1, index);
-        }
+    pub fn has_variation_node(&self, node: NodeId) -> bool {
+        self.base().variations.contains(&node)
     }
-    pub fn demote(&mut self, m: MoveRepr) {
-        let variation = self.index(m);
-        let index= self.0.borrow().variations.iter().position(|x| *x == variation).unwrap();
-        if index < self.0.borrow().variations.len() - 1 {
-            self.0.borrow_mut().variations.swap(index + 1, index)
-        }
+    pub fn next(&self) -> Option<Node<'a>> {
+        self.base().variations.front().map(|id| self.game.node(*id))
     }
-    pub fn remove_variation(&mut self, m: MoveRepr) {
-        let index= self.0.borrow().variations.iter().position(|x| *x == self.variation(m.clone())).unwrap();
-        self.0.borrow_mut().variations.remove(index);
+    pub fn mainline(&self) -> Mainline<'a, Node<'a>> {
+        Mainline::new(*self, |node| node)
     }
-    pub fn add_variation(&mut self, m: Move, comment: &str, starting_comment: &str, nags: HashSet<u64>) -> Node {
-        let n = Node::init(self.0.clone(), m, comment, starting_comment, nags);
-        Node(n)
+    pub fn mainline_moves(&self) -> Mainline<'a, Option<Move>> {
+        Mainline::new(*self, |node| node.m())
     }
-    pub fn add_main_variation(&mut self, m: Move, comment: &str, nags: HashSet<u64>) -> Node {
-        let val= self.0.borrow_mut().variations.pop_back().unwrap();
-        self.0.borrow_mut().variations.push_front(val);
-        let node = self.add_variation(m, comment, "", nags);
-        node
+    pub fn board(&self) -> Board {
+        let mut cache = self.game.board_cache();
+        let root_ply = self.game.nodes[0].ply;
+        let mut stack: Vec<(NodeId, Move)> = Vec::new();
+        let mut node = *self;
+        let depth = loop {
+            let depth = (node.ply() - root_ply) as usize;
+            if cache.path.get(depth).is_some_and(|(id, _)| *id == node.id) {
+                break depth;
+            }
+            // The root is always on the path, so every other node has a move and a parent.
+            stack.push((node.id, node.m().unwrap()));
+            node = node.parent().unwrap();
+        };
+        cache.path.truncate(depth + 1);
+        let mut board = cache.path[depth].1.copy(true);
+        while let Some((id, m)) = stack.pop() {
+            board.push(m);
+            cache.path.push((id, board.copy(true)));
+        }
+        board
     }
-    pub fn next(&self) -> Option<NodeRef> {
-        if !self.0.borrow().variations.is_empty() {Some(self.0.borrow().variations[0].clone())} else {None}
+    pub fn path(&self) -> NodePath {
+        let mut path = Vec::new();
+        let mut node = *self;
+        while let Some(parent) = node.parent() {
+            path.push(parent.base().variations.iter().position(|id| *id == node.id).unwrap());
+            node = parent;
+        }
+        path.reverse();
+        NodePath(path)
     }
-    pub fn mainline(&self) -> Mainline<NodeRef> {
-        Mainline {start: self.0.clone(), f: |node| node}
+    /// Move-number address of this node, see `Game::address`.
+    pub fn address(&self) -> String {
+        self.game.address(&self.path())
     }
-    pub fn mainline_moves(&self) -> Mainline<Option<Move>> {
-        Mainline {start: self.0.clone(), f: |node| node.borrow().m}
+    pub fn parent_board(&self) -> Option<Board> {
+        self.parent().map(|parent| parent.board())
     }
-    pub fn add_line<T>(&self, moves: T, comment: &str, starting_comment: &str, nags: HashSet<u64>) -> Node where T: IntoIterator<Item = Move> {
-        let mut node = Node(self.0.clone()); 
-        for m in moves {
-            node = node.add_variation(m, "", starting_comment, HashSet::new());
-        }
-        node
+    pub fn san(&self) -> Option<String> {
+        let m = self.m()?;
+        Some(self.parent_board()?.san(m))
     }
-    pub fn board(&self) -> Board {
-        let mut stack: Vec<Move> = Vec::new();
-        let mut node = self.0.clone();
-        while node.borrow().m.is_some() && node.borrow().parent.is_some() {
-            stack.push(node.borrow().m.clone().unwrap());
-            node = node.clone().borrow().parent.as_ref().unwrap().clone();
-        }
-
-        let mut board= Board::new(None);
-        board.reset();
-        while !stack.is_empty() {
-            board.push(stack.pop().unwrap());
-        }
-        board
+    pub fn uci(&self) -> Option<String> {
+        self.m().map(|m| m.uci())
     }
-    pub fn index(&self, m: MoveRepr) -> Rc<RefCell<NodeBase>> {
+    pub fn index(&self, m: MoveRepr) -> NodeId {
+        let variations = &self.base().variations;
         match m {
             MoveRepr::Int(x) => {
-                self.0.borrow().variations[x].clone()
+                variations[x]
             },
-            MoveRepr::NodeRef(n) => {
-                let mut retval = None;
-                for variation in self.0.borrow().variations.clone() {
-                    if variation == n {
-                        retval = Some(variation);
-                    }
-                }
-                assert!(retval.is_some());
-                retval.unwrap()
+            MoveRepr::Node(n) => {
+                assert!(variations.contains(&n), "{:?} is not a variation of {:?}", n, self.id);
+                n
             },
             MoveRepr::Move(m) => {
-                let mut retval = None;
-                for variation in self.0.borrow().variations.clone() {
-                    if variation.borrow().m.unwrap() == m {
-                        retval = Some(variation);
-                    }
-                }
-                assert!(retval.is_some());
-                retval.unwrap()
+                let retval = variations.iter().find(|id| self.game[**id].m == Some(m));
+                *retval.expect("no variation for the given move")
             }
         }
     }
-    fn _accept(&self, parent_board: &mut Board, mut visitor: &mut GameBuilder, sidelines: bool) {
-        let mut stack = Vec::from([Rc::new(RefCell::new(AcceptFrame::new(self.0.clone(), false, sidelines)))]); 
-
-        while !stack.is_empty() {
-            let topref = stack.last().unwrap().clone();
-            let mut top = topref.borrow_mut();
+    fn _accept<V: Visitor>(&self, parent_board: &mut Board, visitor: &mut V, sidelines: bool) {
+        let mut stack = Vec::from([AcceptFrame::new(*self, false, sidelines)]);
 
+        while let Some(top) = stack.last_mut() {
             if top.in_variation {
                 top.in_variation = false;
                 visitor.end_variation();
             }
-            if top.state == "pre" {
-                top.node.borrow().accept_node(parent_board, visitor);
-                top.state = "variations".to_string();
-            }    
-            else if top.state == "variations" {
-                let var_opt = top.variations.iter().next();
-
-                if let Some(variation) = var_opt {
-                    if visitor.begin_variation() == () {
-                        stack.push(Rc::new(RefCell::new(AcceptFrame::new(variation.clone(), true, false))));
+            match top.state {
+                AcceptState::Pre => {
+                    self.game.node(top.node).accept_node(parent_board, visitor);
+                    top.state = AcceptState::Variations;
+                },
+                AcceptState::Variations => {
+                    if let Some(variation) = top.variations.pop_front() {
+                        top.in_variation = true;
+                        if !is_skip(visitor.begin_variation()) {
+                            stack.push(AcceptFrame::new(self.game.node(variation), true, false));
+                        }
                     }
-                    top.in_variation = true;
-                }
-                else {
-                    if !top.node.borrow().variations.is_empty() {
-                        parent_board.push(top.node.borrow().m.unwrap());
-                        stack.push(Rc::new(RefCell::new(AcceptFrame::new(top.node.borrow().variations.front().unwrap().clone(), false, true))));
-                        top.state = "post".to_string();
+                    else {
+                        let node = self.game.node(top.node);
+                        match node.next() {
+                            Some(next) => {
+                                parent_board.push(node.m().unwrap());
+                                top.state = AcceptState::Post;
+                                stack.push(AcceptFrame::new(next, false, true));
+                            },
+                            None => { top.state = AcceptState::End; }
+                        }
                     }
-                    else { top.state = "end".to_string(); }
-                }
-            }
-            else if top.state == "post" {
-                parent_board.pop();
-                top.state = "end".to_string();
+                },
+                AcceptState::Post => {
+                    parent_board.pop();
+                    top.state = AcceptState::End;
+                },
+                AcceptState::End => { stack.pop(); }
             }
-            else { stack.pop(); }
-
         }
     }
-    pub fn accept(&mut self, mut visitor: GameBuilder) -> GameBuilder {
-        let mut parent_board = Node(self.0.borrow().parent.as_ref().unwrap().clone()).board();
-        self._accept(&mut parent_board, &mut visitor, false);
-        visitor
+    pub fn accept<V: Visitor>(&self, visitor: &mut V) -> V::Output {
+        let mut parent_board = self.parent().expect("cannot accept the root node").board();
+        self._accept(&mut parent_board, visitor, false);
+        visitor.result()
     }
-}
-impl NodeBase {
-    fn accept_node(&self, mut parent_board: &mut Board, mut visitor: &mut GameBuilder) {
-        if !self.starting_comment.is_empty() {
-            visitor.visit_comment(&self.starting_comment);
+    fn accept_node<V: Visitor>(&self, parent_board: &mut Board, visitor: &mut V) {
+        let base = self.base();
+        if !base.starting_comment.is_empty() {
+            visitor.visit_comment(&base.starting_comment);
         }
-        visitor.visit_move(&parent_board, self.m.unwrap());
+        visitor.visit_move(&parent_board, base.m.unwrap());
 
-        parent_board.push(self.m.unwrap());
-        visitor.visit_board(&Node(self.parent.as_ref().unwrap().clone()).board());
+        parent_board.push(base.m.unwrap());
+        visitor.visit_board(parent_board);
         parent_board.pop();
 
-        let mut nags =  self.nags.iter().collect::<Vec<&u64>>();
+        let mut nags =  base.nags.iter().collect::<Vec<&u64>>();
         nags.sort();
         for nag in nags {
             visitor.visit_nag(*nag);
         }
-        if !self.comment.is_empty() {
-            visitor.visit_comment(&self.comment);
+        if !base.comment.is_empty() {
+            visitor.visit_comment(&base.comment);
+        }
+    }
+    pub fn clock(&self) -> Option<Duration> {
+        let caps = CLOCK_REGEX.captures(self.comment())?;
+        let hours: u64 = caps[1].parse().ok()?;
+        let minutes: u64 = caps[2].parse().ok()?;
+        let seconds: f64 = caps[3].parse().ok()?;
+        let whole = hours.checked_mul(3600)?.checked_add(minutes.checked_mul(60)?)?;
+        Duration::from_secs(whole).checked_add(Duration::try_from_secs_f64(seconds).ok()?)
+    }
+    /// %eval is always given from white's point of view, except for `#0` which
+    /// is taken relative to the side to move, i.e. the side that got mated.
+    pub fn eval(&self) -> Option<PovScore> {
+        let caps = EVAL_REGEX.captures(self.comment())?;
+        let depth = caps.get(3).and_then(|d| d.as_str().parse().ok());
+        if let Some(mate) = caps.get(1) {
+            let mate: i32 = mate.as_str().parse().ok()?;
+            if mate == 0 {
+                return Some(PovScore { relative: Score::Mate(0), turn: self.board().turn, depth });
+            }
+            return Some(PovScore { relative: Score::Mate(mate), turn: WHITE, depth });
+        }
+        let pawns: f64 = caps[2].parse().ok()?;
+        Some(PovScore { relative: Score::Cp((pawns * 100.0).round() as i32), turn: WHITE, depth })
+    }
+    pub fn arrows(&self) -> Vec<Arrow> {
+        let mut arrows = Vec::new();
+        for caps in ARROWS_REGEX.captures_iter(self.comment()) {
+            for group in caps[1].split(',') {
+                if let Some(arrow) = Arrow::from_pgn(group) {
+                    arrows.push(arrow);
+                }
+            }
+        }
+        arrows
+    }
+}
+/// Mutable access to a node, used to edit the tree of a `Game`.
+pub struct NodeMut<'a> {
+    game: &'a mut Game,
+    id: NodeId
+}
+impl<'a> NodeMut<'a> {
+    fn base(&mut self) -> &mut NodeBase {
+        &mut self.game.nodes[self.id.0]
+    }
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+    pub fn as_node(&self) -> Node<'_> {
+        self.game.node(self.id)
+    }
+    pub fn set_comment(&mut self, comment: &str) {
+        self.base().comment = String::from(comment);
+    }
+    pub fn set_starting_comment(&mut self, starting_comment: &str) {
+        self.base().starting_comment = String::from(starting_comment);
+    }
+    pub fn nags_mut(&mut self) -> &mut HashSet<u64> {
+        &mut self.base().nags
+    }
+    pub fn promote_to_main(&mut self, m: MoveRepr) {
+        let variation = self.as_node().index(m);
+        let variations = &mut self.base().variations;
+        let index= variations.iter().position(|x| *x == variation).unwrap();
+        let temp = variations.remove(index).unwrap();
+        variations.push_front(temp);
+    }
+    pub fn promote(&mut self, m: MoveRepr) {
+        let variation = self.as_node().index(m);
+        let variations = &mut self.base().variations;
+        let index= variations.iter().position(|x| *x == variation).unwrap();
+        if index > 0 {
+            variations.swap(index - 1, index);
+        }
+    }
+    pub fn demote(&mut self, m: MoveRepr) {
+        let variation = self.as_node().index(m);
+        let variations = &mut self.base().variations;
+        let index= variations.iter().position(|x| *x == variation).unwrap();
+        if index < variations.len() - 1 {
+            variations.swap(index + 1, index)
+        }
+    }
+    pub fn remove_variation(&mut self, m: MoveRepr) {
+        let variation = self.as_node().index(m);
+        let variations = &mut self.base().variations;
+        let index= variations.iter().position(|x| *x == variation).unwrap();
+        variations.remove(index);
+    }
+    pub fn add_variation(&mut self, m: Move, comment: &str, starting_comment: &str, nags: HashSet<u64>) -> NodeId {
+        self.game.sync_plies();
+        let id = NodeId(self.game.nodes.len());
+        let ply = self.base().ply + 1;
+        let mut node = NodeBase::new(Some(self.id), Some(m), ply);
+        node.comment = String::from(comment);
+        node.starting_comment = String::from(starting_comment);
+        node.nags = nags;
+        self.game.nodes.push(node);
+        self.base().variations.push_back(id);
+        id
+    }
+    pub fn add_main_variation(&mut self, m: Move, comment: &str, nags: HashSet<u64>) -> NodeId {
+        let id = self.add_variation(m, comment, "", nags);
+        self.promote_to_main(MoveRepr::Node(id));
+        id
+    }
+    /// Adds a line of moves below this node. The starting comment goes to the
+    /// first new node, the comment and NAGs to the last one.
+    pub fn add_line<T>(&mut self, moves: T, comment: &str, starting_comment: &str, nags: HashSet<u64>) -> NodeId where T: IntoIterator<Item = Move> {
+        let mut node = self.id;
+        let mut starting_comment = starting_comment;
+        for m in moves {
+            node = self.game.node_mut(node).add_variation(m, "", starting_comment, HashSet::new());
+            starting_comment = "";
+        }
+        let mut last = self.game.node_mut(node);
+        last.set_comment(comment);
+        last.nags_mut().extend(nags);
+        node
+    }
+    pub fn set_clock(&mut self, clock: Option<Duration>) {
+        let clk = match clock {
+            Some(clock) => {
+                // Round to milliseconds first, so that 59.9996 s carries over
+                // into the minutes.
+                let millis = (clock.as_nanos() + 500_000) / 1_000_000;
+                let seconds = format!("{:02}.{:03}", millis / 1000 % 60, millis % 1000);
+                let seconds = seconds.trim_end_matches('0').trim_end_matches('.');
+                format!("[%clk {}:{:02}:{}]", millis / 3_600_000, millis / 60_000 % 60, seconds)
+            },
+            None => String::new()
+        };
+        let comment = condense_command(&self.base().comment, &CLOCK_REGEX, &clk);
+        self.base().comment = comment;
+    }
+    pub fn set_eval(&mut self, score: Option<PovScore>) {
+        let eval = match score {
+            Some(score) => {
+                let value = match score.white() {
+                    Score::Cp(cp) => format!("{:.2}", cp as f64 / 100.0),
+                    Score::Mate(mate) => format!("#{}", mate),
+                };
+                match score.depth {
+                    Some(depth) => format!("[%eval {},{}]", value, depth),
+                    None => format!("[%eval {}]", value)
+                }
+            },
+            None => String::new()
+        };
+        let comment = condense_command(&self.base().comment, &EVAL_REGEX, &eval);
+        self.base().comment = comment;
+    }
+    /// Squares (`tail == head`) are written as %csl, everything else as %cal.
+    pub fn set_arrows<T>(&mut self, arrows: T) where T: IntoIterator<Item = Arrow> {
+        let mut csl = Vec::new();
+        let mut cal = Vec::new();
+        for arrow in arrows {
+            if arrow.tail == arrow.head { csl.push(arrow.pgn()); } else { cal.push(arrow.pgn()); }
+        }
+        let mut comment = self.base().comment.clone();
+        while ARROWS_REGEX.is_match(&comment) {
+            comment = condense_command(&comment, &ARROWS_REGEX, "");
+        }
+        let mut prefix = String::new();
+        if !csl.is_empty() {
+            prefix.push_str(&format!("[%csl {}]", csl.join(",")));
+        }
+        if !cal.is_empty() {
+            prefix.push_str(&format!("[%cal {}]", cal.join(",")));
+        }
+        if !prefix.is_empty() && !comment.is_empty() {
+            prefix.push(' ');
+        }
+        prefix.push_str(&comment);
+        self.base().comment = prefix;
+    }
+}
+/// Replaces the first occurrence of a comment command, or appends it if there is none.
+/// An empty replacement removes the command with one of the separators around
+/// it, if it is separated on both sides, so that its neighbours are neither
+/// glued together nor left with a double space.
+fn condense_command(text: &str, command: &Regex, replacement: &str) -> String {
+    match command.find(text) {
+        Some(found) => {
+            let mut before = &text[..found.start()];
+            let mut after = &text[found.end()..];
+            if replacement.is_empty() {
+                match (before.chars().next_back(), after.chars().next()) {
+                    (Some(b), Some(a)) if b.is_whitespace() && a.is_whitespace() => after = &after[a.len_utf8()..],
+                    (None, Some(a)) if a.is_whitespace() => after = &after[a.len_utf8()..],
+                    (Some(b), None) if b.is_whitespace() => before = &before[..before.len() - b.len_utf8()],
+                    _ => {}
+                }
+            }
+            format!("{}{}{}", before, replacement, after)
+        },
+        None if replacement.is_empty() => text.to_string(),
+        None => {
+            let mut result = text.to_string();
+            if !result.is_empty() && !result.ends_with(' ') && !result.ends_with('\n') {
+                result.push(' ');
+            }
+            result.push_str(replacement);
+            result
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Score {
+    Cp(i32), Mate(i32)
+}
+impl std::ops::Neg for Score {
+    type Output = Score;
+    fn neg(self) -> Score {
+        match self {
+            Score::Cp(cp) => Score::Cp(-cp),
+            Score::Mate(mate) => Score::Mate(-mate)
+        }
+    }
+}
+/// A score relative to `turn`, optionally with the search depth it was reported at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PovScore {
+    pub relative: Score,
+    pub turn: Color,
+    pub depth: Option<u32>
+}
+impl PovScore {
+    pub fn pov(&self, color: Color) -> Score {
+        if color == self.turn { self.relative } else { -self.relative }
+    }
+    pub fn white(&self) -> Score {
+        self.pov(WHITE)
+    }
+    pub fn black(&self) -> Score {
+        self.pov(BLACK)
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrowColor {
+    Red, Green, Yellow, Blue
+}
+impl ArrowColor {
+    fn from_char(c: char) -> Option<ArrowColor> {
+        match c {
+            'R' => Some(ArrowColor::Red),
+            'G' => Some(ArrowColor::Green),
+            'Y' => Some(ArrowColor::Yellow),
+            'B' => Some(ArrowColor::Blue),
+            _ => None
+        }
+    }
+    fn char(&self) -> char {
+        match self {
+            ArrowColor::Red => 'R',
+            ArrowColor::Green => 'G',
+            ArrowColor::Yellow => 'Y',
+            ArrowColor::Blue => 'B'
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arrow {
+    pub tail: u8,
+    pub head: u8,
+    pub color: ArrowColor
+}
+impl Arrow {
+    pub fn new(tail: u8, head: u8, color: ArrowColor) -> Arrow {
+        Arrow { tail, head, color }
+    }
+    /// Parses `Ga1` (a square) or `Ra1h8` (an arrow).
+    pub fn from_pgn(s: &str) -> Option<Arrow> {
+        let color = ArrowColor::from_char(s.chars().next()?)?;
+        match s.len() {
+            3 => {
+                let square = parse_square(&s[1..3]);
+                Some(Arrow { tail: square, head: square, color })
+            },
+            5 => Some(Arrow { tail: parse_square(&s[1..3]), head: parse_square(&s[3..5]), color }),
+            _ => None
+        }
+    }
+    pub fn pgn(&self) -> String {
+        if self.tail == self.head {
+            format!("{}{}", self.color.char(), SQUARE_NAMES[self.tail as usize])
+        } else {
+            format!("{}{}{}", self.color.char(), SQUARE_NAMES[self.tail as usize], SQUARE_NAMES[self.head as usize])
         }
     }
 }
 #[derive(Clone)]
 pub enum MoveRepr {
-    Int(usize), Move(Move), NodeRef(NodeRef)
+    Int(usize), Move(Move), Node(NodeId)
+}
+/// Address of a node as the variation indices taken from the root. Unlike a
+/// `NodeId` it does not depend on the order nodes were added in, so it stays
+/// valid when the game is written out and read back. Displays as `[0,0,1,0]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodePath(pub Vec<usize>);
+impl std::fmt::Display for NodePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let indices: Vec<String> = self.0.iter().map(|index| index.to_string()).collect();
+        write!(f, "[{}]", indices.join(","))
+    }
+}
+impl std::str::FromStr for NodePath {
+    type Err = NodePathError;
+    fn from_str(s: &str) -> Result<NodePath, NodePathError> {
+        let inner = s.trim().strip_prefix('[').and_then(|s| s.strip_suffix(']'))
+            .ok_or_else(|| NodePathError::Invalid(s.to_string()))?;
+        inner.split(',').map(str::trim).filter(|index| !index.is_empty())
+            .map(|index| index.parse().map_err(|_| NodePathError::Invalid(s.to_string())))
+            .collect::<Result<Vec<usize>, _>>()
+            .map(NodePath)
+    }
 }
-pub struct Mainline<T> {
-    start: NodeRef,
-    f: fn(n: NodeRef) ->  T,
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum NodePathError {
+    #[error("invalid node path: {0}")]
+    Invalid(String),
 }
-impl<T> Mainline<T> {
-    fn new(start: NodeRef, f: fn(n: NodeRef) -> T) -> Mainline<T>{
-        Mainline{start, f}
+pub struct Mainline<'a, T> {
+    node: Option<Node<'a>>,
+    f: fn(n: Node<'a>) ->  T,
+}
+impl<'a, T> Mainline<'a, T> {
+    fn new(start: Node<'a>, f: fn(n: Node<'a>) -> T) -> Mainline<'a, T>{
+        Mainline{node: Some(start), f}
     }
-    fn bool(&self) -> bool {
-        !self.start.borrow().variations.is_empty()
+    pub fn bool(&self) -> bool {
+        self.node.map_or(false, |node| !node.is_end())
     }
-    fn iter(&'_ self) -> impl Iterator<Item = T> + '_ {
-        gen_iter!({
-            let mut node = self.start.clone();
-            while !node.borrow().variations.is_empty() {
-                node = node.clone().borrow().variations[0].clone();
-                yield (self.f)(node.clone());
-            }
-        })
-        
+}
+impl<'a, T> Iterator for Mainline<'a, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        let next = self.node?.next();
+        self.node = next;
+        next.map(self.f)
     }
 }
 #[derive(Debug)]
@@ -425,6 +854,16 @@ impl Headers {
         }
         if let Some(a) = self.others.get(key) { Some(a) } else { None }
     }
+    /// All headers as `(tag, value)`: the Seven Tag Roster in its order, then
+    /// the other tags sorted by name.
+    pub fn items(&self) -> Vec<(&str, &str)> {
+        let mut others: Vec<(&str, &str)> = self.others.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        others.sort();
+        TAG_ROASTER.iter()
+            .filter_map(|key| self.tag_roaster.get(*key).map(|value| (*key, value.as_str())))
+            .chain(others)
+            .collect()
+    }
     pub fn iter(&self) -> impl Iterator<Item = &str> {
         gen_iter!({
             for key in TAG_ROASTER {
@@ -435,22 +874,25 @@ impl Headers {
         })
     }
 }
+#[derive(PartialEq, Clone, Copy)]
 pub enum SkipType {
     SKIP = 0
 }
 
+enum AcceptState {
+    Pre, Variations, Post, End
+}
 struct AcceptFrame {
-    state: String,
-    node: NodeRef,
+    state: AcceptState,
+    node: NodeId,
     in_variation: bool,
-    variations: VecDeque<NodeRef>,
-
+    variations: VecDeque<NodeId>,
 }
 impl AcceptFrame{
-    fn new(node: NodeRef, in_variation: bool, sidelines: bool) -> AcceptFrame {
-        let mut frame = AcceptFrame { state: "pre".to_string(), node: node.clone(), in_variation, variations: VecDeque::new()};
+    fn new(node: Node, in_variation: bool, sidelines: bool) -> AcceptFrame {
+        let mut frame = AcceptFrame { state: AcceptState::Pre, node: node.id(), in_variation, variations: VecDeque::new()};
         if sidelines {
-            let mut slice = node.borrow().parent.as_ref().unwrap().borrow().variations.clone();
+            let mut slice = node.parent().unwrap().base().variations.clone();
             slice.pop_front();
             frame.variations = slice; 
         }
@@ -458,124 +900,202 @@ impl AcceptFrame{
         frame
     }
 }
-pub trait BaseVisitor {
-    fn begin_game(&self) -> Option<SkipType>;
-    fn begin_headers(&self) -> Option<Headers>;
-    fn visit_header(&self, tagname: &str, tagvalue: &str);
-    fn end_headers(&self) -> Option<SkipType>;
-    fn parse_san(&self, board: Board, san: &str) -> Move;
-    fn visit_move(&self, board: Board, m: Move);
-    fn visit_board(&self, board: Board);
-    fn visit_comment(&self, comment: &str);
-    fn visit_nag(&self, nag: u64);
-    fn begin_variation(&self) -> Option<SkipType>;
-    fn end_variation(&self);
-    fn visit_result(&self, result: &str);
-    fn end_game(&self);
-    fn result<T>(&self) -> T;
-    fn handle_error(&self, error: &str);
+/// Receives the events of a game, either while it is being parsed by
+/// `read_game` or while an existing tree is walked by `Node::accept`.
+/// Every method but `result` has a no-op default, so custom visitors
+/// only need to implement the events they care about.
+pub trait Visitor {
+    type Output;
+    fn begin_game(&mut self) -> Option<SkipType> { None }
+    /// Byte offset of the first line of the game in the underlying reader.
+    fn visit_offset(&mut self, _offset: u64) {}
+    fn begin_headers(&mut self) {}
+    fn visit_header(&mut self, _tagname: &str, _tagvalue: &str) {}
+    fn end_headers(&mut self) -> Option<SkipType> { None }
+    fn parse_san(&mut self, board: &Board, san: &str) -> Result<Move, SanError> {
+        board.parse_san(san)
+    }
+    fn visit_move(&mut self, _board: &Board, _m: Move) {}
+    fn visit_board(&mut self, _board: &Board) {}
+    fn visit_comment(&mut self, _comment: &str) {}
+    fn visit_nag(&mut self, _nag: u64) {}
+    /// Every variation is closed with `end_variation`, skipped ones too.
+    fn begin_variation(&mut self) -> Option<SkipType> { None }
+    fn end_variation(&mut self) {}
+    fn visit_result(&mut self, _result: &str) {}
+    fn end_game(&mut self) {}
+    fn result(&mut self) -> Self::Output;
+    /// Called for moves that could not be parsed. Returning the error aborts the
+    /// game, returning `Ok` skips the rest of the current variation instead.
+    fn handle_error(&mut self, error: ParsingError) -> Result<(), ParsingError> {
+        Err(error)
+    }
 }
 pub struct GameBuilder {
     game: Game,
-    variation_stack: Vec<NodeRef>,
+    variation_stack: Vec<NodeId>,
     starting_comment: String,
-    in_variation: bool
+    in_variation: bool,
+    lenient: bool
 }
 impl GameBuilder {
-    fn new() -> GameBuilder {
+    pub fn new() -> GameBuilder {
+        let game = Game::new();
+        let variation_stack = Vec::from([Game::ROOT]);
         GameBuilder {
-            game: Game{root: Node::new(""), headers: Headers::new(None)},
-            variation_stack: Vec::new(),
+            game,
+            variation_stack,
             starting_comment: String::new(),
-            in_variation: false
+            in_variation: false,
+            lenient: false
         }
     }
+    /// A builder that records invalid moves in `Game::errors` and keeps
+    /// parsing instead of failing the whole game.
+    pub fn lenient() -> GameBuilder {
+        GameBuilder { lenient: true, ..GameBuilder::new() }
+    }
+}
+impl Visitor for GameBuilder {
+    type Output = Game;
     fn begin_game(&mut self) -> Option<SkipType>{
-        self.game = Game{root: Node::new(""), headers: Headers::new(None)};
+        self.game = Game::new();
         self.variation_stack = Vec::new();
-        self.variation_stack.push(self.game.root.0.clone());
+        self.variation_stack.push(Game::ROOT);
         self.starting_comment = String::new();
         self.in_variation = false;
         None
     }
-    fn new_and_begin() -> GameBuilder {
-        let game = Game{root: Node::new(""), headers: Headers::new(None)};
-        let mut variation_stack = Vec::new();
-        variation_stack.push(game.root.0.clone());
-        let starting_comment = String::new();
-        let in_variation = false;
-        GameBuilder {
-            game,
-            variation_stack,
-            starting_comment,
-            in_variation
-        }
-    }
-    fn begin_headers(&self) -> Option<&Headers>{
-        Some(&self.game.headers)
-    }
     fn visit_header(&mut self, tagname: &str, tagvalue: &str){
         self.game.headers.set(tagname, tagvalue)
     }
-    // fn end_headers(&self) -> Option<SkipType>;
-    // fn parse_san(&self, board: Board, san: &str) -> Move;
-    fn visit_nag(&self, nag: u64) -> Option<SkipType> {
-        self.variation_stack.last().unwrap().borrow_mut().nags.insert(nag);
-        None
+    fn visit_nag(&mut self, nag: u64) {
+        let top = *self.variation_stack.last().unwrap();
+        self.game.node_mut(top).nags_mut().insert(nag);
     }
-    fn begin_variation(&mut self){
-        if let Some(parent) = self.variation_stack.last().cloned().unwrap().borrow().parent.clone() {
-            self.variation_stack.push(parent.clone());
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        if let Some(parent) = self.game[*self.variation_stack.last().unwrap()].parent {
+            self.variation_stack.push(parent);
             self.in_variation = false;
         }
         else {
             panic!("begin variation called, but root node on top of stack");
         }
+        None
     }
     fn end_variation(&mut self) {
         self.variation_stack.pop();
     }
     fn visit_result(&mut self, result: &str) {
-        if self.game.headers.get("Result").is_none(){
+        if self.game.headers.get("Result").map_or(true, |r| r == "*") {
             self.game.headers.set("Result", result);
         }
     }
     fn visit_comment(&mut self, comment: &str){
-        if self.in_variation || (self.variation_stack.last().unwrap().borrow().parent.is_some() && Node(self.variation_stack.last().unwrap().clone()).is_end()){
-            self.starting_comment = String::from(self.variation_stack.last().unwrap().borrow().starting_comment.clone());
+        let top = self.game.node(*self.variation_stack.last().unwrap());
+        // Comments in the middle of a variation or before the first move belong
+        // to the current node, everything else starts the next move.
+        if self.in_variation || (top.parent().is_none() && top.is_end()) {
+            let joined = format!("{}\n{}", top.comment(), comment);
+            let id = top.id();
+            self.game.node_mut(id).set_comment(joined.trim());
+        }
+        else {
+            let joined = format!("{}\n{}", self.starting_comment, comment);
+            self.starting_comment = joined.trim().to_string();
         }
-        self.starting_comment.push('\n');
-        self.starting_comment.push_str(comment.trim());
-
     }
-    fn visit_move(&mut self, board: &Board, m: Move){
+    fn visit_move(&mut self, _board: &Board, m: Move){
         let last_copy = self.variation_stack.pop().unwrap();
-        self.variation_stack.push(Node(last_copy).add_variation(m, "", "", HashSet::new()).0);
-
-        self.variation_stack.last().unwrap().borrow_mut().starting_comment = self.starting_comment.clone();
-        self.starting_comment = "".to_string();
+        let starting_comment = std::mem::take(&mut self.starting_comment);
+        self.variation_stack.push(self.game.node_mut(last_copy).add_variation(m, "", &starting_comment, HashSet::new()));
         self.in_variation = true;
     }
-    fn visit_board(&self, board: &Board){}
-    fn end_game(&self){}
-    pub fn result(&self) -> &Game {
-        &self.game
+    fn handle_error(&mut self, error: ParsingError) -> Result<(), ParsingError> {
+        if !self.lenient {
+            return Err(error);
+        }
+        self.game.errors.push(error);
+        Ok(())
     }
-    pub fn parse_san(&self, board: &Board, san: &str) -> Move {
-        board.parse_san(san)
+    fn result(&mut self) -> Game {
+        std::mem::replace(&mut self.game, Game::new())
+    }
+}
+/// Collects only the headers and the byte offset of each game, the
+/// movetext is skipped without parsing any moves.
+pub struct HeadersOnly {
+    offset: u64,
+    headers: Headers
+}
+pub struct GameHeaders {
+    pub offset: u64,
+    pub headers: Headers
+}
+impl HeadersOnly {
+    pub fn new() -> HeadersOnly {
+        HeadersOnly { offset: 0, headers: Headers::new(None) }
+    }
+}
+impl Visitor for HeadersOnly {
+    type Output = GameHeaders;
+    fn begin_game(&mut self) -> Option<SkipType> {
+        self.headers = Headers::new(None);
+        None
+    }
+    fn visit_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+    fn visit_header(&mut self, tagname: &str, tagvalue: &str) {
+        self.headers.set(tagname, tagvalue);
+    }
+    fn end_headers(&mut self) -> Option<SkipType> {
+        Some(SkipType::SKIP)
+    }
+    fn result(&mut self) -> GameHeaders {
+        GameHeaders { offset: self.offset, headers: std::mem::replace(&mut self.headers, Headers::new(None)) }
     }
-    // fn handle_error(&self, error: &str);
 }
-pub struct BufReader {
-    reader: io::BufReader<File>,
+/// Line reader over any `BufRead` source (files, stdin, in-memory strings,
+/// decompressed streams) that keeps track of where each game starts.
+pub struct BufReader<R: BufRead = io::BufReader<File>> {
+    reader: R,
+    offset: u64,
+    line_start: u64,
+    line_number: u64,
+    games: u64,
 }
 
-impl BufReader {
+impl BufReader<io::BufReader<File>> {
     pub fn open(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
         let file = File::open(path)?;
-        let reader = io::BufReader::new(file);
-
-        Ok(Self { reader })
+        Ok(BufReader::new(io::BufReader::new(file)))
+    }
+}
+impl<R: BufRead> BufReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, offset: 0, line_start: 0, line_number: 0, games: 0 }
+    }
+    /// A reader whose first byte is at `offset` in a larger file, so that
+    /// reported offsets stay valid for that file.
+    pub fn at_offset(reader: R, offset: u64) -> Self {
+        Self { reader, offset, line_start: offset, line_number: 0, games: 0 }
+    }
+    /// Byte offset of the next unread line.
+    pub fn tell(&self) -> u64 {
+        self.offset
+    }
+    /// Byte offset at which the most recently read line starts.
+    pub fn line_start(&self) -> u64 {
+        self.line_start
+    }
+    /// 1-based number of the most recently read line.
+    pub fn line_number(&self) -> u64 {
+        self.line_number
+    }
+    /// Number of games started so far.
+    pub fn games(&self) -> u64 {
+        self.games
     }
 
     pub fn read_line<'buf>(
@@ -583,155 +1103,528 @@ impl BufReader {
         buffer: &'buf mut String,
     ) -> Option<io::Result<&'buf mut String>> {
         buffer.clear();
+        self.line_start = self.offset;
 
-        self.reader
-            .read_line(buffer)
-            .map(|u| if u == 0 { None } else { Some(buffer) })
-            .transpose()
+        match self.reader.read_line(buffer) {
+            Ok(0) => None,
+            Ok(u) => {
+                self.offset += u as u64;
+                self.line_number += 1;
+                Some(Ok(buffer))
+            },
+            Err(e) => Some(Err(e))
+        }
+    }
+}
+impl<R: BufRead + Seek> BufReader<R> {
+    /// Jumps to `offset`, e.g. the start of a game taken from an index.
+    /// Line numbers restart at 1 from there.
+    pub fn seek(&mut self, offset: u64) -> io::Result<()> {
+        self.reader.seek(io::SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.line_start = offset;
+        self.line_number = 0;
+        Ok(())
     }
 }
 fn isspace(s: &str) -> bool {
     s.chars().all(|x| x.is_whitespace())
 }
-fn read_line_or_empty<'buf>(handle: &mut BufReader, buffer: &'buf mut String ) -> &'buf str {
-    match handle.read_line(buffer) {Some(s) => {s.expect("error while reading line")}, None => {""}}
+fn read_line_or_empty<R: BufRead>(handle: &mut BufReader<R>, buffer: &mut String) -> io::Result<String> {
+    match handle.read_line(buffer) {Some(s) => {Ok(s?.clone())}, None => {Ok(String::new())}}
 }
-fn read_until_end_of_game<'buf>(handle: &mut BufReader, buffer: &'buf mut String) {
-    let mut line = read_line_or_empty(handle, buffer);
-    while !isspace(line){
-        line = read_line_or_empty(handle, buffer);
+/// Fast path for skipped games: only tracks comments so that blank lines
+/// inside `{ }` do not end the game early, no SAN is parsed.
+fn skip_movetext<R: BufRead>(handle: &mut BufReader<R>, buffer: &mut String, mut line: String) -> io::Result<()> {
+    let mut in_comment = false;
+    while !line.is_empty() {
+        if !in_comment {
+            if isspace(&line) { break; }
+            if line.starts_with("%") {
+                line = read_line_or_empty(handle, buffer)?;
+                continue;
+            }
+        }
+        for token in SKIP_MOVETEXT_REGEX.find_iter(&line) {
+            match token.as_str() {
+                "{" => { in_comment = true; },
+                "}" => { in_comment = false; },
+                _ if !in_comment => { break; },
+                _ => {}
+            }
+        }
+        line = read_line_or_empty(handle, buffer)?;
     }
+    Ok(())
 }
-pub fn read_game(mut handle: &mut BufReader) -> Result<Rc<RefCell<GameBuilder>>, ParsingError> {
-    let mut visitor = Rc::new(RefCell::new(GameBuilder::new_and_begin()));
-
+fn is_skip(skip: Option<SkipType>) -> bool {
+    matches!(skip, Some(SkipType::SKIP))
+}
+pub fn read_game<V: Visitor, R: BufRead>(handle: &mut BufReader<R>, visitor: &mut V) -> Result<V::Output, ParsingError> {
     let mut found_game = false;
     let mut skipping_game = false;
-    // let mut headers: Option<&Headers> = None;
+    let mut fen: Option<String> = None;
 
     let mut buffer = String::new();
 
-    let mut board: Board = Board::new(None);
-
     let mut line = match handle.read_line(&mut buffer) {
-        Some(line) => { line.unwrap().trim_start_matches("\u{feff}")}
-        None => { return Err(ParsingError::ReadLineError);}
+        Some(line) => { line?.trim_start_matches("\u{feff}").to_string()}
+        None => { return Err(ParsingError::Eof);}
     };
 
-    //let mut line = handle.read_line(&mut buffer).expect("Couldnt read first line of the file")?.trim_start_matches("\u{feff}");
-
-    while isspace(line) || line.starts_with("%") || line.starts_with(";") {
-        //line = handle.read_line(&mut buffer).expect("error while reading the line").unwrap();
+    while isspace(&line) || line.starts_with("%") || line.starts_with(";") {
         line = match handle.read_line(&mut buffer) {
-            Some(l) => {l.unwrap()},
-            _ => { println!("i: {}", unsafe{crate::I}); return Err(ParsingError::InvalidMoveError);}
+            Some(l) => {l?.clone()},
+            None => { return Err(ParsingError::Eof);}
         }
     }
-    
+
+    let game_index = handle.games;
+    handle.games += 1;
     let mut consecutive_empty_lines = 0;
 
     while !line.is_empty() {
         if line.starts_with("%") || line.starts_with(";") {
-            line = read_line_or_empty(&mut handle, &mut buffer);
+            line = read_line_or_empty(handle, &mut buffer)?;
             continue;
         }
 
-        if consecutive_empty_lines < 1 && isspace(line) {
+        if consecutive_empty_lines < 1 && isspace(&line) {
             consecutive_empty_lines += 1;
-            line = read_line_or_empty(&mut handle, &mut buffer);
+            line = read_line_or_empty(handle, &mut buffer)?;
             continue;
         }
 
         if !found_game {
             found_game = true;
-            skipping_game = false;
-
-            // if !skipping_game {
-            //     headers = Some(&visitor.borrow().game.headers);
-            // }
+            skipping_game = is_skip(visitor.begin_game());
+            visitor.visit_offset(handle.line_start());
+            if !skipping_game {
+                visitor.begin_headers();
+            }
         }
         if !line.starts_with("[") { break; }
 
         consecutive_empty_lines = 0;
 
         if !skipping_game {
-            let tag_match = TAG_REGEX.captures(line);
-            if let Some(tag) = tag_match {
-                visitor.borrow_mut().visit_header(&tag[1], &tag[2]);
+            if let Some(tag) = TAG_REGEX.captures(&line) {
+                visitor.visit_header(&tag[1], &tag[2]);
+                if &tag[1] == "FEN" {
+                    fen = Some(tag[2].to_string());
+                }
             }
         }
-        line = read_line_or_empty(&mut handle, &mut buffer);
-
+        line = read_line_or_empty(handle, &mut buffer)?;
     }
-    if !found_game { return Err(ParsingError::EmptyMoves); }
+    if !found_game { return Err(ParsingError::Eof); }
 
     if !skipping_game {
-        skipping_game = false;
+        skipping_game = is_skip(visitor.end_headers());
     }
 
-    if !skipping_game {
-        
-        let temp_v = visitor.borrow();
-        // let fen = match temp_v.game.headers.get("FEN") { Some(v) => {v.clone()}, None => {"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR"}};
-        // board = Board::new(Some(crate::init::STARTING_FEN));
-        board.reset();
-        //visitor.borrow_mut().visit_board(&board);
+    if skipping_game {
+        skip_movetext(handle, &mut buffer, line)?;
+        visitor.end_game();
+        return Ok(visitor.result());
     }
 
-    //if skipping_game { CODE }
+    let mut board_stack = Vec::from([Board::new(Some(fen.as_deref().unwrap_or(STARTING_FEN)))]);
+    visitor.visit_board(&board_stack[0]);
 
-    let skip_variation_depth = 0;
+    let mut skip_variation_depth = 0;
+    // Whether the visitor began the variation that is being skipped, so
+    // that its closing parenthesis must end it, and whether its board is on
+    // the stack because the skip started at an invalid move inside it.
+    let mut skip_ends_variation = false;
+    let mut skip_pops_board = false;
+    let mut fresh_line = true;
+    // Column of `line[0]` in the physical line, non-zero after a closing brace.
+    let mut column_base = 0;
 
     while !line.is_empty() {
-        let read_next_line = true;
-
-        if line.starts_with("%") || line.starts_with(";"){
-            line = read_line_or_empty(&mut handle, &mut buffer);
-            continue;
-        }
-        if isspace(line) {
-            visitor.borrow_mut().end_game();
-            return Ok(visitor);
+        if fresh_line {
+            if line.starts_with("%") || line.starts_with(";") {
+                line = read_line_or_empty(handle, &mut buffer)?;
+                continue;
+            }
+            if isspace(&line) {
+                visitor.end_game();
+                return Ok(visitor.result());
+            }
         }
+        let mut read_next_line = true;
+        let mut rest = String::new();
 
-        for re_match in MOVETEXT_REGEX.find_iter(line) {
+        for re_match in MOVETEXT_REGEX.find_iter(&line) {
             let token = re_match.as_str();
 
             if token.starts_with("{") {
-
+                // Comments may span several lines, consume until the closing brace.
+                let mut comment = String::new();
+                let mut comment_line = token[1..].to_string();
+                let mut comment_base = column_base + re_match.start() + 1;
+                while !comment_line.is_empty() && !comment_line.contains('}') {
+                    comment.push_str(&comment_line);
+                    comment_line = read_line_or_empty(handle, &mut buffer)?;
+                    comment_base = 0;
+                }
+                if let Some(end_index) = comment_line.find('}') {
+                    comment.push_str(&comment_line[..end_index]);
+                    rest = comment_line[end_index + 1..].to_string();
+                    column_base = comment_base + end_index + 1;
+                }
+                if skip_variation_depth == 0 {
+                    visitor.visit_comment(comment.trim());
+                }
+                fresh_line = false;
+                read_next_line = false;
+                break;
+            }
+            else if token == "(" {
+                if skip_variation_depth > 0 {
+                    skip_variation_depth += 1;
+                }
+                else if !board_stack.last().unwrap().move_stack.is_empty() {
+                    if !is_skip(visitor.begin_variation()) {
+                        let mut board = board_stack.last_mut().unwrap().copy(true);
+                        board.pop();
+                        board_stack.push(board);
+                    }
+                    else {
+                        skip_variation_depth = 1;
+                        skip_ends_variation = true;
+                        skip_pops_board = false;
+                    }
+                }
+            }
+            else if token == ")" {
+                if skip_variation_depth == 1 {
+                    skip_variation_depth = 0;
+                    if skip_ends_variation {
+                        visitor.end_variation();
+                    }
+                    if skip_pops_board {
+                        board_stack.pop();
+                    }
+                }
+                else if skip_variation_depth > 0 {
+                    skip_variation_depth -= 1;
+                }
+                else if board_stack.len() > 1 {
+                    visitor.end_variation();
+                    board_stack.pop();
+                }
+            }
+            else if skip_variation_depth > 0 {
+                continue;
+            }
+            else if token.starts_with(";") {
+                break;
+            }
+            else if token.starts_with("$") {
+                if let Ok(nag) = token[1..].parse::<u64>() {
+                    visitor.visit_nag(nag);
+                }
+            }
+            else if let Some(nag) = suffix_nag(token) {
+                visitor.visit_nag(nag as u64);
             }
             else if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) {
-                visitor.borrow_mut().visit_result(token);
+                if board_stack.len() == 1 {
+                    visitor.visit_result(token);
+                }
             }
             else {
-                let m = visitor.borrow().parse_san(&board, token);
-                if !m.bool() {
-                    read_until_end_of_game(&mut handle, &mut buffer);
-                    return Err(ParsingError::InvalidMoveError);
+                let board = board_stack.last_mut().unwrap();
+                match visitor.parse_san(board, token) {
+                    Ok(m) => {
+                        visitor.visit_move(board, m);
+                        board.push(m);
+                        visitor.visit_board(board);
+                    },
+                    Err(reason) => {
+                        let error = ParsingError::InvalidMoveError {
+                            game: game_index,
+                            line: handle.line_number(),
+                            column: column_base + re_match.start() + 1,
+                            token: token.to_string(),
+                            fen: board.fen(),
+                            reason
+                        };
+                        if let Err(error) = visitor.handle_error(error) {
+                            let next_line = read_line_or_empty(handle, &mut buffer)?;
+                            skip_movetext(handle, &mut buffer, next_line)?;
+                            return Err(error);
+                        }
+                        // Lenient visitors keep the game, but the rest of the
+                        // variation cannot be interpreted without the move. On
+                        // the mainline that is the rest of the game.
+                        skip_variation_depth = 1;
+                        skip_ends_variation = board_stack.len() > 1;
+                        skip_pops_board = board_stack.len() > 1;
+                    }
                 }
-                visitor.borrow_mut().visit_move(&board, m);
-                board.push(m);
-                visitor.borrow().visit_board(&board);
             }
         }
         if read_next_line {
-            line = read_line_or_empty(&mut handle, &mut buffer);
+            line = read_line_or_empty(handle, &mut buffer)?;
+            fresh_line = true;
+            column_base = 0;
+        }
+        else {
+            line = rest;
+        }
+    }
+    visitor.end_game();
+    Ok(visitor.result())
+}
+pub fn read_headers<R: BufRead>(handle: &mut BufReader<R>) -> Result<GameHeaders, ParsingError> {
+    read_game(handle, &mut HeadersOnly::new())
+}
+/// Reads games one after another until the end of the input.
+pub struct Games<R: BufRead, V: Visitor = GameBuilder> {
+    handle: BufReader<R>,
+    visitor: V,
+    done: bool
+}
+impl<R: BufRead> Games<R, GameBuilder> {
+    pub fn new(handle: BufReader<R>) -> Self {
+        Games::with_visitor(handle, GameBuilder::new())
+    }
+    pub fn lenient(handle: BufReader<R>) -> Self {
+        Games::with_visitor(handle, GameBuilder::lenient())
+    }
+}
+impl<R: BufRead, V: Visitor> Games<R, V> {
+    pub fn with_visitor(handle: BufReader<R>, visitor: V) -> Self {
+        Games { handle, visitor, done: false }
+    }
+    pub fn into_inner(self) -> BufReader<R> {
+        self.handle
+    }
+}
+impl<R: BufRead, V: Visitor> Iterator for Games<R, V> {
+    type Item = Result<V::Output, ParsingError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
+        match read_game(&mut self.handle, &mut self.visitor) {
+            Err(ParsingError::Eof) => { self.done = true; None },
+            Err(ParsingError::ReadLineError(e)) => { self.done = true; Some(Err(ParsingError::ReadLineError(e))) },
+            result => Some(result)
+        }
+    }
+}
+fn suffix_nag(token: &str) -> Option<u8> {
+    match token {
+        "!" => Some(NAG_GOOD_MOVE),
+        "?" => Some(NAG_MISTAKE),
+        "!!" => Some(NAG_BRILLIANT_MOVE),
+        "??" => Some(NAG_BLUNDER),
+        "!?" => Some(NAG_SPECULATIVE_MOVE),
+        "?!" => Some(NAG_DUBIOUS_MOVE),
+        _ => None
     }
-    Ok(visitor)
 }
 
 #[derive(Error, Debug)]
 pub enum ParsingError {
-    #[error("Error while reading the line")]
-    ReadLineError,
-    #[error("Error while reading the line")]
-    InvalidMoveError,
-    #[error("Error while reading the line")]
-    EmptyMoves
+    #[error("end of file")]
+    Eof,
+    #[error("error while reading the line: {0}")]
+    ReadLineError(#[from] io::Error),
+    #[error("game {game}, line {line}, column {column}: {reason} (token `{token}`, position {fen})")]
+    InvalidMoveError {
+        game: u64,
+        line: u64,
+        column: usize,
+        token: String,
+        fen: String,
+        reason: SanError
+    },
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sans(start: Node) -> Vec<String> {
+        start.mainline().map(|node| node.san().unwrap()).collect()
+    }
+    fn read_lenient(pgn: &str) -> Game {
+        read_game(&mut BufReader::new(pgn.as_bytes()), &mut GameBuilder::lenient()).unwrap()
+    }
+
+    #[test]
+    fn lenient_error_inside_variation() {
+        let game = read_lenient("1. e4 (1. d4 Ke5 2. c4) 1... e5 2. Nf3 *\n\n");
+        assert_eq!(sans(game.root()), ["e4", "e5", "Nf3"]);
+        let variation = game.root().variations().nth(1).unwrap();
+        assert_eq!(variation.san().unwrap(), "d4");
+        assert!(variation.is_end());
+        assert_eq!(game.errors.len(), 1);
+        assert!(matches!(&game.errors[0], ParsingError::InvalidMoveError { token, .. } if token == "Ke5"));
+        assert_eq!(game.headers.get("Result"), Some("*"));
+    }
+
+    #[test]
+    fn lenient_error_inside_nested_variation() {
+        let game = read_lenient("1. e4 e5 (1... c5 2. Nf3 (2. Kd5 Nc6) 2... d6) 2. Nf3 Nc6 *\n\n");
+        assert_eq!(sans(game.root()), ["e4", "e5", "Nf3", "Nc6"]);
+        let sicilian = game.root().next().unwrap().variations().nth(1).unwrap();
+        assert_eq!(sicilian.san().unwrap(), "c5");
+        assert_eq!(sans(sicilian), ["Nf3", "d6"]);
+        assert_eq!(game.errors.len(), 1);
+    }
+
+    #[test]
+    fn lenient_error_on_mainline_keeps_earlier_moves() {
+        let game = read_lenient("1. e4 e5 2. Ke4 Nc6 3. Nf3 *\n\n");
+        assert_eq!(sans(game.root()), ["e4", "e5"]);
+        assert_eq!(game.errors.len(), 1);
+    }
+
+    #[test]
+    fn clock_round_trip() {
+        let mut game = Game::new();
+        let node = game.node_mut(Game::ROOT).add_variation(Move::from_uci("e2e4"), "", "", HashSet::new());
+        for (clock, text) in [(Duration::from_millis(3_723_500), "[%clk 1:02:03.5]"), (Duration::from_secs(5), "[%clk 0:00:05]"),
+                              (Duration::from_micros(59_999_600), "[%clk 0:01:00]"), (Duration::from_micros(3_599_999_700), "[%clk 1:00:00]")] {
+            game.node_mut(node).set_clock(Some(clock));
+            assert_eq!(game.node(node).comment(), text);
+            assert_eq!(game.node(node).clock(), Some(Duration::from_millis((clock.as_micros() as u64 + 500) / 1000)));
+        }
+        game.node_mut(node).set_clock(None);
+        assert_eq!(game.node(node).comment(), "");
+        game.node_mut(node).set_comment("[%clk 99999999999999999999:00:00]");
+        assert_eq!(game.node(node).clock(), None);
+        game.node_mut(node).set_comment("[%clk 5124095576030432:00:00]");
+        assert_eq!(game.node(node).clock(), None);
+    }
+
+    #[test]
+    fn eval_round_trip() {
+        let mut game = Game::new();
+        let node = game.node_mut(Game::ROOT).add_variation(Move::from_uci("e2e4"), "", "", HashSet::new());
+        let scores = [
+            (PovScore { relative: Score::Cp(-35), turn: WHITE, depth: Some(18) }, "[%eval -0.35,18]"),
+            (PovScore { relative: Score::Cp(120), turn: BLACK, depth: None }, "[%eval -1.20]"),
+            (PovScore { relative: Score::Mate(3), turn: WHITE, depth: None }, "[%eval #3]"),
+            (PovScore { relative: Score::Mate(2), turn: BLACK, depth: Some(30) }, "[%eval #-2,30]")
+        ];
+        for (score, text) in scores {
+            game.node_mut(node).set_eval(Some(score));
+            assert_eq!(game.node(node).comment(), text);
+            let eval = game.node(node).eval().unwrap();
+            assert_eq!((eval.white(), eval.depth), (score.white(), score.depth));
+        }
+    }
+
+    #[test]
+    fn arrows_round_trip() {
+        let mut game = Game::new();
+        let node = game.node_mut(Game::ROOT).add_variation(Move::from_uci("e2e4"), "", "", HashSet::new());
+        let arrows = vec![Arrow::new(0, 0, ArrowColor::Green), Arrow::new(12, 28, ArrowColor::Red), Arrow::new(3, 39, ArrowColor::Blue)];
+        game.node_mut(node).set_arrows(arrows.clone());
+        assert_eq!(game.node(node).comment(), "[%csl Ga1][%cal Re2e4,Bd1h5]");
+        assert_eq!(game.node(node).arrows(), arrows);
+    }
+
+    #[test]
+    fn removing_commands_keeps_one_space() {
+        let mut game = Game::new();
+        let node = game.node_mut(Game::ROOT).add_variation(Move::from_uci("e2e4"), "", "", HashSet::new());
+        game.node_mut(node).set_comment("hello [%clk 1:02:03.5] [%eval -0.35,18] [%csl Ga1][%cal Re2e4,Bd1h5] bye");
+        game.node_mut(node).set_eval(None);
+        assert_eq!(game.node(node).comment(), "hello [%clk 1:02:03.5] [%csl Ga1][%cal Re2e4,Bd1h5] bye");
+        game.node_mut(node).set_arrows(vec![]);
+        assert_eq!(game.node(node).comment(), "hello [%clk 1:02:03.5] bye");
+        game.node_mut(node).set_clock(None);
+        assert_eq!(game.node(node).comment(), "hello bye");
+        game.node_mut(node).set_comment("[%clk 0:00:01] hello");
+        game.node_mut(node).set_clock(None);
+        assert_eq!(game.node(node).comment(), "hello");
+        game.node_mut(node).set_comment("hello [%clk 0:00:01]");
+        game.node_mut(node).set_clock(None);
+        assert_eq!(game.node(node).comment(), "hello");
+    }
+
+    #[test]
+    fn plies_follow_the_fen_header() {
+        let game = read_lenient("[FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]\n\n1... e5 (1... c5 2. Nf3) 2. Nf3 *\n\n");
+        let plies: Vec<u64> = game.root().mainline().map(|node| node.ply()).collect();
+        assert_eq!((game.root().ply(), plies), (1, vec![2, 3]));
+        assert!(!game.root().turn());
 
+        let mut game = Game::new();
+        let king = game.node_mut(Game::ROOT).add_variation(Move::from_uci("e1e2"), "", "", HashSet::new());
+        assert_eq!(game.node(king).ply(), 1);
+        game.headers.set("FEN", "4k3/8/8/8/8/8/8/4K3 w - - 0 10");
+        let reply = game.node_mut(king).add_variation(Move::from_uci("e8e7"), "", "", HashSet::new());
+        assert_eq!((game.root().ply(), game.node(king).ply(), game.node(reply).ply()), (18, 19, 20));
+    }
 
+    #[test]
+    fn boards_of_alternatives() {
+        let game = read_lenient("1. e4 e5 (1... c5 2. Nf3 (2. c3 d5) 2... d6) (1... e6) 2. Nf3 Nc6 *\n\n");
+        let mut expected = Vec::new();
+        let mut stack = Vec::from([game.root()]);
+        while let Some(node) = stack.pop() {
+            let mut board = game.setup_board();
+            let mut moves: Vec<Move> = Vec::new();
+            let mut current = node;
+            while let (Some(m), Some(parent)) = (current.m(), current.parent()) {
+                moves.push(m);
+                current = parent;
+            }
+            for m in moves.into_iter().rev() {
+                board.push(m);
+            }
+            expected.push((node.id(), board.fen()));
+            stack.extend(node.variations());
+        }
+        for _ in 0..2 {
+            for (id, fen) in &expected {
+                assert_eq!(&game.node(*id).board().fen(), fen);
+            }
+        }
+    }
 
+    #[test]
+    fn node_paths_and_addresses() {
+        let game = read_lenient("1. e4 e5 (1... c5 2. Nf3 (2. c3 d5) 2... d6) 2. Nf3 *\n\n");
+        let mut stack = Vec::from([game.root()]);
+        while let Some(node) = stack.pop() {
+            let path = node.path();
+            assert_eq!(path.to_string().parse::<NodePath>(), Ok(path.clone()));
+            assert_eq!(game.node_at(&path), Some(node));
+            assert_eq!(game.parse_address(&node.address()), Ok(path));
+            stack.extend(node.variations());
+        }
+        let c3 = game.node_at(&"[0, 1, 1]".parse().unwrap()).unwrap();
+        assert_eq!((c3.san().unwrap(), c3.path().to_string(), c3.address()), (String::from("c3"), String::from("[0,1,1]"), String::from("1.b/1,2.w/1")));
+        assert_eq!(c3.next().unwrap().address(), "1.b/1,2.w/1,2.b");
+        assert_eq!(game.end().address(), "2.w");
+        assert_eq!(game.root().address(), "");
+        assert_eq!(game.parse_address(" 1.b/1 , 2.w "), Ok(NodePath(vec![0, 1, 0])));
+        assert_eq!(game.node_at(&NodePath(vec![0, 2])), None);
+        for invalid in ["0,1", "[0,x]"] {
+            assert_eq!(invalid.parse::<NodePath>(), Err(NodePathError::Invalid(String::from(invalid))));
+        }
+        for invalid in ["0.w", "1.x", "1.w/a", "2.w,1.b", "1.b,1.b", "e4"] {
+            assert_eq!(game.parse_address(invalid), Err(NodePathError::Invalid(String::from(invalid))));
+        }
+    }
 
+    #[test]
+    fn addresses_from_black_to_move() {
+        let game = read_lenient("[FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]\n\n1... e5 (1... c5) 2. Nf3 *\n\n");
+        let c5 = game.root().variations().nth(1).unwrap();
+        assert_eq!((c5.path(), c5.address()), (NodePath(vec![1]), String::from("1.b/1")));
+        assert_eq!(game.end().address(), "2.w");
+        assert_eq!(game.parse_address("2.w"), Ok(NodePath(vec![0, 0])));
+        assert_eq!(game.parse_address("1.w"), Err(NodePathError::Invalid(String::from("1.w"))));
+    }
+}
@@ -0,0 +1,445 @@
+use std::cmp::Ordering;
+
+use thiserror::Error;
+
+use crate::init::{Board, Move, SanError};
+use crate::pgn::{Headers, HeadersOnly, ParsingError, SkipType, Visitor};
+
+/// A filter over game headers, for example
+///
+/// `White ~ "Carlsen" and WhiteElo >= 2600 and Date >= 2015.01.01 and ECO in B90..B99 and Result = "1-0"`
+///
+/// Comparisons are `=`, `!=`, `<`, `<=`, `>`, `>=`, `~` (case-insensitive
+/// substring) and `in low..high` (inclusive). They combine with `and`, `or`,
+/// `not` and parentheses. Values are bare words or double-quoted strings.
+/// `*Elo` tags and integer values compare as numbers, `Date`, `EventDate`
+/// and `UTCDate` as dates (`??` parts count as 0) and `ECO` as opening codes.
+/// A comparison against a missing tag is false.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Compare { tag: String, op: Op, value: String },
+    Range { tag: String, low: String, high: String }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq, Ne, Lt, Le, Gt, Ge, Contains
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum QueryError {
+    #[error("column {column}: {message}")]
+    Syntax { column: usize, message: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    DotDot,
+    Open,
+    Close
+}
+
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => { i += 1; continue; },
+            ('(', _) => { i += 1; Token::Open },
+            (')', _) => { i += 1; Token::Close },
+            ('=', _) => { i += 1; Token::Op(Op::Eq) },
+            ('~', _) => { i += 1; Token::Op(Op::Contains) },
+            ('!', Some('=')) => { i += 2; Token::Op(Op::Ne) },
+            ('<', Some('=')) => { i += 2; Token::Op(Op::Le) },
+            ('>', Some('=')) => { i += 2; Token::Op(Op::Ge) },
+            ('<', _) => { i += 1; Token::Op(Op::Lt) },
+            ('>', _) => { i += 1; Token::Op(Op::Gt) },
+            ('.', Some('.')) => { i += 2; Token::DotDot },
+            ('"', _) => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(QueryError::Syntax { column: start + 1, message: String::from("unterminated string") }),
+                        Some('"') => { i += 1; break; },
+                        Some('\\') if i + 1 < chars.len() => { value.push(chars[i + 1]); i += 2; },
+                        Some(c) => { value.push(*c); i += 1; }
+                    }
+                }
+                Token::Quoted(value)
+            },
+            _ => {
+                // Bare words may contain single dots (dates) but stop at `..`.
+                let mut value = String::new();
+                while let Some(c) = chars.get(i) {
+                    let ends = c.is_whitespace() || "()=~!<>\"".contains(*c) || (*c == '.' && chars.get(i + 1) == Some(&'.'));
+                    if ends {
+                        break;
+                    }
+                    value.push(*c);
+                    i += 1;
+                }
+                if value.is_empty() {
+                    return Err(QueryError::Syntax { column: start + 1, message: format!("unexpected `{}`", c) });
+                }
+                Token::Word(value)
+            }
+        };
+        tokens.push((start + 1, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    end: usize
+}
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+    fn column(&self) -> usize {
+        self.tokens.get(self.position).map_or(self.end, |(column, _)| *column)
+    }
+    fn error<T>(&self, message: &str) -> Result<T, QueryError> {
+        Err(QueryError::Syntax { column: self.column(), message: String::from(message) })
+    }
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            },
+            _ => false
+        }
+    }
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.and()?;
+        while self.keyword("or") {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut query = self.not()?;
+        while self.keyword("and") {
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+        Ok(query)
+    }
+    fn not(&mut self) -> Result<Query, QueryError> {
+        if self.keyword("not") {
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.position += 1;
+            let query = self.or()?;
+            if self.peek() != Some(&Token::Close) {
+                return self.error("expected `)`");
+            }
+            self.position += 1;
+            return Ok(query);
+        }
+        self.comparison()
+    }
+    fn value(&mut self) -> Result<String, QueryError> {
+        match self.peek().cloned() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => {
+                self.position += 1;
+                Ok(value)
+            },
+            _ => self.error("expected a value")
+        }
+    }
+    fn comparison(&mut self) -> Result<Query, QueryError> {
+        let tag = match self.peek().cloned() {
+            Some(Token::Word(tag)) => tag,
+            _ => return self.error("expected a tag name")
+        };
+        self.position += 1;
+        if self.keyword("in") {
+            let low = self.value()?;
+            if self.peek() != Some(&Token::DotDot) {
+                return self.error("expected `..`");
+            }
+            self.position += 1;
+            let high = self.value()?;
+            return Ok(Query::Range { tag, low, high });
+        }
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => return self.error("expected a comparison")
+        };
+        self.position += 1;
+        let value = self.value()?;
+        Ok(Query::Compare { tag, op, value })
+    }
+}
+
+/// Typed view of a header value, chosen from the tag name and the values.
+#[derive(PartialEq, PartialOrd)]
+enum Value {
+    Int(i64),
+    Date(u32, u32, u32),
+    Eco(char, u32),
+    Text(String)
+}
+fn parse_date(value: &str) -> Option<Value> {
+    let mut parts = value.split('.').map(|part| match part.trim_matches('?') {
+        "" => Some(0),
+        digits => digits.parse::<u32>().ok()
+    });
+    let year = parts.next()??;
+    let month = parts.next().unwrap_or(Some(0))?;
+    let day = parts.next().unwrap_or(Some(0))?;
+    Some(Value::Date(year, month, day))
+}
+fn parse_eco(value: &str) -> Option<Value> {
+    let mut chars = value.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let number = chars.as_str();
+    if !('A'..='E').contains(&letter) || number.len() != 2 {
+        return None;
+    }
+    Some(Value::Eco(letter, number.parse().ok()?))
+}
+fn typed(tag: &str, value: &str) -> Option<Value> {
+    match tag {
+        "Date" | "EventDate" | "UTCDate" => parse_date(value),
+        "ECO" => parse_eco(value),
+        _ => match value.trim().parse::<i64>() {
+            Ok(number) => Some(Value::Int(number)),
+            Err(_) if tag.ends_with("Elo") => None,
+            Err(_) => Some(Value::Text(value.to_string()))
+        }
+    }
+}
+fn compare(tag: &str, actual: &str, expected: &str) -> Option<Ordering> {
+    match (typed(tag, actual)?, typed(tag, expected)?) {
+        (Value::Text(_), _) | (_, Value::Text(_)) => Some(actual.cmp(expected)),
+        (actual, expected) => actual.partial_cmp(&expected)
+    }
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(query)?;
+        let mut parser = Parser { tokens, position: 0, end: query.chars().count() + 1 };
+        let result = parser.or()?;
+        if parser.peek().is_some() {
+            return parser.error("unexpected input after the query");
+        }
+        Ok(result)
+    }
+    pub fn matches(&self, headers: &Headers) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(headers) && b.matches(headers),
+            Query::Or(a, b) => a.matches(headers) || b.matches(headers),
+            Query::Not(a) => !a.matches(headers),
+            Query::Compare { tag, op, value } => {
+                let actual = match headers.get(tag) {
+                    Some(actual) => actual,
+                    None => return false
+                };
+                if *op == Op::Contains {
+                    return actual.to_lowercase().contains(&value.to_lowercase());
+                }
+                match compare(tag, actual, value) {
+                    Some(ordering) => match op {
+                        Op::Eq => ordering == Ordering::Equal,
+                        Op::Ne => ordering != Ordering::Equal,
+                        Op::Lt => ordering == Ordering::Less,
+                        Op::Le => ordering != Ordering::Greater,
+                        Op::Gt => ordering == Ordering::Greater,
+                        Op::Ge => ordering != Ordering::Less,
+                        Op::Contains => unreachable!()
+                    },
+                    None => false
+                }
+            },
+            Query::Range { tag, low, high } => {
+                let actual = match headers.get(tag) {
+                    Some(actual) => actual,
+                    None => return false
+                };
+                compare(tag, actual, low).map_or(false, |o| o != Ordering::Less)
+                    && compare(tag, actual, high).map_or(false, |o| o != Ordering::Greater)
+            }
+        }
+    }
+}
+impl std::str::FromStr for Query {
+    type Err = QueryError;
+    fn from_str(s: &str) -> Result<Query, QueryError> {
+        Query::parse(s)
+    }
+}
+
+/// Wraps a visitor so that only games matching a query reach it. The query
+/// is evaluated at the end of the headers; other games are skipped without
+/// parsing their movetext and come out as `None`.
+pub struct Filter<V: Visitor> {
+    query: Query,
+    inner: V,
+    headers: Headers,
+    matched: bool
+}
+impl<V: Visitor> Filter<V> {
+    pub fn new(query: Query, inner: V) -> Filter<V> {
+        Filter { query, inner, headers: Headers::new(None), matched: false }
+    }
+    pub fn into_inner(self) -> V {
+        self.inner
+    }
+}
+impl Filter<HeadersOnly> {
+    /// Header-only scanning of matching games.
+    pub fn headers(query: Query) -> Filter<HeadersOnly> {
+        Filter::new(query, HeadersOnly::new())
+    }
+}
+impl<V: Visitor> Visitor for Filter<V> {
+    type Output = Option<V::Output>;
+    fn begin_game(&mut self) -> Option<SkipType> {
+        self.headers = Headers::new(None);
+        self.matched = false;
+        self.inner.begin_game()
+    }
+    fn visit_offset(&mut self, offset: u64) {
+        self.inner.visit_offset(offset)
+    }
+    fn begin_headers(&mut self) {
+        self.inner.begin_headers()
+    }
+    fn visit_header(&mut self, tagname: &str, tagvalue: &str) {
+        self.headers.set(tagname, tagvalue);
+        self.inner.visit_header(tagname, tagvalue)
+    }
+    fn end_headers(&mut self) -> Option<SkipType> {
+        self.matched = self.query.matches(&self.headers);
+        match self.matched {
+            true => self.inner.end_headers(),
+            false => Some(SkipType::SKIP)
+        }
+    }
+    fn parse_san(&mut self, board: &Board, san: &str) -> Result<Move, SanError> {
+        self.inner.parse_san(board, san)
+    }
+    fn visit_move(&mut self, board: &Board, m: Move) {
+        self.inner.visit_move(board, m)
+    }
+    fn visit_board(&mut self, board: &Board) {
+        self.inner.visit_board(board)
+    }
+    fn visit_comment(&mut self, comment: &str) {
+        self.inner.visit_comment(comment)
+    }
+    fn visit_nag(&mut self, nag: u64) {
+        self.inner.visit_nag(nag)
+    }
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        self.inner.begin_variation()
+    }
+    fn end_variation(&mut self) {
+        self.inner.end_variation()
+    }
+    fn visit_result(&mut self, result: &str) {
+        self.inner.visit_result(result)
+    }
+    fn end_game(&mut self) {
+        self.inner.end_game()
+    }
+    fn result(&mut self) -> Option<V::Output> {
+        let output = self.inner.result();
+        self.matched.then_some(output)
+    }
+    fn handle_error(&mut self, error: ParsingError) -> Result<(), ParsingError> {
+        self.inner.handle_error(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(tags: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new(None);
+        for (key, value) in tags {
+            headers.set(key, value);
+        }
+        headers
+    }
+    fn matches(query: &str, tags: &[(&str, &str)]) -> bool {
+        Query::parse(query).unwrap().matches(&headers(tags))
+    }
+
+    #[test]
+    fn parses_precedence_and_ranges() {
+        let compare = |tag: &str, op, value: &str| Query::Compare { tag: tag.to_string(), op, value: value.to_string() };
+        assert_eq!(Query::parse("a = 1 or b != 2 and not c ~ \"x y\"").unwrap(), Query::Or(
+            Box::new(compare("a", Op::Eq, "1")),
+            Box::new(Query::And(Box::new(compare("b", Op::Ne, "2")), Box::new(Query::Not(Box::new(compare("c", Op::Contains, "x y"))))))
+        ));
+        assert_eq!("(a < 1 OR a >= 2) AND ECO in B90..B99".parse::<Query>().unwrap(), Query::And(
+            Box::new(Query::Or(Box::new(compare("a", Op::Lt, "1")), Box::new(compare("a", Op::Ge, "2")))),
+            Box::new(Query::Range { tag: String::from("ECO"), low: String::from("B90"), high: String::from("B99") })
+        ));
+        assert_eq!(Query::parse("Date <= 2015.01.01").unwrap(), compare("Date", Op::Le, "2015.01.01"));
+    }
+
+    #[test]
+    fn syntax_errors_have_columns() {
+        for (query, column, message) in [("White = \"Carlsen", 9, "unterminated string"), ("(White = a", 11, "expected `)`"),
+                                          ("White", 6, "expected a comparison"), ("ECO in A00 B99", 12, "expected `..`"),
+                                          ("= 1", 1, "expected a tag name"), ("a = 1 b", 7, "unexpected input after the query"),
+                                          ("a = ", 5, "expected a value")] {
+            assert_eq!(Query::parse(query), Err(QueryError::Syntax { column, message: String::from(message) }), "{}", query);
+        }
+    }
+
+    #[test]
+    fn compares_dates() {
+        let tags = [("Date", "2015.??.??"), ("UTCDate", "2015.03.07")];
+        assert!(matches("Date >= 2015", &tags));
+        assert!(!matches("Date >= 2015.01.01", &tags));
+        assert!(matches("Date < 2015.01.01 and Date > 2014.12.31", &tags));
+        assert!(matches("UTCDate in 2015.03.01..2015.03.31", &tags));
+        assert!(matches("UTCDate > 2015.3.6", &tags));
+        assert!(!matches("UTCDate = 2015.03.08", &tags));
+        assert!(!matches("Date = someday", &tags));
+    }
+
+    #[test]
+    fn compares_eco_codes() {
+        let tags = [("ECO", "B92")];
+        assert!(matches("ECO in B90..B99", &tags));
+        assert!(matches("ECO in b90..c00", &tags));
+        assert!(!matches("ECO in A00..B91", &tags));
+        assert!(matches("ECO > A99 and ECO < C00", &tags));
+        assert!(matches("ECO = b92", &tags));
+        assert!(!matches("ECO < F00", &tags));
+        assert!(!matches("ECO = B92x", &tags));
+    }
+
+    #[test]
+    fn compares_elos_as_numbers() {
+        let tags = [("White", "Carlsen, Magnus"), ("WhiteElo", "2863"), ("BlackElo", "-"), ("Round", "10")];
+        assert!(matches("WhiteElo > 900", &tags));
+        assert!(matches("WhiteElo in 2800..3000", &tags));
+        assert!(!matches("WhiteElo < 900", &tags));
+        assert!(!matches("BlackElo < 3000 or BlackElo >= 0", &tags));
+        assert!(matches("Round > 9", &tags));
+        assert!(matches("White ~ carlsen and not White = Carlsen", &tags));
+        assert!(!matches("Annotator = x or Annotator != x", &tags));
+        assert!(matches("not Annotator = x", &tags));
+    }
+}
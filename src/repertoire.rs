@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::init::{Board, STARTING_FEN};
+use crate::pgn::{Game, MoveRepr, NodeId};
+
+/// Merges many games into one tree. Lines sharing a prefix share nodes,
+/// divergent lines become variations, and every node counts the games
+/// that passed through it.
+///
+/// With transpositions joined, a move reaching a position already in the
+/// tree still gets its own node, but the line continues below the node
+/// that reached the position first. `transposition` links the two. A
+/// position repeated within a line is only joined at its first occurrence,
+/// so the continuation after a repetition stays below the repeating move.
+pub struct Repertoire {
+    game: Game,
+    counts: Vec<u64>,
+    join_transpositions: bool,
+    positions: HashMap<String, NodeId>,
+    transpositions: HashMap<NodeId, NodeId>
+}
+impl Repertoire {
+    pub fn new() -> Repertoire {
+        Repertoire {
+            game: Game::new(),
+            counts: Vec::new(),
+            join_transpositions: false,
+            positions: HashMap::new(),
+            transpositions: HashMap::new()
+        }
+    }
+    pub fn with_transpositions() -> Repertoire {
+        Repertoire { join_transpositions: true, ..Repertoire::new() }
+    }
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+    pub fn into_game(self) -> Game {
+        self.game
+    }
+    /// Number of merged games that passed through `id`.
+    pub fn count(&self, id: NodeId) -> u64 {
+        self.counts.get(id.0).copied().unwrap_or(0)
+    }
+    /// The node whose continuations a transposing node shares, if any.
+    pub fn transposition(&self, id: NodeId) -> Option<NodeId> {
+        self.transpositions.get(&id).copied()
+    }
+    /// Node at which a game starting from `board` is merged in.
+    fn start_node(&mut self, board: &Board) -> Option<NodeId> {
+        if self.counts.is_empty() {
+            if board.fen() != STARTING_FEN {
+                self.game.headers.set("SetUp", "1");
+                self.game.headers.set("FEN", &board.fen());
+            }
+            self.positions.insert(board.epd(), Game::ROOT);
+            return Some(Game::ROOT);
+        }
+        if board.epd() == self.game.setup_board().epd() {
+            return Some(Game::ROOT);
+        }
+        match self.join_transpositions {
+            true => self.positions.get(&board.epd()).copied(),
+            false => None
+        }
+    }
+    /// Merges `source` and all of its variations into the tree. Returns false
+    /// if the game starts from a position the repertoire does not contain.
+    pub fn add_game(&mut self, source: &Game) -> bool {
+        let board = source.setup_board();
+        let start = match self.start_node(&board) {
+            Some(start) => start,
+            None => return false
+        };
+        let mut visited = HashSet::from([start]);
+        merge_comments(&mut self.game, start, source, Game::ROOT);
+
+        let mut epds = HashMap::from([(Game::ROOT, board.epd())]);
+        let mut stack = Vec::from([(Game::ROOT, start, board)]);
+        while let Some((src, dst, board)) = stack.pop() {
+            let variations: Vec<NodeId> = source.node(src).variations().map(|variation| variation.id()).collect();
+            for src_child in variations {
+                let m = source[src_child].m.unwrap();
+                let existing = self.game.node(dst).has_variation(m).then(|| self.game.node(dst).index(MoveRepr::Move(m)));
+                let dst_child = match existing {
+                    Some(id) => id,
+                    None => self.game.node_mut(dst).add_variation(m, "", "", HashSet::new())
+                };
+                visited.insert(dst_child);
+                merge_comments(&mut self.game, dst_child, source, src_child);
+
+                let mut child_board = board.copy(false);
+                child_board.push(m);
+                let epd = child_board.epd();
+                let mut next = dst_child;
+                if self.join_transpositions {
+                    let canonical = *self.positions.entry(epd.clone()).or_insert(dst_child);
+                    if canonical != dst_child && !repeats(source, &epds, src, &epd) {
+                        self.transpositions.insert(dst_child, canonical);
+                        visited.insert(canonical);
+                        next = canonical;
+                    }
+                }
+                epds.insert(src_child, epd);
+                stack.push((src_child, next, child_board));
+            }
+        }
+
+        for id in visited {
+            if self.counts.len() <= id.0 {
+                self.counts.resize(id.0 + 1, 0);
+            }
+            self.counts[id.0] += 1;
+        }
+        true
+    }
+}
+/// Whether `epd` occurs on the line of `source` from the root to `node`.
+/// `epds` holds the positions of the source nodes merged so far.
+fn repeats(source: &Game, epds: &HashMap<NodeId, String>, node: NodeId, epd: &str) -> bool {
+    let mut node = Some(node);
+    while let Some(id) = node {
+        if epds[&id] == epd {
+            return true;
+        }
+        node = source[id].parent;
+    }
+    false
+}
+/// Appends the comments of a source node that the target does not have yet
+/// and takes the union of the NAGs.
+fn merge_comments(game: &mut Game, dst: NodeId, source: &Game, src: NodeId) {
+    let src = source.node(src);
+    let mut node = game.node_mut(dst);
+    for (comment, starting) in [(src.comment(), false), (src.starting_comment(), true)] {
+        let current = match starting {
+            true => node.as_node().starting_comment().to_string(),
+            false => node.as_node().comment().to_string()
+        };
+        if comment.is_empty() || current.contains(comment) {
+            continue;
+        }
+        let joined = format!("{}\n{}", current, comment);
+        match starting {
+            true => node.set_starting_comment(joined.trim()),
+            false => node.set_comment(joined.trim())
+        }
+    }
+    node.nags_mut().extend(src.nags().iter().copied());
+}
+/// Merges `games` into one repertoire tree.
+pub fn merge<'a, T>(games: T, join_transpositions: bool) -> Repertoire where T: IntoIterator<Item = &'a Game> {
+    let mut repertoire = match join_transpositions {
+        true => Repertoire::with_transpositions(),
+        false => Repertoire::new()
+    };
+    for game in games {
+        repertoire.add_game(game);
+    }
+    repertoire
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::{read_game, BufReader, GameBuilder};
+
+    fn games(pgns: &[&str]) -> Vec<Game> {
+        pgns.iter().map(|pgn| read_game(&mut BufReader::new(pgn.as_bytes()), &mut GameBuilder::new()).unwrap()).collect()
+    }
+    fn node(game: &Game, sans: &[&str]) -> NodeId {
+        let mut node = game.root();
+        for san in sans {
+            node = node.variations().find(|variation| variation.san().as_deref() == Some(*san)).unwrap();
+        }
+        node.id()
+    }
+
+    #[test]
+    fn counts_games_per_node() {
+        let games = games(&["1. e4 e5 2. Nf3 *\n\n", "1. e4 c5 *\n\n", "1. d4 d5 *\n\n"]);
+        let repertoire = merge(&games, false);
+        let game = repertoire.game();
+        let counts: Vec<u64> = [&[][..], &["e4"], &["e4", "e5"], &["e4", "e5", "Nf3"], &["e4", "c5"], &["d4"], &["d4", "d5"]]
+            .iter().map(|sans| repertoire.count(node(game, sans))).collect();
+        assert_eq!(counts, [3, 2, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn joins_transpositions() {
+        let games = games(&["1. d4 Nf6 2. c4 e6 *\n\n", "1. c4 Nf6 2. d4 e6 3. Nc3 *\n\n"]);
+        let repertoire = merge(&games, true);
+        let game = repertoire.game();
+        let canonical = node(game, &["d4", "Nf6", "c4"]);
+        let transposing = node(game, &["c4", "Nf6", "d4"]);
+        assert_eq!(repertoire.transposition(transposing), Some(canonical));
+        assert!(game.node(transposing).is_end());
+        assert_eq!(repertoire.count(canonical), 2);
+        assert_eq!(repertoire.count(node(game, &["d4", "Nf6", "c4", "e6"])), 2);
+        assert_eq!(repertoire.count(node(game, &["d4", "Nf6", "c4", "e6", "Nc3"])), 1);
+    }
+
+    #[test]
+    fn repetitions_are_not_joined() {
+        let games = games(&["1. Nf3 Nf6 2. Ng1 Ng8 3. e4 *\n\n", "1. Nf3 Nf6 2. Ng1 Ng8 3. d4 *\n\n"]);
+        let repertoire = merge(&games, true);
+        let game = repertoire.game();
+        assert_eq!(game.root().variations().count(), 1);
+        let repeated = node(game, &["Nf3", "Nf6", "Ng1", "Ng8"]);
+        assert_eq!(repertoire.transposition(repeated), None);
+        assert_eq!(game.node(repeated).variations().count(), 2);
+        assert_eq!(repertoire.count(Game::ROOT), 2);
+        assert_eq!(repertoire.count(repeated), 2);
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::output::{csv_field, json_number, json_string};
+use crate::pgn::{self, Games, SkipType, Visitor};
+
+/// What a report needs from one game.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameSummary {
+    pub white: String,
+    pub black: String,
+    pub result: String,
+    pub white_elo: Option<u32>,
+    pub black_elo: Option<u32>,
+    pub eco: String,
+    pub time_control: String,
+    pub plies: u64
+}
+/// Visitor producing a `GameSummary`. Variations are skipped.
+pub struct SummaryVisitor {
+    player: Option<String>,
+    summary: GameSummary
+}
+impl SummaryVisitor {
+    pub fn new() -> SummaryVisitor {
+        SummaryVisitor { player: None, summary: GameSummary::default() }
+    }
+    /// Skips the moves of games that `player` did not play, compared as in
+    /// `PlayerReport::build`. Their summaries only have headers.
+    pub fn for_player(player: &str) -> SummaryVisitor {
+        SummaryVisitor { player: Some(player.trim().to_lowercase()), summary: GameSummary::default() }
+    }
+}
+impl Visitor for SummaryVisitor {
+    type Output = GameSummary;
+    fn begin_game(&mut self) -> Option<SkipType> {
+        self.summary = GameSummary::default();
+        None
+    }
+    fn visit_header(&mut self, tagname: &str, tagvalue: &str) {
+        let summary = &mut self.summary;
+        match tagname {
+            "White" => summary.white = tagvalue.to_string(),
+            "Black" => summary.black = tagvalue.to_string(),
+            "Result" => summary.result = tagvalue.to_string(),
+            "WhiteElo" => summary.white_elo = tagvalue.parse().ok(),
+            "BlackElo" => summary.black_elo = tagvalue.parse().ok(),
+            "ECO" => summary.eco = tagvalue.to_string(),
+            "TimeControl" => summary.time_control = tagvalue.to_string(),
+            _ => {}
+        }
+    }
+    fn end_headers(&mut self) -> Option<SkipType> {
+        let player = self.player.as_ref()?;
+        let plays = |name: &str| name.trim().to_lowercase() == *player;
+        (!plays(&self.summary.white) && !plays(&self.summary.black)).then_some(SkipType::SKIP)
+    }
+    fn visit_move(&mut self, _board: &crate::init::Board, _m: crate::init::Move) {
+        self.summary.plies += 1;
+    }
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        Some(SkipType::SKIP)
+    }
+    fn result(&mut self) -> GameSummary {
+        std::mem::take(&mut self.summary)
+    }
+}
+
+/// Wins, draws and losses from the player's point of view.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32
+}
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+    pub fn points(&self) -> f64 {
+        self.wins as f64 + 0.5 * self.draws as f64
+    }
+    pub fn percent(&self) -> f64 {
+        100.0 * self.points() / self.games().max(1) as f64
+    }
+    fn add(&mut self, points: f64) {
+        match points {
+            p if p > 0.75 => self.wins += 1,
+            p if p > 0.25 => self.draws += 1,
+            _ => self.losses += 1
+        }
+    }
+}
+
+/// Speed category of a PGN `TimeControl` such as `180+2`, from the
+/// estimated duration `base + 40 * increment`, "unknown" if it does not
+/// parse or overflows.
+pub fn time_control_category(time_control: &str) -> &'static str {
+    let (base, increment) = time_control.split_once('+').unwrap_or((time_control, "0"));
+    let duration = match (base.parse::<u32>(), increment.parse::<u32>()) {
+        (Ok(base), Ok(increment)) => increment.checked_mul(40).and_then(|i| base.checked_add(i)),
+        _ => None
+    };
+    match duration {
+        Some(0..=179) => "bullet",
+        Some(180..=479) => "blitz",
+        Some(480..=1499) => "rapid",
+        Some(_) => "classical",
+        None => "unknown"
+    }
+}
+
+/// Statistics of one player over a set of games.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerReport {
+    pub player: String,
+    pub white: Score,
+    pub black: Score,
+    /// Average opponent rating plus 400 times the win/loss balance per game.
+    pub performance: Option<f64>,
+    pub average_opponent_elo: Option<f64>,
+    /// `(ECO, score)`, most played first.
+    pub openings: Vec<(String, Score)>,
+    /// Average length of the player's games in full moves.
+    pub average_moves: f64,
+    /// `(category, score)` for bullet, blitz, rapid, classical and unknown.
+    pub time_controls: Vec<(String, Score)>
+}
+impl PlayerReport {
+    /// Builds the report of `player` (matched case-insensitively against the
+    /// `White` and `Black` headers) from `games`. Games without a result are
+    /// ignored.
+    pub fn build<T>(player: &str, games: T) -> PlayerReport where T: IntoIterator<Item = GameSummary> {
+        let name = player.trim().to_lowercase();
+        let mut report = PlayerReport { player: player.to_string(), ..PlayerReport::default() };
+        let mut openings: HashMap<String, Score> = HashMap::new();
+        let mut time_controls: HashMap<String, Score> = HashMap::new();
+        let (mut opponent_elo, mut rated_games, mut rated_balance) = (0.0, 0, 0.0);
+        let mut plies = 0;
+
+        for game in games {
+            let white_points = match game.result.as_str() {
+                "1-0" => 1.0,
+                "0-1" => 0.0,
+                "1/2-1/2" => 0.5,
+                _ => continue
+            };
+            let (points, score, opponent) = if game.white.trim().to_lowercase() == name {
+                (white_points, &mut report.white, game.black_elo)
+            }
+            else if game.black.trim().to_lowercase() == name {
+                (1.0 - white_points, &mut report.black, game.white_elo)
+            }
+            else {
+                continue;
+            };
+            score.add(points);
+            if let Some(elo) = opponent {
+                opponent_elo += elo as f64;
+                rated_games += 1;
+                rated_balance += 2.0 * points - 1.0;
+            }
+            let eco = if game.eco.is_empty() { "?" } else { &game.eco };
+            openings.entry(eco.to_string()).or_default().add(points);
+            time_controls.entry(time_control_category(&game.time_control).to_string()).or_default().add(points);
+            plies += game.plies;
+        }
+
+        let games = report.white.games() + report.black.games();
+        if rated_games > 0 {
+            let average = opponent_elo / rated_games as f64;
+            report.average_opponent_elo = Some(average);
+            report.performance = Some(average + 400.0 * rated_balance / rated_games as f64);
+        }
+        report.average_moves = plies as f64 / 2.0 / games.max(1) as f64;
+        report.openings = openings.into_iter().collect();
+        report.openings.sort_by(|a, b| b.1.games().cmp(&a.1.games()).then_with(|| a.0.cmp(&b.0)));
+        report.time_controls = time_controls.into_iter().collect();
+        report.time_controls.sort_by(|a, b| b.1.games().cmp(&a.1.games()).then_with(|| a.0.cmp(&b.0)));
+        report
+    }
+    /// Builds the report of `player` from every game read from `handle`.
+    pub fn from_pgn<R: BufRead>(player: &str, handle: pgn::BufReader<R>) -> PlayerReport {
+        PlayerReport::build(player, Games::with_visitor(handle, SummaryVisitor::for_player(player)).flatten())
+    }
+    pub fn to_json(&self) -> String {
+        let score = |score: &Score| format!("{{\"games\":{},\"wins\":{},\"draws\":{},\"losses\":{},\"percent\":{}}}",
+            score.games(), score.wins, score.draws, score.losses, json_number(score.percent()));
+        let table = |rows: &Vec<(String, Score)>, key: &str| rows.iter()
+            .map(|(name, s)| format!("{{\"{}\":{},\"score\":{}}}", key, json_string(name), score(s)))
+            .collect::<Vec<String>>().join(",");
+        let optional = |value: Option<f64>| value.map_or(String::from("null"), json_number);
+        format!("{{\"player\":{},\"white\":{},\"black\":{},\"performance\":{},\"average_opponent_elo\":{},\"average_moves\":{},\"openings\":[{}],\"time_controls\":[{}]}}",
+            json_string(&self.player), score(&self.white), score(&self.black), optional(self.performance),
+            optional(self.average_opponent_elo), json_number(self.average_moves),
+            table(&self.openings, "eco"), table(&self.time_controls, "time_control"))
+    }
+    /// One row per category: `section,key,games,wins,draws,losses,percent`.
+    /// The summary figures are in the `summary` section with the value in
+    /// the `percent` column.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("section,key,games,wins,draws,losses,percent\n");
+        let mut row = |section: &str, key: &str, s: &Score| {
+            csv.push_str(&format!("{},{},{},{},{},{},{:.1}\n", section, csv_field(key), s.games(), s.wins, s.draws, s.losses, s.percent()));
+        };
+        row("color", "white", &self.white);
+        row("color", "black", &self.black);
+        for (eco, s) in &self.openings {
+            row("opening", eco, s);
+        }
+        for (category, s) in &self.time_controls {
+            row("time_control", category, s);
+        }
+        for (key, value) in [("performance", self.performance), ("average_opponent_elo", self.average_opponent_elo), ("average_moves", Some(self.average_moves))] {
+            csv.push_str(&format!("summary,{},,,,,{}\n", key, value.map_or(String::new(), |v| format!("{:.1}", v))));
+        }
+        csv
+    }
+    /// Plain-text table for the terminal.
+    pub fn table(&self) -> String {
+        let mut out = format!("{}\n", self.player);
+        let line = |label: &str, s: &Score| format!("  {:<14} {:>5} {:>5} {:>5} {:>5} {:>6.1}%\n", label, s.games(), s.wins, s.draws, s.losses, s.percent());
+        out.push_str(&format!("  {:<14} {:>5} {:>5} {:>5} {:>5} {:>7}\n", "", "games", "+", "=", "-", "score"));
+        out.push_str(&line("white", &self.white));
+        out.push_str(&line("black", &self.black));
+        for (category, s) in &self.time_controls {
+            out.push_str(&line(category, s));
+        }
+        out.push_str("  openings\n");
+        for (eco, s) in self.openings.iter().take(10) {
+            out.push_str(&line(eco, s));
+        }
+        if let (Some(performance), Some(average)) = (self.performance, self.average_opponent_elo) {
+            out.push_str(&format!("  performance {:.0} (average opponent {:.0})\n", performance, average));
+        }
+        out.push_str(&format!("  average length {:.1} moves\n", self.average_moves));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_from_pgn() {
+        let pgn = "[White \"Carlsen\"]\n[Black \"Caruana\"]\n[Result \"1-0\"]\n[BlackElo \"2800\"]\n[ECO \"C65\"]\n[TimeControl \"180+2\"]\n\n1. e4 e5 2. Nf3 Nc6 1-0\n\n\
+                   [White \"Nakamura\"]\n[Black \"carlsen \"]\n[Result \"1/2-1/2\"]\n[WhiteElo \"2700\"]\n\n1. d4 d5 1/2-1/2\n\n\
+                   [White \"Nakamura\"]\n[Black \"Caruana\"]\n[Result \"0-1\"]\n\n1. Kf3 *\n\n";
+        let report = PlayerReport::from_pgn("Carlsen", pgn::BufReader::new(pgn.as_bytes()));
+        assert_eq!(report.white, Score { wins: 1, draws: 0, losses: 0 });
+        assert_eq!(report.black, Score { wins: 0, draws: 1, losses: 0 });
+        assert_eq!(report.average_opponent_elo, Some(2750.0));
+        assert_eq!(report.performance, Some(2950.0));
+        assert_eq!(report.average_moves, 1.5);
+        assert_eq!(report.openings, [(String::from("?"), Score { wins: 0, draws: 1, losses: 0 }), (String::from("C65"), Score { wins: 1, draws: 0, losses: 0 })]);
+        assert_eq!(report.time_controls[0].0, "blitz");
+    }
+
+    #[test]
+    fn other_players_games_are_skipped() {
+        let mut visitor = SummaryVisitor::for_player("Carlsen");
+        visitor.begin_game();
+        visitor.visit_header("White", "Nakamura");
+        visitor.visit_header("Black", "Caruana");
+        assert!(visitor.end_headers().is_some());
+        visitor.begin_game();
+        visitor.visit_header("White", "Nakamura");
+        visitor.visit_header("Black", "CARLSEN");
+        assert!(visitor.end_headers().is_none());
+        assert!(SummaryVisitor::new().end_headers().is_none());
+    }
+}
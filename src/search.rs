@@ -0,0 +1,301 @@
+use std::io::BufRead;
+
+use thiserror::Error;
+
+use crate::init::{BaseBoard, Board, Color, FenError, COLORS, KING, PAWN, PIECE_SYMBOLS};
+use crate::pgn::{self, Games, ParsingError, SkipType, Visitor};
+
+/// A partial position: pieces that must be somewhere in a set of squares,
+/// e.g. "white rook on the 7th, black king on g8", and optionally the side
+/// to move.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pattern {
+    pieces: Vec<(u8, Color, u64)>,
+    turn: Option<Color>
+}
+impl Pattern {
+    pub fn new() -> Pattern {
+        Pattern::default()
+    }
+    /// Requires at least one piece of `piece_type` and `color` on `mask`.
+    pub fn piece(mut self, piece_type: u8, color: Color, mask: u64) -> Pattern {
+        self.pieces.push((piece_type, color, mask));
+        self
+    }
+    pub fn turn(mut self, turn: Color) -> Pattern {
+        self.turn = Some(turn);
+        self
+    }
+    /// Every piece of a board FEN must stand on its square; other squares
+    /// may hold anything.
+    pub fn from_board_fen(board_fen: &str) -> Result<Pattern, FenError> {
+        BaseBoard::check_board_fen(board_fen)?;
+        let placement = BaseBoard::new(Some(board_fen));
+        let mut pattern = Pattern::new();
+        for color in COLORS {
+            for piece_type in PAWN..=KING {
+                let mut bb = placement.pieces_mask(piece_type, color);
+                while bb != 0 {
+                    pattern = pattern.piece(piece_type, color, bb & bb.wrapping_neg());
+                    bb &= bb - 1;
+                }
+            }
+        }
+        Ok(pattern)
+    }
+    pub fn matches(&self, board: &Board) -> bool {
+        self.turn.map_or(true, |turn| board.turn == turn)
+            && self.pieces.iter().all(|(piece_type, color, mask)| board.baseboard.pieces_mask(*piece_type, *color) & mask != 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// A full position, compared by `Board::zobrist_hash`.
+    Hash(u64),
+    Pattern(Pattern)
+}
+impl Target {
+    pub fn fen(fen: &str) -> Result<Target, FenError> {
+        Ok(Target::Hash(Board::from_fen(fen)?.zobrist_hash()))
+    }
+    pub fn matches(&self, board: &Board) -> bool {
+        match self {
+            Target::Hash(hash) => board.zobrist_hash() == *hash,
+            Target::Pattern(pattern) => pattern.matches(board)
+        }
+    }
+}
+
+/// Plies of the mainline of one game at which the target occurs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameHits {
+    pub offset: u64,
+    pub plies: Vec<u64>
+}
+/// Visitor checking every mainline position of a game against a target.
+/// Variations are skipped.
+pub struct PositionSearch {
+    target: Target,
+    hits: GameHits
+}
+impl PositionSearch {
+    pub fn new(target: Target) -> PositionSearch {
+        PositionSearch { target, hits: GameHits { offset: 0, plies: Vec::new() } }
+    }
+}
+impl Visitor for PositionSearch {
+    type Output = GameHits;
+    fn begin_game(&mut self) -> Option<SkipType> {
+        self.hits = GameHits { offset: 0, plies: Vec::new() };
+        None
+    }
+    fn visit_offset(&mut self, offset: u64) {
+        self.hits.offset = offset;
+    }
+    fn visit_board(&mut self, board: &Board) {
+        if self.target.matches(board) {
+            self.hits.plies.push(board.ply());
+        }
+    }
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        Some(SkipType::SKIP)
+    }
+    fn result(&mut self) -> GameHits {
+        std::mem::replace(&mut self.hits, GameHits { offset: 0, plies: Vec::new() })
+    }
+}
+
+/// One occurrence of the target: the index of the game in the file, where
+/// the game starts and the ply at which the position was on the board.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchHit {
+    pub game: u64,
+    pub offset: u64,
+    pub ply: u64
+}
+/// Streams every occurrence of `target` in the games read from `handle`.
+/// Games that fail to parse are reported and the search goes on.
+pub fn search<R: BufRead>(handle: pgn::BufReader<R>, target: Target) -> impl Iterator<Item = Result<SearchHit, ParsingError>> {
+    Games::with_visitor(handle, PositionSearch::new(target))
+        .enumerate()
+        .flat_map(|(game, hits)| {
+            let hits: Vec<Result<SearchHit, ParsingError>> = match hits {
+                Ok(hits) => hits.plies.iter().map(|ply| Ok(SearchHit { game: game as u64, offset: hits.offset, ply: *ply })).collect(),
+                Err(e) => Vec::from([Err(e)])
+            };
+            hits
+        })
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    #[error("invalid material signature {signature}: {reason}")]
+    Invalid { signature: String, reason: &'static str }
+}
+/// A material signature such as `krpvkr` or `KPRvKR` in the form of
+/// `BaseBoard::material_signature`: uppercase, pieces from king to pawn.
+fn canonical_signature(signature: &str) -> Result<String, SignatureError> {
+    let invalid = |reason| SignatureError::Invalid { signature: signature.to_string(), reason };
+    let (white, black) = signature.split_once(['v', 'V']).ok_or_else(|| invalid("expected one 'v' between the sides"))?;
+    let mut sides = Vec::new();
+    for side in [white, black] {
+        let mut piece_types = side.chars()
+            .map(|c| PIECE_SYMBOLS.iter().position(|symbol| *symbol == Some(c.to_ascii_lowercase())).ok_or_else(|| invalid("unknown piece")))
+            .collect::<Result<Vec<_>, _>>()?;
+        if piece_types.iter().filter(|piece_type| **piece_type == KING as usize).count() != 1 {
+            return Err(invalid("expected one king per side"));
+        }
+        piece_types.sort_unstable_by(|a, b| b.cmp(a));
+        sides.push(piece_types.into_iter().map(|piece_type| PIECE_SYMBOLS[piece_type].unwrap().to_ascii_uppercase()).collect::<String>());
+    }
+    Ok(sides.join("v"))
+}
+
+/// First ply of a game at which a material balance held for long enough.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialHit {
+    pub game: u64,
+    pub offset: u64,
+    pub first_ply: u64
+}
+/// Visitor finding where a game's mainline first keeps the material balance
+/// given by a signature such as `KRPvKR` for at least `min_plies` positions
+/// in a row.
+pub struct MaterialSearch {
+    signature: String,
+    mirrored: Option<String>,
+    min_plies: u64,
+    offset: u64,
+    streak_start: Option<u64>,
+    first_ply: Option<u64>
+}
+impl MaterialSearch {
+    /// The signature may be in any case and piece order, so `krpvkr` and
+    /// `KPRvKR` both mean `KRPvKR`.
+    pub fn new(signature: &str, min_plies: u64) -> Result<MaterialSearch, SignatureError> {
+        Ok(MaterialSearch {
+            signature: canonical_signature(signature)?,
+            mirrored: None,
+            min_plies: min_plies.max(1),
+            offset: 0,
+            streak_start: None,
+            first_ply: None
+        })
+    }
+    /// Also matches the balance with colors swapped, so `KRPvKR` finds
+    /// `KRvKRP` as well.
+    pub fn either_color(mut self) -> MaterialSearch {
+        self.mirrored = self.signature.split_once('v').map(|(white, black)| format!("{}v{}", black, white));
+        self
+    }
+}
+impl Visitor for MaterialSearch {
+    type Output = Option<(u64, u64)>;
+    fn begin_game(&mut self) -> Option<SkipType> {
+        self.streak_start = None;
+        self.first_ply = None;
+        None
+    }
+    fn visit_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+    fn visit_board(&mut self, board: &Board) {
+        if self.first_ply.is_some() {
+            return;
+        }
+        let signature = board.baseboard.material_signature();
+        if signature != self.signature && Some(&signature) != self.mirrored.as_ref() {
+            self.streak_start = None;
+            return;
+        }
+        let start = *self.streak_start.get_or_insert(board.ply());
+        if board.ply() + 1 - start >= self.min_plies {
+            self.first_ply = Some(start);
+        }
+    }
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        Some(SkipType::SKIP)
+    }
+    fn result(&mut self) -> Option<(u64, u64)> {
+        self.first_ply.take().map(|ply| (self.offset, ply))
+    }
+}
+/// Streams the games read from `handle` that pass through the material
+/// balance of `search`.
+pub fn material_search<R: BufRead>(handle: pgn::BufReader<R>, search: MaterialSearch) -> impl Iterator<Item = Result<MaterialHit, ParsingError>> {
+    Games::with_visitor(handle, search)
+        .enumerate()
+        .filter_map(|(game, hit)| match hit {
+            Ok(Some((offset, first_ply))) => Some(Ok(MaterialHit { game: game as u64, offset, first_ply })),
+            Ok(None) => None,
+            Err(e) => Some(Err(e))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::{Move, ROOK, STARTING_FEN, WHITE, BLACK, BB_SQUARES, BB_RANK_7};
+
+    fn play(fen: &str, moves: &str) -> Board {
+        let mut board = Board::new(Some(fen));
+        for m in moves.split_whitespace() {
+            board.push(Move::from_uci(m));
+        }
+        board
+    }
+
+    #[test]
+    fn hash_targets_match_transpositions() {
+        let target = Target::fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        assert!(target.matches(&play(STARTING_FEN, "e2e4 e7e5 g1f3 b8c6")));
+        assert!(target.matches(&play(STARTING_FEN, "g1f3 b8c6 e2e4 e7e5")));
+        // The move counters are not part of the position.
+        assert!(target.matches(&play(STARTING_FEN, "e2e4 e7e5 g1f3 b8c6 f1c4 g8f6 c4f1 f6g8")));
+        assert!(!target.matches(&play(STARTING_FEN, "e2e4 e7e5 g1f3 b8c6 f1e2")));
+        assert!(!target.matches(&play(STARTING_FEN, "e2e4 e7e5 g1f3 b8c6 e1e2 e8e7 e2e1 e7e8")));
+        assert!(Target::fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN w KQkq - 0 1").is_err());
+    }
+
+    #[test]
+    fn patterns_match_partial_positions() {
+        let board = Board::new(Some("6k1/1R6/8/8/8/8/5PPP/6K1 w - - 0 40"));
+        let rook_on_seventh = Pattern::new().piece(ROOK, WHITE, BB_RANK_7).piece(KING, BLACK, BB_SQUARES[62]);
+        assert!(rook_on_seventh.matches(&board));
+        assert!(rook_on_seventh.clone().turn(WHITE).matches(&board));
+        assert!(!rook_on_seventh.turn(BLACK).matches(&board));
+        assert!(!Pattern::new().piece(ROOK, BLACK, BB_RANK_7).matches(&board));
+
+        let pattern = Pattern::from_board_fen("6k1/8/8/8/8/8/5PPP/8").unwrap();
+        assert!(pattern.matches(&board));
+        assert!(!pattern.matches(&Board::new(Some("6k1/8/8/8/8/8/5PP1/6K1 w - - 0 1"))));
+        assert!(Pattern::from_board_fen("6k1/8/8/8/8/8/5PPP").is_err());
+        assert!(Pattern::from_board_fen("6k1/8/8/8/8/8/5PPP/8 w").is_err());
+    }
+
+    #[test]
+    fn signatures_are_canonical() {
+        for signature in ["KRPvKR", "KPRvKR", "krpvkr", "PRKVRK"] {
+            assert_eq!(canonical_signature(signature).unwrap(), "KRPvKR");
+        }
+        assert_eq!(canonical_signature("KRvKRP").unwrap(), "KRvKRP");
+        for signature in ["KRPKR", "KRvKvK", "KRvR", "KKRvK", "KXvK", "", "v"] {
+            assert!(canonical_signature(signature).is_err(), "{}", signature);
+        }
+    }
+
+    #[test]
+    fn material_search_either_color() {
+        let pgn = "1. e4 d5 2. exd5 Qxd5 3. Nc3 Qxd2+ 4. Qxd2 *\n\n";
+        let search = |signature: &str| {
+            let search = MaterialSearch::new(signature, 1).unwrap();
+            material_search(pgn::BufReader::new(pgn.as_bytes()), search).map(|hit| hit.unwrap().first_ply).collect::<Vec<_>>()
+        };
+        assert_eq!(search("kqrrbbnnppppppppvkqrrbbnnppppppp"), [3]);
+        assert_eq!(search("KQRRBBNNPPPPPPPvKQRRBBNNPPPPPPPP"), Vec::<u64>::new());
+        let search = MaterialSearch::new("KQRRBBNNPPPPPPPvKQRRBBNNPPPPPPPP", 1).unwrap().either_color();
+        let hits: Vec<u64> = material_search(pgn::BufReader::new(pgn.as_bytes()), search).map(|hit| hit.unwrap().first_ply).collect();
+        assert_eq!(hits, [3]);
+    }
+}
@@ -0,0 +1,1265 @@
+//! Probing of Syzygy endgame tablebases (`.rtbw` WDL and `.rtbz` DTZ files)
+//! from local directories, following the reference probing code of the
+//! format's author.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use thiserror::Error;
+
+use crate::init::{Board, Move, BB_ALL, WHITE};
+
+const TBPIECES: usize = 7;
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+const TRIANGLE: [u64; 64] = [
+    6, 0, 1, 2, 2, 1, 0, 6,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    6, 0, 1, 2, 2, 1, 0, 6
+];
+const LOWER: [u64; 64] = [
+    28,  0,  1,  2,  3,  4,  5,  6,
+     0, 29,  7,  8,  9, 10, 11, 12,
+     1,  7, 30, 13, 14, 15, 16, 17,
+     2,  8, 13, 31, 18, 19, 20, 21,
+     3,  9, 14, 18, 32, 22, 23, 24,
+     4, 10, 15, 19, 22, 33, 25, 26,
+     5, 11, 16, 20, 23, 25, 34, 27,
+     6, 12, 17, 21, 24, 26, 27, 35
+];
+const DIAG: [u64; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  8,
+     0,  1,  0,  0,  0,  0,  9,  0,
+     0,  0,  2,  0,  0, 10,  0,  0,
+     0,  0,  0,  3, 11,  0,  0,  0,
+     0,  0,  0, 12,  4,  0,  0,  0,
+     0,  0, 13,  0,  0,  5,  0,  0,
+     0, 14,  0,  0,  0,  0,  6,  0,
+    15,  0,  0,  0,  0,  0,  0,  7
+];
+const FLAP: [u64; 64] = [
+    0,  0,  0,  0,  0,  0,  0, 0,
+    0,  6, 12, 18, 18, 12,  6, 0,
+    1,  7, 13, 19, 19, 13,  7, 1,
+    2,  8, 14, 20, 20, 14,  8, 2,
+    3,  9, 15, 21, 21, 15,  9, 3,
+    4, 10, 16, 22, 22, 16, 10, 4,
+    5, 11, 17, 23, 23, 17, 11, 5,
+    0,  0,  0,  0,  0,  0,  0, 0
+];
+const PTWIST: [u64; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    47, 35, 23, 11, 10, 22, 34, 46,
+    45, 33, 21,  9,  8, 20, 32, 44,
+    43, 31, 19,  7,  6, 18, 30, 42,
+    41, 29, 17,  5,  4, 16, 28, 40,
+    39, 27, 15,  3,  2, 14, 26, 38,
+    37, 25, 13,  1,  0, 12, 24, 36,
+     0,  0,  0,  0,  0,  0,  0,  0
+];
+const INVFLAP: [usize; 24] = [
+     8, 16, 24, 32, 40, 48,
+     9, 17, 25, 33, 41, 49,
+    10, 18, 26, 34, 42, 50,
+    11, 19, 27, 35, 43, 51
+];
+const FILE_TO_FILE: [usize; 8] = [0, 1, 2, 3, 3, 2, 1, 0];
+const WDL_TO_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+const PA_FLAGS: [u8; 5] = [8, 0, 0, 0, 4];
+const WDL_TO_DTZ: [i32; 5] = [-1, -101, 0, 101, 1];
+
+struct Indices {
+    /// `binomial[k][n]` is n choose k + 1.
+    binomial: [[u64; 64]; 6],
+    pawn_idx: [[u64; 24]; 6],
+    pawn_factor: [[u64; 4]; 6],
+    /// Index of the two kings, the first in the a1-d1-d4 triangle.
+    kk_idx: [[u64; 64]; 10]
+}
+lazy_static! {
+    static ref INDICES: Indices = {
+        let mut binomial = [[0u64; 64]; 6];
+        for (i, row) in binomial.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                let (mut f, mut l) = (j as u64, 1u64);
+                for k in 1..=i as u64 {
+                    f *= (j as u64).saturating_sub(k);
+                    l *= k + 1;
+                }
+                *value = f / l;
+            }
+        }
+
+        let mut pawn_idx = [[0u64; 24]; 6];
+        let mut pawn_factor = [[0u64; 4]; 6];
+        for i in 0..6 {
+            for file in 0..4 {
+                let mut s = 0;
+                for j in 6 * file..6 * file + 6 {
+                    pawn_idx[i][j] = s;
+                    s += if i == 0 { 1 } else { binomial[i - 1][PTWIST[INVFLAP[j]] as usize] };
+                }
+                pawn_factor[i][file] = s;
+            }
+        }
+
+        // Kings next to each other are left out, and with the first king on
+        // the a1-h8 diagonal the second is below it. Positions with both on
+        // the diagonal come last.
+        let mut kk_idx = [[0u64; 64]; 10];
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        let offdiag = |square: usize| (square / 8) as i32 - (square % 8) as i32;
+        for (idx, row) in kk_idx.iter_mut().enumerate() {
+            let first = (0..64).find(|square| square % 8 < 4 && offdiag(*square) <= 0 && TRIANGLE[*square] == idx as u64).unwrap();
+            for second in 0..64 {
+                let adjacent = (first % 8).abs_diff(second % 8) <= 1 && (first / 8).abs_diff(second / 8) <= 1;
+                if adjacent || (offdiag(first) == 0 && offdiag(second) > 0) {
+                    continue;
+                }
+                if offdiag(first) == 0 && offdiag(second) == 0 {
+                    both_on_diagonal.push((idx, second));
+                }
+                else {
+                    row[second] = code;
+                    code += 1;
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            kk_idx[idx][second] = code;
+            code += 1;
+        }
+        Indices { binomial, pawn_idx, pawn_factor, kk_idx }
+    };
+}
+
+fn offdiag(square: u32) -> i32 {
+    (square >> 3) as i32 - (square & 7) as i32
+}
+fn flipdiag(square: u32) -> u32 {
+    ((square >> 3) | (square << 3)) & 63
+}
+/// n choose k.
+fn subfactor(k: usize, n: usize) -> u64 {
+    let (mut f, mut l) = (n as u64, 1u64);
+    for i in 1..k as u64 {
+        f *= n as u64 - i;
+        l *= i + 1;
+    }
+    f / l
+}
+/// `KRPvKR` read the other way round: `KRvKRP`.
+fn mirror_key(key: &str) -> String {
+    match key.split_once('v') {
+        Some((white, black)) => format!("{}v{}", black, white),
+        None => key.to_string()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SyzygyError {
+    #[error("error while reading a tablebase file: {0}")]
+    Io(#[from] io::Error),
+    #[error("missing table {0}")]
+    MissingTable(String),
+    #[error("positions with castling rights are not in the tablebases")]
+    Castling,
+    #[error("corrupt table {table}: {reason}")]
+    Corrupt { table: String, reason: &'static str }
+}
+
+/// Win/draw/loss from the side to move's point of view. Cursed wins and
+/// blessed losses are wins and losses that the fifty-move rule turns into
+/// draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2
+}
+impl Wdl {
+    fn from_i32(wdl: i32) -> Wdl {
+        match wdl {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win
+        }
+    }
+}
+
+/// Decoding state of one compressed sub-table.
+#[derive(Default)]
+struct PairsData {
+    indextable: usize,
+    sizetable: usize,
+    data: usize,
+    offset: usize,
+    symlen: Vec<u32>,
+    sympat: usize,
+    blocksize: u8,
+    idxbits: u8,
+    min_len: u8,
+    base: Vec<u64>
+}
+/// How the pieces of one side to move (and, with pawns, one leading pawn
+/// file) are mapped to an index.
+#[derive(Default, Clone)]
+struct Encoding {
+    pieces: Vec<u8>,
+    norm: Vec<usize>,
+    factor: [u64; TBPIECES],
+    tb_size: u64
+}
+
+/// The parts common to WDL and DTZ tables.
+struct Table {
+    name: String,
+    data: Vec<u8>,
+    symmetric: bool,
+    num: usize,
+    has_pawns: bool,
+    /// Pawns of the leading color, then of the other one.
+    pawns: [usize; 2],
+    enc_type: u8
+}
+impl Table {
+    fn open(path: &Path, name: &str, magic: [u8; 4]) -> Result<Table, SyzygyError> {
+        let data = fs::read(path)?;
+        if data.len() < 5 || data[..4] != magic {
+            return Err(SyzygyError::Corrupt { table: name.to_string(), reason: "invalid magic" });
+        }
+        let (white, black) = name.split_once('v').unwrap_or((name, ""));
+        let has_pawns = name.contains('P');
+        let mut pawns = [0; 2];
+        let mut enc_type = 2;
+        if has_pawns {
+            pawns = [white.matches('P').count(), black.matches('P').count()];
+            if pawns[1] > 0 && (pawns[0] == 0 || pawns[1] < pawns[0]) {
+                pawns.swap(0, 1);
+            }
+        }
+        else {
+            let unique = "KQRBN".chars().map(|piece| (white.matches(piece).count() == 1) as usize + (black.matches(piece).count() == 1) as usize).sum::<usize>();
+            if unique >= 3 {
+                enc_type = 0;
+            }
+        }
+        Ok(Table { name: name.to_string(), data, symmetric: white == black, num: name.len() - 1, has_pawns, pawns, enc_type })
+    }
+    fn corrupt(&self, reason: &'static str) -> SyzygyError {
+        SyzygyError::Corrupt { table: self.name.clone(), reason }
+    }
+    fn u8(&self, at: usize) -> u8 {
+        self.data.get(at).copied().unwrap_or(0)
+    }
+    fn u16(&self, at: usize) -> u16 {
+        u16::from_le_bytes([self.u8(at), self.u8(at + 1)])
+    }
+    fn u32(&self, at: usize) -> u32 {
+        u32::from_le_bytes([self.u8(at), self.u8(at + 1), self.u8(at + 2), self.u8(at + 3)])
+    }
+    fn u32_be(&self, at: usize) -> u32 {
+        u32::from_be_bytes([self.u8(at), self.u8(at + 1), self.u8(at + 2), self.u8(at + 3)])
+    }
+    fn u64_be(&self, at: usize) -> u64 {
+        (self.u32_be(at) as u64) << 32 | self.u32_be(at + 4) as u64
+    }
+
+    /// The piece encoding at `at`, from the low or the high nibbles.
+    fn setup_pieces(&self, at: usize, high: bool, file: Option<usize>) -> Encoding {
+        let nibble = |byte: u8| if high { byte >> 4 } else { byte & 0x0f };
+        let mut encoding = Encoding { norm: vec![0; self.num], ..Encoding::default() };
+        match file {
+            None => {
+                encoding.pieces = (0..self.num).map(|i| nibble(self.u8(at + i + 1))).collect();
+                let order = nibble(self.u8(at)) as usize;
+                encoding.norm[0] = if self.enc_type == 0 { 3 } else { 2 };
+                let start = encoding.norm[0];
+                self.set_norm(&mut encoding, start);
+                encoding.tb_size = self.calc_factors_piece(&mut encoding, order);
+            },
+            Some(file) => {
+                let skip = 1 + (self.pawns[1] > 0) as usize;
+                let order = nibble(self.u8(at)) as usize;
+                let order2 = if self.pawns[1] > 0 { nibble(self.u8(at + 1)) as usize } else { 0x0f };
+                encoding.pieces = (0..self.num).map(|i| nibble(self.u8(at + i + skip))).collect();
+                encoding.norm[0] = self.pawns[0];
+                if self.pawns[1] > 0 {
+                    encoding.norm[self.pawns[0]] = self.pawns[1];
+                }
+                self.set_norm(&mut encoding, self.pawns[0] + self.pawns[1]);
+                encoding.tb_size = self.calc_factors_pawn(&mut encoding, order, order2, file);
+            }
+        }
+        encoding
+    }
+    /// Counts the runs of equal pieces from `start` on.
+    fn set_norm(&self, encoding: &mut Encoding, start: usize) {
+        let mut i = start;
+        while i < self.num {
+            let mut j = i;
+            while j < self.num && encoding.pieces[j] == encoding.pieces[i] {
+                encoding.norm[i] += 1;
+                j += 1;
+            }
+            i += encoding.norm[i];
+        }
+    }
+    fn calc_factors_piece(&self, encoding: &mut Encoding, order: usize) -> u64 {
+        const PIVFAC: [u64; 3] = [31332, 28056, 462];
+        let norm = &encoding.norm;
+        let mut n = 64 - norm[0];
+        let (mut f, mut i, mut k) = (1, norm[0], 0);
+        while i < self.num || k == order {
+            if k == order {
+                encoding.factor[0] = f;
+                f *= PIVFAC[self.enc_type as usize];
+            }
+            else {
+                encoding.factor[i] = f;
+                f *= subfactor(norm[i], n);
+                n -= norm[i];
+                i += norm[i];
+            }
+            k += 1;
+        }
+        f
+    }
+    fn calc_factors_pawn(&self, encoding: &mut Encoding, order: usize, order2: usize, file: usize) -> u64 {
+        let norm = &encoding.norm;
+        let mut i = norm[0];
+        if order2 < 0x0f {
+            i += norm[i];
+        }
+        let mut n = 64 - i;
+        let (mut f, mut k) = (1, 0);
+        while i < self.num || k == order || k == order2 {
+            if k == order {
+                encoding.factor[0] = f;
+                f *= INDICES.pawn_factor[norm[0] - 1][file];
+            }
+            else if k == order2 {
+                encoding.factor[norm[0]] = f;
+                f *= subfactor(norm[norm[0]], 48 - norm[0]);
+            }
+            else {
+                encoding.factor[i] = f;
+                f *= subfactor(norm[i], n);
+                n -= norm[i];
+                i += norm[i];
+            }
+            k += 1;
+        }
+        f
+    }
+
+    /// Reads the Huffman header of a sub-table at `at`. Returns it with the
+    /// sizes of its index, size and data tables, where the next header
+    /// starts, and its flags.
+    fn setup_pairs(&self, at: usize, tb_size: u64, wdl: bool) -> (PairsData, [usize; 3], usize, u8) {
+        let flags = self.u8(at);
+        let mut d = PairsData::default();
+        if flags & 0x80 != 0 {
+            d.min_len = if wdl { self.u8(at + 1) } else { 0 };
+            return (d, [0; 3], at + 2, flags);
+        }
+        d.blocksize = self.u8(at + 1);
+        d.idxbits = self.u8(at + 2);
+        let real_num_blocks = self.u32(at + 4) as usize;
+        let num_blocks = real_num_blocks + self.u8(at + 3) as usize;
+        let max_len = self.u8(at + 8) as usize;
+        let min_len = self.u8(at + 9) as usize;
+        let h = max_len - min_len + 1;
+        let num_syms = self.u16(at + 10 + 2 * h) as usize;
+
+        d.offset = at + 10;
+        d.sympat = at + 12 + 2 * h;
+        d.min_len = min_len as u8;
+        let next = at + 12 + 2 * h + 3 * num_syms + (num_syms & 1);
+        let num_indices = ((tb_size + (1 << d.idxbits) - 1) >> d.idxbits) as usize;
+        let sizes = [6 * num_indices, 2 * num_blocks, (1 << d.blocksize) * real_num_blocks];
+
+        d.symlen = vec![0; num_syms];
+        let mut done = vec![false; num_syms];
+        for symbol in 0..num_syms {
+            self.calc_symlen(&mut d, symbol, &mut done);
+        }
+
+        d.base = vec![0; h];
+        for i in (0..h.saturating_sub(1)).rev() {
+            let sum = d.base[i + 1] as i64 + self.u16(d.offset + 2 * i) as i64 - self.u16(d.offset + 2 * i + 2) as i64;
+            d.base[i] = (sum / 2) as u64;
+        }
+        for (i, base) in d.base.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - (min_len + i) as u32).unwrap_or(0);
+        }
+        d.offset -= 2 * min_len;
+        (d, sizes, next, flags)
+    }
+    fn calc_symlen(&self, d: &mut PairsData, symbol: usize, done: &mut Vec<bool>) {
+        if done[symbol] {
+            return;
+        }
+        let w = d.sympat + 3 * symbol;
+        let s2 = (self.u8(w + 2) as usize) << 4 | (self.u8(w + 1) as usize) >> 4;
+        if s2 == 0x0fff {
+            d.symlen[symbol] = 0;
+        }
+        else {
+            let s1 = ((self.u8(w + 1) as usize) & 0x0f) << 8 | self.u8(w) as usize;
+            if s1 < done.len() && s2 < done.len() {
+                self.calc_symlen(d, s1, done);
+                self.calc_symlen(d, s2, done);
+                d.symlen[symbol] = d.symlen[s1] + d.symlen[s2] + 1;
+            }
+        }
+        done[symbol] = true;
+    }
+    /// The symbol at `idx` of a sub-table.
+    fn decompress_pairs(&self, d: &PairsData, idx: u64, dtz: bool) -> u16 {
+        if d.idxbits == 0 {
+            return d.min_len as u16;
+        }
+        let mainidx = (idx >> d.idxbits) as usize;
+        let mut litidx = (idx & ((1 << d.idxbits) - 1)) as i64 - (1 << (d.idxbits - 1));
+        let mut block = self.u32(d.indextable + 6 * mainidx) as usize;
+        litidx += self.u16(d.indextable + 6 * mainidx + 4) as i64;
+        if litidx < 0 {
+            while litidx < 0 {
+                block -= 1;
+                litidx += self.u16(d.sizetable + 2 * block) as i64 + 1;
+            }
+        }
+        else {
+            while litidx > self.u16(d.sizetable + 2 * block) as i64 {
+                litidx -= self.u16(d.sizetable + 2 * block) as i64 + 1;
+                block += 1;
+            }
+        }
+
+        let mut ptr = d.data + (block << d.blocksize);
+        let min_len = d.min_len as usize;
+        let mut code = self.u64_be(ptr);
+        ptr += 8;
+        let mut bitcnt = 0;
+        let mut symbol;
+        loop {
+            let mut l = min_len;
+            while l - min_len + 1 < d.base.len() && code < d.base[l - min_len] {
+                l += 1;
+            }
+            symbol = self.u16(d.offset + 2 * l) as usize + ((code - d.base[l - min_len]) >> (64 - l)) as usize;
+            let len = *d.symlen.get(symbol).unwrap_or(&0) as i64;
+            if litidx < len + 1 {
+                break;
+            }
+            litidx -= len + 1;
+            code <<= l;
+            bitcnt += l;
+            if bitcnt >= 32 {
+                bitcnt -= 32;
+                code |= (self.u32_be(ptr) as u64) << bitcnt;
+                ptr += 4;
+            }
+        }
+
+        while d.symlen.get(symbol).map_or(false, |len| *len != 0) {
+            let w = d.sympat + 3 * symbol;
+            let s1 = ((self.u8(w + 1) as usize) & 0x0f) << 8 | self.u8(w) as usize;
+            if litidx < d.symlen[s1] as i64 + 1 {
+                symbol = s1;
+            }
+            else {
+                litidx -= d.symlen[s1] as i64 + 1;
+                symbol = (self.u8(w + 2) as usize) << 4 | (self.u8(w + 1) as usize) >> 4;
+            }
+        }
+        let w = d.sympat + 3 * symbol;
+        match dtz {
+            true => ((self.u8(w + 1) as u16) & 0x0f) << 8 | self.u8(w) as u16,
+            false => self.u8(w) as u16
+        }
+    }
+
+    fn encode_piece(&self, encoding: &Encoding, pos: &mut [u32; TBPIECES]) -> u64 {
+        let n = self.num;
+        let norm = &encoding.norm;
+        if pos[0] & 0x04 != 0 {
+            pos[..n].iter_mut().for_each(|square| *square ^= 0x07);
+        }
+        if pos[0] & 0x20 != 0 {
+            pos[..n].iter_mut().for_each(|square| *square ^= 0x38);
+        }
+        let limit = if self.enc_type == 0 { 3 } else { 2 };
+        if let Some(i) = (0..n).find(|i| offdiag(pos[*i]) != 0) {
+            if i < limit && offdiag(pos[i]) > 0 {
+                pos[..n].iter_mut().for_each(|square| *square = flipdiag(*square));
+            }
+        }
+
+        let p = |i: usize| pos[i] as u64;
+        let (mut idx, mut i) = if self.enc_type == 0 {
+            let i = (pos[1] > pos[0]) as u64;
+            let j = (pos[2] > pos[0]) as u64 + (pos[2] > pos[1]) as u64;
+            let idx = if offdiag(pos[0]) != 0 {
+                TRIANGLE[pos[0] as usize] * 63 * 62 + (p(1) - i) * 62 + (p(2) - j)
+            }
+            else if offdiag(pos[1]) != 0 {
+                6 * 63 * 62 + DIAG[pos[0] as usize] * 28 * 62 + LOWER[pos[1] as usize] * 62 + p(2) - j
+            }
+            else if offdiag(pos[2]) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62 + DIAG[pos[0] as usize] * 7 * 28 + (DIAG[pos[1] as usize] - i) * 28 + LOWER[pos[2] as usize]
+            }
+            else {
+                6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + DIAG[pos[0] as usize] * 7 * 6 + (DIAG[pos[1] as usize] - i) * 6 + (DIAG[pos[2] as usize] - j)
+            };
+            (idx, 3)
+        }
+        else {
+            (INDICES.kk_idx[TRIANGLE[pos[0] as usize] as usize][pos[1] as usize], 2)
+        };
+        idx *= encoding.factor[0];
+
+        while i < n {
+            let t = norm[i];
+            pos[i..i + t].sort_unstable();
+            let mut s = 0;
+            for m in i..i + t {
+                let below = pos[..i].iter().filter(|square| pos[m] > **square).count();
+                s += INDICES.binomial[m - i][pos[m] as usize - below];
+            }
+            idx += s * encoding.factor[i];
+            i += t;
+        }
+        idx
+    }
+    /// Moves the leading pawn to `pos[0]` and returns its file group.
+    fn pawn_file(&self, pos: &mut [u32; TBPIECES]) -> usize {
+        for i in 1..self.pawns[0] {
+            if FLAP[pos[0] as usize] > FLAP[pos[i] as usize] {
+                pos.swap(0, i);
+            }
+        }
+        FILE_TO_FILE[(pos[0] & 0x07) as usize]
+    }
+    fn encode_pawn(&self, encoding: &Encoding, pos: &mut [u32; TBPIECES]) -> u64 {
+        let n = self.num;
+        let norm = &encoding.norm;
+        if pos[0] & 0x04 != 0 {
+            pos[..n].iter_mut().for_each(|square| *square ^= 0x07);
+        }
+        for i in 1..self.pawns[0] {
+            for j in i + 1..self.pawns[0] {
+                if PTWIST[pos[i] as usize] < PTWIST[pos[j] as usize] {
+                    pos.swap(i, j);
+                }
+            }
+        }
+
+        let t = self.pawns[0] - 1;
+        let mut idx = INDICES.pawn_idx[t][FLAP[pos[0] as usize] as usize];
+        for i in (1..=t).rev() {
+            idx += INDICES.binomial[t - i][PTWIST[pos[i] as usize] as usize];
+        }
+        idx *= encoding.factor[0];
+
+        // The pawns of the other color.
+        let mut i = self.pawns[0];
+        let t = i + self.pawns[1];
+        if t > i {
+            pos[i..t].sort_unstable();
+            let mut s = 0;
+            for m in i..t {
+                let below = pos[..i].iter().filter(|square| pos[m] > **square).count();
+                s += INDICES.binomial[m - i][pos[m] as usize - below - 8];
+            }
+            idx += s * encoding.factor[i];
+            i = t;
+        }
+
+        while i < n {
+            let t = norm[i];
+            pos[i..i + t].sort_unstable();
+            let mut s = 0;
+            for m in i..i + t {
+                let below = pos[..i].iter().filter(|square| pos[m] > **square).count();
+                s += INDICES.binomial[m - i][pos[m] as usize - below];
+            }
+            idx += s * encoding.factor[i];
+            i += t;
+        }
+        idx
+    }
+
+    /// Color and square mirroring for a board, and which side of the table
+    /// to use: 0 for the first color of the table name to move.
+    fn orientation(&self, board: &Board) -> (u8, u32, usize) {
+        if self.symmetric {
+            return match board.turn == WHITE {
+                true => (0, 0, 0),
+                false => (8, 0x38, 0)
+            };
+        }
+        match board.baseboard.material_signature() == self.name {
+            true => (0, 0, (board.turn != WHITE) as usize),
+            false => (8, 0x38, (board.turn == WHITE) as usize)
+        }
+    }
+    /// Fills `pos` with the squares of the pieces in table order, starting
+    /// at `start`.
+    fn squares(&self, board: &Board, pieces: &[u8], cmirror: u8, mirror: u32, pos: &mut [u32; TBPIECES], start: usize) -> Result<(), SyzygyError> {
+        let mut i = start;
+        while i < self.num {
+            let piece = pieces[i] ^ cmirror;
+            let mut bb = board.baseboard.pieces_mask(piece & 0x07, piece >> 3 == 0);
+            if bb == 0 {
+                return Err(self.corrupt("pieces do not match the position"));
+            }
+            while bb != 0 && i < TBPIECES {
+                pos[i] = bb.trailing_zeros() ^ mirror;
+                i += 1;
+                bb &= bb - 1;
+            }
+        }
+        Ok(())
+    }
+    /// Finds the file group of a pawn table and fills `pos`.
+    fn pawn_squares(&self, board: &Board, first: u8, groups: &[Vec<u8>], cmirror: u8, mirror: u32, pos: &mut [u32; TBPIECES]) -> Result<usize, SyzygyError> {
+        let piece = first ^ cmirror;
+        let mut bb = board.baseboard.pieces_mask(piece & 0x07, piece >> 3 == 0);
+        let mut i = 0;
+        while bb != 0 && i < TBPIECES {
+            pos[i] = bb.trailing_zeros() ^ mirror;
+            i += 1;
+            bb &= bb - 1;
+        }
+        let file = self.pawn_file(pos);
+        self.squares(board, &groups[file], cmirror, mirror, pos, i)?;
+        Ok(file)
+    }
+}
+
+/// Offsets of the index, size and data tables of the sub-tables, in the
+/// order the file stores them.
+fn layout(at: &mut usize, pairs: &mut [&mut PairsData], sizes: &[[usize; 3]]) {
+    for (d, size) in pairs.iter_mut().zip(sizes) {
+        d.indextable = *at;
+        *at += size[0];
+    }
+    for (d, size) in pairs.iter_mut().zip(sizes) {
+        d.sizetable = *at;
+        *at += size[1];
+    }
+    for (d, size) in pairs.iter_mut().zip(sizes) {
+        *at = (*at + 0x3f) & !0x3f;
+        d.data = *at;
+        *at += size[2];
+    }
+}
+
+struct WdlTable {
+    table: Table,
+    /// One entry per leading pawn file (a single one without pawns), with
+    /// an encoding and sub-table for each side to move.
+    groups: Vec<[(Encoding, Option<PairsData>); 2]>
+}
+impl WdlTable {
+    fn open(path: &Path, name: &str) -> Result<WdlTable, SyzygyError> {
+        let table = Table::open(path, name, WDL_MAGIC)?;
+        let split = table.u8(4) & 0x01 != 0;
+        let files = if table.u8(4) & 0x02 != 0 { 4 } else { 1 };
+        let mut at = 5;
+        let mut encodings: Vec<[Encoding; 2]> = Vec::new();
+        if !table.has_pawns {
+            encodings.push([table.setup_pieces(at, false, None), table.setup_pieces(at, true, None)]);
+            at += table.num + 1;
+        }
+        else {
+            for file in 0..4 {
+                encodings.push([table.setup_pieces(at, false, Some(file)), table.setup_pieces(at, true, Some(file))]);
+                at += table.num + 1 + (table.pawns[1] > 0) as usize;
+            }
+        }
+        at += at & 0x01;
+
+        let mut pairs: Vec<(PairsData, Option<PairsData>)> = Vec::new();
+        let mut sizes = Vec::new();
+        for encoding in encodings.iter().take(files) {
+            let (first, first_sizes, next, _) = table.setup_pairs(at, encoding[0].tb_size, true);
+            at = next;
+            sizes.push(first_sizes);
+            let second = match split {
+                true => {
+                    let (second, second_sizes, next, _) = table.setup_pairs(at, encoding[1].tb_size, true);
+                    at = next;
+                    sizes.push(second_sizes);
+                    Some(second)
+                },
+                false => None
+            };
+            pairs.push((first, second));
+        }
+        {
+            let mut refs: Vec<&mut PairsData> = Vec::new();
+            for (first, second) in pairs.iter_mut() {
+                refs.push(first);
+                if let Some(second) = second {
+                    refs.push(second);
+                }
+            }
+            layout(&mut at, &mut refs, &sizes);
+        }
+
+        let mut pairs = pairs.into_iter();
+        let groups = encodings.into_iter().map(|[first, second]| match pairs.next() {
+            Some((first_pairs, second_pairs)) => [(first, Some(first_pairs)), (second, second_pairs)],
+            None => [(first, None), (second, None)]
+        }).collect();
+        Ok(WdlTable { table, groups })
+    }
+    fn probe(&self, board: &Board) -> Result<i32, SyzygyError> {
+        let table = &self.table;
+        let (cmirror, mirror, bside) = table.orientation(board);
+        let mut pos = [0u32; TBPIECES];
+        let (pairs, idx) = if !table.has_pawns {
+            let (encoding, pairs) = &self.groups[0][bside];
+            table.squares(board, &encoding.pieces, cmirror, 0, &mut pos, 0)?;
+            (pairs, table.encode_piece(encoding, &mut pos))
+        }
+        else {
+            let groups: Vec<Vec<u8>> = self.groups.iter().map(|group| group[bside].0.pieces.clone()).collect();
+            let file = table.pawn_squares(board, self.groups[0][0].0.pieces[0], &groups, cmirror, mirror, &mut pos)?;
+            let (encoding, pairs) = &self.groups[file][bside];
+            (pairs, table.encode_pawn(encoding, &mut pos))
+        };
+        let pairs = pairs.as_ref().ok_or_else(|| table.corrupt("side to move not in table"))?;
+        Ok(table.decompress_pairs(pairs, idx, false) as i32 - 2)
+    }
+}
+
+struct DtzGroup {
+    encoding: Encoding,
+    pairs: Option<PairsData>,
+    flags: u8,
+    map_idx: [usize; 4]
+}
+struct DtzTable {
+    table: Table,
+    groups: Vec<DtzGroup>,
+    map: usize
+}
+impl DtzTable {
+    fn open(path: &Path, name: &str) -> Result<DtzTable, SyzygyError> {
+        let table = Table::open(path, name, DTZ_MAGIC)?;
+        let files = if table.u8(4) & 0x02 != 0 { 4 } else { 1 };
+        let mut at = 5;
+        let mut groups: Vec<DtzGroup> = Vec::new();
+        let group = |encoding| DtzGroup { encoding, pairs: None, flags: 0, map_idx: [0; 4] };
+        if !table.has_pawns {
+            groups.push(group(table.setup_pieces(at, false, None)));
+            at += table.num + 1;
+        }
+        else {
+            for file in 0..4 {
+                groups.push(group(table.setup_pieces(at, false, Some(file))));
+                at += table.num + 1 + (table.pawns[1] > 0) as usize;
+            }
+        }
+        at += at & 0x01;
+
+        let mut sizes = Vec::new();
+        for group in groups.iter_mut().take(files) {
+            let (pairs, group_sizes, next, flags) = table.setup_pairs(at, group.encoding.tb_size, false);
+            at = next;
+            sizes.push(group_sizes);
+            group.pairs = Some(pairs);
+            group.flags = flags;
+        }
+
+        let map = at;
+        for group in groups.iter_mut().take(files) {
+            if group.flags & 2 == 0 {
+                continue;
+            }
+            if group.flags & 16 == 0 {
+                for i in 0..4 {
+                    group.map_idx[i] = at + 1 - map;
+                    at += 1 + table.u8(at) as usize;
+                }
+            }
+            else {
+                at += at & 0x01;
+                for i in 0..4 {
+                    group.map_idx[i] = (at + 2 - map) / 2;
+                    at += 2 + 2 * table.u16(at) as usize;
+                }
+            }
+        }
+        at += at & 0x01;
+
+        {
+            let mut refs: Vec<&mut PairsData> = groups.iter_mut().filter_map(|group| group.pairs.as_mut()).collect();
+            layout(&mut at, &mut refs, &sizes);
+        }
+        Ok(DtzTable { table, groups, map })
+    }
+    /// The DTZ of a position with WDL `wdl`, or None if the table is for the
+    /// other side to move.
+    fn probe(&self, board: &Board, wdl: i32) -> Result<Option<i32>, SyzygyError> {
+        let table = &self.table;
+        let (cmirror, mirror, bside) = table.orientation(board);
+        let mut pos = [0u32; TBPIECES];
+        let (group, idx) = if !table.has_pawns {
+            let group = &self.groups[0];
+            if (group.flags & 1) as usize != bside && !table.symmetric {
+                return Ok(None);
+            }
+            table.squares(board, &group.encoding.pieces, cmirror, 0, &mut pos, 0)?;
+            (group, table.encode_piece(&group.encoding, &mut pos))
+        }
+        else {
+            let pieces: Vec<Vec<u8>> = self.groups.iter().map(|group| group.encoding.pieces.clone()).collect();
+            let file = table.pawn_squares(board, self.groups[0].encoding.pieces[0], &pieces, cmirror, mirror, &mut pos)?;
+            let group = &self.groups[file];
+            if (group.flags & 1) as usize != bside {
+                return Ok(None);
+            }
+            (group, table.encode_pawn(&group.encoding, &mut pos))
+        };
+
+        let pairs = group.pairs.as_ref().ok_or_else(|| table.corrupt("missing pawn file"))?;
+        let mut res = table.decompress_pairs(pairs, idx, true) as i32;
+        if group.flags & 2 != 0 {
+            let map_idx = group.map_idx[WDL_TO_MAP[(wdl + 2) as usize]];
+            res = match group.flags & 16 {
+                0 => table.u8(self.map + map_idx + res as usize) as i32,
+                _ => table.u16(self.map + 2 * (map_idx + res as usize)) as i32
+            };
+        }
+        if group.flags & PA_FLAGS[(wdl + 2) as usize] == 0 || wdl & 1 != 0 {
+            res *= 2;
+        }
+        Ok(Some(res))
+    }
+}
+
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    wdl.signum() * if wdl.abs() == 2 { 1 } else { 101 }
+}
+
+/// A move chosen by `Tablebase::best_move`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TablebaseMove {
+    pub m: Move,
+    /// Outcome for the side to move, counting the plies since the last
+    /// capture or pawn move towards the fifty-move rule.
+    pub wdl: Wdl,
+    /// Distance to zeroing after the move, counted from the root position.
+    pub dtz: i32
+}
+
+/// Syzygy tables found in local directories. Tables are read on first use
+/// and kept in memory.
+pub struct Tablebase {
+    wdl_paths: HashMap<String, PathBuf>,
+    dtz_paths: HashMap<String, PathBuf>,
+    wdl: Mutex<HashMap<String, Arc<WdlTable>>>,
+    dtz: Mutex<HashMap<String, Arc<DtzTable>>>,
+    max_pieces: usize
+}
+impl Tablebase {
+    pub fn new() -> Tablebase {
+        Tablebase { wdl_paths: HashMap::new(), dtz_paths: HashMap::new(), wdl: Mutex::new(HashMap::new()), dtz: Mutex::new(HashMap::new()), max_pieces: 0 }
+    }
+    pub fn open_directory(path: impl AsRef<Path>) -> io::Result<Tablebase> {
+        let mut tablebase = Tablebase::new();
+        tablebase.add_directory(path)?;
+        Ok(tablebase)
+    }
+    /// Adds the `.rtbw` and `.rtbz` files of a directory and returns how
+    /// many were found.
+    pub fn add_directory(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let mut found = 0;
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            let (name, extension) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|s| s.to_str())) {
+                (Some(name), Some(extension)) => (name.to_string(), extension),
+                _ => continue
+            };
+            let valid = name.len() <= TBPIECES + 1 && name.matches('v').count() == 1 && name.chars().all(|c| "KQRBNPv".contains(c))
+                && name.split('v').all(|side| side.matches('K').count() == 1);
+            if !valid {
+                continue;
+            }
+            let paths = match extension {
+                "rtbw" => &mut self.wdl_paths,
+                "rtbz" => &mut self.dtz_paths,
+                _ => continue
+            };
+            self.max_pieces = self.max_pieces.max(name.len() - 1);
+            paths.insert(name, path);
+            found += 1;
+        }
+        Ok(found)
+    }
+    /// The largest number of pieces of the tables found.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// The table for the material of `board`, under either color order.
+    fn table<T>(&self, board: &Board, paths: &HashMap<String, PathBuf>, cache: &Mutex<HashMap<String, Arc<T>>>,
+                open: fn(&Path, &str) -> Result<T, SyzygyError>) -> Result<Arc<T>, SyzygyError> {
+        let key = board.baseboard.material_signature();
+        let (name, path) = [key.clone(), mirror_key(&key)].into_iter()
+            .find_map(|name| paths.get(&name).map(|path| (name, path.clone())))
+            .ok_or(SyzygyError::MissingTable(key))?;
+        if let Some(table) = cache.lock().unwrap().get(&name) {
+            return Ok(table.clone());
+        }
+        let table = Arc::new(open(&path, &name)?);
+        cache.lock().unwrap().insert(name, table.clone());
+        Ok(table)
+    }
+    fn probe_wdl_table(&self, board: &Board) -> Result<i32, SyzygyError> {
+        if board.baseboard.occupied == board.baseboard.kings {
+            return Ok(0);
+        }
+        self.table(board, &self.wdl_paths, &self.wdl, WdlTable::open)?.probe(board)
+    }
+    fn probe_dtz_table(&self, board: &Board, wdl: i32) -> Result<Option<i32>, SyzygyError> {
+        self.table(board, &self.dtz_paths, &self.dtz, DtzTable::open)?.probe(board, wdl)
+    }
+
+    /// Alpha-beta over captures, since tables assume the side to move
+    /// cannot capture profitably. The flag is 2 if a capture is best.
+    fn probe_ab(&self, board: &mut Board, mut alpha: i32, beta: i32) -> Result<(i32, u8), SyzygyError> {
+        let captures: Vec<Move> = board.generate_legal_moves(BB_ALL, board.baseboard.occupied_co[!board.turn as usize]).collect();
+        for m in captures {
+            board.push(m);
+            let v = self.probe_ab(board, -beta, -alpha);
+            board.pop();
+            let v = -v?.0;
+            if v > alpha {
+                if v >= beta {
+                    return Ok((v, 2));
+                }
+                alpha = v;
+            }
+        }
+        let v = self.probe_wdl_table(board)?;
+        match alpha >= v {
+            true => Ok((alpha, 1 + (alpha > 0) as u8)),
+            false => Ok((v, 1))
+        }
+    }
+    /// The best value of the legal en passant captures, if there are any.
+    fn probe_ep(&self, board: &mut Board) -> Result<Option<i32>, SyzygyError> {
+        let captures: Vec<Move> = board.generate_legal_ep(BB_ALL, BB_ALL).collect();
+        let mut best = None;
+        for m in captures {
+            board.push(m);
+            let v = self.probe_ab(board, -2, 2);
+            board.pop();
+            let v = -v?.0;
+            best = Some(best.map_or(v, |best: i32| best.max(v)));
+        }
+        Ok(best)
+    }
+    fn wdl(&self, board: &mut Board) -> Result<i32, SyzygyError> {
+        if board.castling_rights != 0 {
+            return Err(SyzygyError::Castling);
+        }
+        let (mut v, _) = self.probe_ab(board, -2, 2)?;
+        if let Some(v1) = self.probe_ep(board)? {
+            if v1 >= v {
+                v = v1;
+            }
+            else if v == 0 && board.generate_legal_moves(BB_ALL, BB_ALL).all(|m| board.is_en_passant(m)) {
+                // The losing en passant capture is forced.
+                v = v1;
+            }
+        }
+        Ok(v)
+    }
+    fn dtz_no_ep(&self, board: &mut Board) -> Result<i32, SyzygyError> {
+        let (wdl, flag) = self.probe_ab(board, -2, 2)?;
+        if wdl == 0 {
+            return Ok(0);
+        }
+        if flag == 2 {
+            return Ok(dtz_before_zeroing(wdl));
+        }
+        if wdl > 0 {
+            // A pawn move that keeps the result zeroes at once.
+            let pawn_moves: Vec<Move> = board.generate_legal_moves(board.baseboard.pawns, !board.baseboard.occupied)
+                .filter(|m| !board.is_en_passant(*m))
+                .collect();
+            for m in pawn_moves {
+                board.push(m);
+                let v = self.wdl(board);
+                board.pop();
+                if -v? == wdl {
+                    return Ok(dtz_before_zeroing(wdl));
+                }
+            }
+        }
+
+        if let Some(dtz) = self.probe_dtz_table(board, wdl)? {
+            return Ok(dtz_before_zeroing(wdl) + if wdl > 0 { dtz } else { -dtz });
+        }
+
+        // The table is for the other side to move: search one ply.
+        if wdl > 0 {
+            let mut best = 0xffff;
+            let moves: Vec<Move> = board.generate_legal_moves(board.baseboard.occupied_co[board.turn as usize] & !board.baseboard.pawns, !board.baseboard.occupied).collect();
+            for m in moves {
+                board.push(m);
+                let v = self.dtz(board).map(|v| (-v, board.is_checkmate()));
+                board.pop();
+                match v? {
+                    (1, true) => best = 1,
+                    (v, _) if v > 0 && v + 1 < best => best = v + 1,
+                    _ => {}
+                }
+            }
+            Ok(best)
+        }
+        else {
+            let mut best = -1;
+            let moves: Vec<Move> = board.generate_legal_moves(BB_ALL, BB_ALL).collect();
+            for m in moves {
+                board.push(m);
+                let v = match board.halfmove_clock {
+                    0 if wdl == -2 => Ok(-1),
+                    0 => self.probe_ab(board, 1, 2).map(|(v, _)| if v == 2 { 0 } else { -101 }),
+                    _ => self.dtz(board).map(|v| -v - 1)
+                };
+                board.pop();
+                best = best.min(v?);
+            }
+            Ok(best)
+        }
+    }
+    fn dtz(&self, board: &mut Board) -> Result<i32, SyzygyError> {
+        let mut v = self.dtz_no_ep(board)?;
+        let v1 = match self.probe_ep(board)? {
+            Some(v1) => WDL_TO_DTZ[(v1 + 2) as usize],
+            None => return Ok(v)
+        };
+        let replace = match v {
+            v if v < -100 => v1 >= 0,
+            v if v < 0 => v1 >= 0 || v1 < -100,
+            v if v > 100 => v1 > 0,
+            v if v > 0 => v1 == 1,
+            _ => v1 >= 0 || board.generate_legal_moves(BB_ALL, BB_ALL).all(|m| board.is_en_passant(m))
+        };
+        if replace {
+            v = v1;
+        }
+        Ok(v)
+    }
+
+    /// Win/draw/loss of `board` for the side to move, assuming the
+    /// fifty-move counter was reset by the last move.
+    pub fn probe_wdl(&self, board: &Board) -> Result<Wdl, SyzygyError> {
+        self.wdl(&mut board.copy(false)).map(Wdl::from_i32)
+    }
+    /// Distance to zeroing: plies to the next capture or pawn move that keeps
+    /// the result, positive when the side to move wins and negative when it
+    /// loses, 0 for draws. Values beyond 100 mark cursed wins and blessed
+    /// losses. The value may be one more than the exact distance when the
+    /// table stores it in steps of two.
+    pub fn probe_dtz(&self, board: &Board) -> Result<i32, SyzygyError> {
+        self.dtz(&mut board.copy(false))
+    }
+    /// The best move of `board`, or None if there are no legal moves. Wins
+    /// that the fifty-move rule allows, given `halfmove_clock`, come first,
+    /// fastest first, then wins that it spoils, then draws, then losses,
+    /// slowest first.
+    pub fn best_move(&self, board: &Board) -> Result<Option<TablebaseMove>, SyzygyError> {
+        let mut board = board.copy(false);
+        let clock = board.halfmove_clock as i32;
+        let moves: Vec<Move> = board.generate_legal_moves(BB_ALL, BB_ALL).collect();
+        let mut best: Option<(i32, TablebaseMove)> = None;
+        for m in moves {
+            board.push(m);
+            let dtz = if board.halfmove_clock == 0 {
+                self.wdl(&mut board).map(|wdl| dtz_before_zeroing(-wdl))
+            }
+            else {
+                self.dtz(&mut board).map(|dtz| -dtz - dtz.signum())
+            };
+            let mate = board.is_checkmate();
+            board.pop();
+            let dtz = match dtz? {
+                2 if mate => 1,
+                dtz => dtz
+            };
+
+            let in_time = |dtz: i32| dtz.abs() + if dtz.abs() > 100 { 0 } else { clock } <= 100;
+            let (wdl, rank) = match dtz {
+                dtz if dtz > 0 && dtz <= 100 && in_time(dtz) => (Wdl::Win, 2000 - dtz),
+                dtz if dtz > 0 => (Wdl::CursedWin, 1000 - dtz),
+                0 => (Wdl::Draw, 0),
+                dtz if dtz >= -100 && in_time(dtz) => (Wdl::Loss, -2000 - dtz),
+                dtz => (Wdl::BlessedLoss, -1000 - dtz)
+            };
+            if best.map_or(true, |(best_rank, _)| rank > best_rank) {
+                best = Some((rank, TablebaseMove { m, wdl, dtz }));
+            }
+        }
+        Ok(best.map(|(_, best)| best))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Most tests need the official tables listed in
+    // `tests/fixtures/syzygy/README.md`.
+    fn tablebase() -> Tablebase {
+        Tablebase::open_directory(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/syzygy")).unwrap()
+    }
+    fn wdl(fen: &str) -> Wdl {
+        tablebase().probe_wdl(&Board::new(Some(fen))).unwrap()
+    }
+    fn dtz(fen: &str) -> i32 {
+        tablebase().probe_dtz(&Board::new(Some(fen))).unwrap()
+    }
+    fn best_move(fen: &str) -> Option<TablebaseMove> {
+        tablebase().best_move(&Board::new(Some(fen))).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn kqk() {
+        assert_eq!(wdl("4k3/8/8/8/8/8/8/3QK3 w - - 0 1"), Wdl::Win);
+        assert_eq!(wdl("4k3/8/8/8/8/8/8/3QK3 b - - 0 1"), Wdl::Loss);
+        // The queen hangs.
+        assert_eq!(wdl("8/8/8/3k4/4Q3/8/8/7K b - - 0 1"), Wdl::Draw);
+        // Stalemate.
+        assert_eq!(wdl("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), Wdl::Draw);
+        // Checkmate.
+        assert_eq!(wdl("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), Wdl::Loss);
+        assert_eq!(best_move("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), None);
+        // The same material the other way round.
+        assert_eq!(wdl("3qk3/8/8/8/8/8/8/4K3 w - - 0 1"), Wdl::Loss);
+        assert_eq!(wdl("3qk3/8/8/8/8/8/8/4K3 b - - 0 1"), Wdl::Win);
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn krk() {
+        assert_eq!(wdl("8/8/8/3k4/4R3/8/8/7K b - - 0 1"), Wdl::Draw);
+        assert_eq!(dtz("1k6/8/1K6/8/8/8/8/7R w - - 0 1"), 1);
+        // 1... Kb8 2. Rh8#, or one more if the table stores steps of two.
+        assert!(matches!(dtz("k7/8/1K6/8/8/8/8/7R b - - 0 1"), -3..=-2));
+        let won = dtz("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        assert!(won > 2 && won <= 100);
+        assert!(dtz("4k3/8/8/8/8/8/8/R3K3 b - - 0 1") < -2);
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn kpk() {
+        // The king on the sixth rank in front of its pawn wins either way.
+        assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"), Wdl::Win);
+        assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), Wdl::Loss);
+        assert!(dtz("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1") < 0);
+        // The defending king has the opposition.
+        assert_eq!(wdl("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1"), Wdl::Draw);
+        assert_eq!(dtz("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1"), 0);
+        // The pawn hangs.
+        assert_eq!(wdl("8/8/8/8/8/8/3kP3/7K b - - 0 1"), Wdl::Draw);
+        // Stalemate.
+        assert_eq!(wdl("k7/P7/1K6/8/8/8/8/8 b - - 0 1"), Wdl::Draw);
+        // Black pawns are read from the same table.
+        assert_eq!(wdl("8/8/8/8/4p3/4k3/8/4K3 w - - 0 1"), Wdl::Loss);
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn kbnk() {
+        // The example of the python-chess documentation.
+        assert_eq!(wdl("8/2K5/4B3/3N4/8/8/4k3/8 b - - 0 1"), Wdl::Loss);
+        assert_eq!(dtz("8/2K5/4B3/3N4/8/8/4k3/8 b - - 0 1"), -53);
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn krkp() {
+        // The rook takes the blocked pawn.
+        assert_eq!(wdl("4k3/8/8/8/8/8/p7/R3K3 w - - 0 1"), Wdl::Win);
+        assert_eq!(dtz("4k3/8/8/8/8/8/p7/R3K3 w - - 0 1"), 1);
+        assert_eq!(wdl("4k3/8/8/8/8/8/p7/R3K3 b - - 0 1"), Wdl::Loss);
+        // The pawn takes the rook.
+        assert_eq!(wdl("4k3/8/8/8/8/8/p7/1R2K3 b - - 0 1"), Wdl::Win);
+        assert_eq!(wdl("4K3/8/8/8/8/8/P7/1r2k3 w - - 0 1"), Wdl::Win);
+        let best = best_move("4K3/8/8/8/8/8/P7/1r2k3 w - - 0 1").unwrap();
+        assert_eq!((best.m.uci().get(..4), best.wdl, best.dtz), (Some("a2b1"), Wdl::Win, 1));
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn promotion() {
+        assert_eq!(dtz("8/4P3/8/8/8/8/k7/4K3 w - - 0 1"), 1);
+        let best = best_move("8/4P3/8/8/8/8/k7/4K3 w - - 0 1").unwrap();
+        assert_eq!((best.m.uci().get(..4), best.wdl, best.dtz), (Some("e7e8"), Wdl::Win, 1));
+        assert!(best.m.uci().ends_with('q') || best.m.uci().ends_with('r'));
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn best_move_mates() {
+        let best = best_move("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1").unwrap();
+        assert_eq!(best, TablebaseMove { m: Move::from_uci("c1c8"), wdl: Wdl::Win, dtz: 1 });
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy tables in tests/fixtures/syzygy"]
+    fn best_move_cursed_win() {
+        // No mate in one, so every win takes more plies than the fifty-move
+        // rule leaves.
+        let best = best_move("4k3/8/8/8/8/8/8/3QK3 w - - 98 1").unwrap();
+        assert_eq!(best.wdl, Wdl::CursedWin);
+        assert!(best.dtz > 2);
+        let best = best_move("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        assert_eq!(best.wdl, Wdl::Win);
+        assert_eq!(wdl("4k3/8/8/8/8/8/8/3QK3 w - - 98 1"), Wdl::Win);
+    }
+
+    #[test]
+    fn missing_table() {
+        let tablebase = Tablebase::new();
+        let result = tablebase.probe_wdl(&Board::new(Some("4k3/8/8/8/8/8/8/3QK2R w - - 0 1")));
+        assert!(matches!(result, Err(SyzygyError::MissingTable(_))));
+        let result = tablebase.probe_wdl(&Board::new(Some("4k3/8/8/8/8/8/8/4K2R w K - 0 1")));
+        assert!(matches!(result, Err(SyzygyError::Castling)));
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let path = std::env::temp_dir().join(format!("syzygy-magic-{}", std::process::id()));
+        fs::write(&path, [0u8; 16]).unwrap();
+        let result = Table::open(&path, "KQvK", WDL_MAGIC);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(SyzygyError::Corrupt { reason: "invalid magic", .. })));
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashSet;
+use std::io::{self, prelude::*};
+
+use crate::init::{Move, PACKED_SIZE, WHITE};
+use crate::pgn::{self, Game, Games, ParsingError, Score};
+
+const MAGIC: &[u8; 8] = b"RCTRAIN1";
+/// Size of a record in the binary format, see `write_binary`.
+pub const RECORD_SIZE: usize = PACKED_SIZE + 12;
+
+/// One position of a game and the move that was played from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingRecord {
+    pub fen: String,
+    pub m: Move,
+    /// 1 if the side to move went on to win, 0 for a draw, -1 for a loss.
+    pub result: i8,
+    pub ply: u64,
+    pub white_elo: Option<u32>,
+    pub black_elo: Option<u32>,
+    /// The `%eval` of the position, from the side to move's point of view.
+    pub eval: Option<Score>,
+    packed: [u8; PACKED_SIZE]
+}
+impl TrainingRecord {
+    /// `fen`, `uci`, `result`, `ply`, `white_elo`, `black_elo`, `eval` separated
+    /// by tabs. Unknown values are `-`; evals are written `cp 31` or `mate -2`.
+    pub fn to_text(&self) -> String {
+        let elo = |elo: Option<u32>| elo.map_or(String::from("-"), |elo| elo.to_string());
+        let eval = match self.eval {
+            Some(Score::Cp(cp)) => format!("cp {}", cp),
+            Some(Score::Mate(mate)) => format!("mate {}", mate),
+            None => String::from("-")
+        };
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}", self.fen, self.m.uci(), self.result, self.ply, elo(self.white_elo), elo(self.black_elo), eval)
+    }
+    /// The record in the binary format, see `write_binary`.
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[..PACKED_SIZE].copy_from_slice(&self.packed);
+        let rest = &mut bytes[PACKED_SIZE..];
+        let m = self.m.from_square as u16 | (self.m.to_square as u16) << 6 | (self.m.promotion.unwrap_or(0) as u16) << 12;
+        rest[0..2].copy_from_slice(&m.to_le_bytes());
+        rest[2] = self.result as u8;
+        rest[3..5].copy_from_slice(&(self.ply.min(u16::MAX as u64) as u16).to_le_bytes());
+        rest[5..7].copy_from_slice(&(self.white_elo.unwrap_or(0).min(u16::MAX as u32) as u16).to_le_bytes());
+        rest[7..9].copy_from_slice(&(self.black_elo.unwrap_or(0).min(u16::MAX as u32) as u16).to_le_bytes());
+        let (kind, value) = match self.eval {
+            None => (0, 0),
+            Some(Score::Cp(cp)) => (1, cp.clamp(i16::MIN as i32, i16::MAX as i32) as i16),
+            Some(Score::Mate(mate)) => (2, mate.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+        };
+        rest[9] = kind;
+        rest[10..12].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+}
+
+/// Which positions of a game become records. Built like
+/// `TrainingFilter::new().skip_opening(16).skip_check().dedup().sample(0.1, 7)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrainingFilter {
+    skip_opening: u64,
+    skip_check: bool,
+    dedup: bool,
+    sample: f64,
+    seed: u64,
+    min_elo: u32,
+    max_elo: u32
+}
+impl TrainingFilter {
+    /// Every position of every game with a result.
+    pub fn new() -> TrainingFilter {
+        TrainingFilter { skip_opening: 0, skip_check: false, dedup: false, sample: 1.0, seed: 0, min_elo: 0, max_elo: u32::MAX }
+    }
+    /// Skips the first `plies` positions of each game.
+    pub fn skip_opening(mut self, plies: u64) -> TrainingFilter {
+        self.skip_opening = plies;
+        self
+    }
+    /// Skips positions where the side to move is in check.
+    pub fn skip_check(mut self) -> TrainingFilter {
+        self.skip_check = true;
+        self
+    }
+    /// Keeps only the first occurrence of every position, by Zobrist hash.
+    pub fn dedup(mut self) -> TrainingFilter {
+        self.dedup = true;
+        self
+    }
+    /// Keeps each position with probability `rate`. The same seed selects
+    /// the same positions.
+    pub fn sample(mut self, rate: f64, seed: u64) -> TrainingFilter {
+        self.sample = rate;
+        self.seed = seed;
+        self
+    }
+    /// Only games where both players are rated within `min..=max`.
+    pub fn elo_range(mut self, min: u32, max: u32) -> TrainingFilter {
+        self.min_elo = min;
+        self.max_elo = max;
+        self
+    }
+}
+
+/// Turns games into training records, keeping the state that filtering
+/// needs across games.
+pub struct TrainingExporter {
+    filter: TrainingFilter,
+    seen: HashSet<u64>,
+    rng: u64
+}
+impl TrainingExporter {
+    pub fn new(filter: TrainingFilter) -> TrainingExporter {
+        TrainingExporter { filter, seen: HashSet::new(), rng: filter.seed }
+    }
+    /// A uniform number in [0, 1) from splitmix64.
+    fn random(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as f64 / (u64::MAX as f64 + 1.0)
+    }
+    /// Records of the mainline positions of `game` that pass the filter.
+    /// Games without a result, or with a rating outside the Elo range, give
+    /// none. Positions of more than 32 pieces, which only a FEN header can
+    /// set up, are skipped.
+    pub fn records(&mut self, game: &Game) -> Vec<TrainingRecord> {
+        let filter = self.filter;
+        let white_result: i8 = match game.headers.get("Result") {
+            Some("1-0") => 1,
+            Some("0-1") => -1,
+            Some("1/2-1/2") => 0,
+            _ => return Vec::new()
+        };
+        let white_elo = game.headers.get("WhiteElo").and_then(|elo| elo.parse::<u32>().ok());
+        let black_elo = game.headers.get("BlackElo").and_then(|elo| elo.parse::<u32>().ok());
+        let in_range = |elo: Option<u32>| elo.is_some_and(|elo| (filter.min_elo..=filter.max_elo).contains(&elo));
+        if (filter.min_elo > 0 || filter.max_elo < u32::MAX) && !(in_range(white_elo) && in_range(black_elo)) {
+            return Vec::new();
+        }
+
+        let mut records = Vec::new();
+        let mut board = game.setup_board();
+        let mut eval = game.root().eval();
+        for node in game.root().mainline() {
+            let m = node.m().unwrap();
+            let keep = board.ply() >= filter.skip_opening
+                && !(filter.skip_check && board.is_check())
+                && (filter.sample >= 1.0 || self.random() < filter.sample)
+                && !(filter.dedup && !self.seen.insert(board.zobrist_hash()));
+            if let (true, Ok(packed)) = (keep, board.to_packed()) {
+                records.push(TrainingRecord {
+                    fen: board.fen(),
+                    m,
+                    result: if board.turn == WHITE { white_result } else { -white_result },
+                    ply: board.ply(),
+                    white_elo,
+                    black_elo,
+                    eval: eval.map(|eval| eval.pov(board.turn)),
+                    packed
+                });
+            }
+            board.push(m);
+            eval = node.eval();
+        }
+        records
+    }
+    fn export<R: BufRead>(&mut self, handle: pgn::BufReader<R>, mut write: impl FnMut(&TrainingRecord) -> io::Result<()>) -> io::Result<u64> {
+        let mut written = 0;
+        for game in Games::new(handle) {
+            let game = match game {
+                Ok(game) => game,
+                Err(ParsingError::ReadLineError(e)) => return Err(e),
+                Err(_) => continue
+            };
+            for record in self.records(&game) {
+                write(&record)?;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+    /// Writes the records of every game of `handle` as `TrainingRecord::to_text`
+    /// lines and returns how many were written. Games that fail to parse are
+    /// skipped.
+    pub fn write_text<R: BufRead, W: Write>(&mut self, handle: pgn::BufReader<R>, writer: &mut W) -> io::Result<u64> {
+        self.export(handle, |record| writeln!(writer, "{}", record.to_text()))
+    }
+    /// Writes the records of every game of `handle` in the binary format and
+    /// returns how many were written. The file is the magic `RCTRAIN1` and
+    /// then fixed-size little-endian records of `RECORD_SIZE` bytes: the
+    /// position as `Board::to_packed`, the move u16 (from, to << 6, promotion
+    /// << 12), the result i8, the ply u16, the white and black Elo u16 (0 if
+    /// unknown), the eval kind u8 (0 none, 1 centipawns, 2 mate) and the eval
+    /// value i16.
+    pub fn write_binary<R: BufRead, W: Write>(&mut self, handle: pgn::BufReader<R>, writer: &mut W) -> io::Result<u64> {
+        writer.write_all(MAGIC)?;
+        self.export(handle, |record| writer.write_all(&record.to_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::Board;
+    use crate::pgn::{read_game, GameBuilder};
+
+    const PGN: &str = "[Result \"0-1\"]\n[WhiteElo \"2000\"]\n[BlackElo \"2100\"]\n\n1. e4 {[%eval 0.3]} f5 {[%eval 1.5]} 2. Qh5+ g6 {[%eval #-3]} 0-1\n\n";
+
+    fn game(pgn: &str) -> Game {
+        read_game(&mut pgn::BufReader::new(pgn.as_bytes()), &mut GameBuilder::new()).unwrap()
+    }
+    fn plies(exporter: &mut TrainingExporter, game: &Game) -> Vec<u64> {
+        exporter.records(game).iter().map(|record| record.ply).collect()
+    }
+
+    #[test]
+    fn records_of_the_mainline() {
+        let records = TrainingExporter::new(TrainingFilter::new()).records(&game(PGN));
+        let text: Vec<String> = records.iter().map(|record| record.to_text()).collect();
+        assert_eq!(text, [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\te2e4\t-1\t0\t2000\t2100\t-",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\tf7f5\t1\t1\t2000\t2100\tcp -30",
+            "rnbqkbnr/ppppp1pp/8/5p2/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2\td1h5\t-1\t2\t2000\t2100\tcp 150",
+            "rnbqkbnr/ppppp1pp/8/5p1Q/4P3/8/PPPP1PPP/RNB1KBNR b KQkq - 1 2\tg7g6\t1\t3\t2000\t2100\t-"
+        ]);
+        let mut game = game(PGN);
+        game.headers.set("Result", "*");
+        assert!(TrainingExporter::new(TrainingFilter::new()).records(&game).is_empty());
+    }
+
+    #[test]
+    fn filters_positions() {
+        let game = game(PGN);
+        assert_eq!(plies(&mut TrainingExporter::new(TrainingFilter::new().skip_opening(2)), &game), [2, 3]);
+        assert_eq!(plies(&mut TrainingExporter::new(TrainingFilter::new().skip_check()), &game), [0, 1, 2]);
+        assert_eq!(plies(&mut TrainingExporter::new(TrainingFilter::new().elo_range(2000, 2100)), &game), [0, 1, 2, 3]);
+        assert!(plies(&mut TrainingExporter::new(TrainingFilter::new().elo_range(2050, 3000)), &game).is_empty());
+
+        let mut exporter = TrainingExporter::new(TrainingFilter::new().dedup());
+        assert_eq!(plies(&mut exporter, &game), [0, 1, 2, 3]);
+        assert!(plies(&mut exporter, &game).is_empty());
+
+        let sample = |seed| {
+            let mut exporter = TrainingExporter::new(TrainingFilter::new().sample(0.5, seed));
+            (0..50).flat_map(|_| plies(&mut exporter, &game)).collect::<Vec<u64>>()
+        };
+        assert_eq!(sample(7), sample(7));
+        assert_ne!(sample(7), sample(8));
+        assert!((60..140).contains(&sample(7).len()));
+        assert!(plies(&mut TrainingExporter::new(TrainingFilter::new().sample(0.0, 7)), &game).is_empty());
+    }
+
+    #[test]
+    fn writes_binary_records() {
+        let mut out = Vec::new();
+        let written = TrainingExporter::new(TrainingFilter::new()).write_binary(pgn::BufReader::new(PGN.as_bytes()), &mut out).unwrap();
+        assert_eq!((written, out.len()), (4, MAGIC.len() + 4 * RECORD_SIZE));
+        assert_eq!(&out[..MAGIC.len()], MAGIC);
+
+        let records = TrainingExporter::new(TrainingFilter::new()).records(&game(PGN));
+        for (record, bytes) in records.iter().zip(out[MAGIC.len()..].chunks(RECORD_SIZE)) {
+            assert_eq!(bytes, record.to_bytes());
+            let board = Board::from_packed(bytes[..PACKED_SIZE].try_into().unwrap()).unwrap();
+            assert_eq!(board.fen(), record.fen);
+        }
+        let third = &out[MAGIC.len() + 2 * RECORD_SIZE + PACKED_SIZE..][..RECORD_SIZE - PACKED_SIZE];
+        let m = 3 | 39 << 6;
+        assert_eq!(third, [m as u8, (m >> 8) as u8, 0xff, 2, 0, 0xd0, 0x07, 0x34, 0x08, 1, 150, 0]);
+
+        let mut text = Vec::new();
+        let written = TrainingExporter::new(TrainingFilter::new().skip_opening(3)).write_text(pgn::BufReader::new(PGN.as_bytes()), &mut text).unwrap();
+        assert_eq!((written, String::from_utf8(text).unwrap()), (1, format!("{}\n", records[3].to_text())));
+    }
+}